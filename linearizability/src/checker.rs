@@ -0,0 +1,261 @@
+use std::{
+    collections::{BTreeMap, HashSet},
+    error::Error,
+    fmt,
+};
+
+use crate::history::{Event, EventKind};
+use crate::model::Model;
+
+/// the return position of an operation whose outcome is unknown, it may take effect any time later
+const NEVER: usize = usize::MAX;
+
+/// An operation of a history, with the positions of its invocation and return in the history.
+#[derive(Debug, Clone)]
+struct Operation<I, O> {
+    process: usize,
+    call: usize,
+    ret: usize,
+    input: I,
+    /// `None` if the outcome is unknown
+    output: Option<O>,
+}
+
+type Operations<M> = Vec<Operation<<M as Model>::Input, <M as Model>::Output>>;
+
+/// A partition of a history that has no linearization.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Violation {
+    pub partition: String,
+    /// the operations of the partition in the order they are invoked, with what they returned
+    pub operations: Vec<String>,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "partition {:?} is not linearizable:", self.partition)?;
+        for op in self.operations.iter() {
+            writeln!(f, "  {}", op)?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for Violation {}
+
+/// Check whether `history` is linearizable with respect to `model`, i.e. every operation appears
+/// to take effect atomically at some point between its invocation and its return, in a sequence
+/// the model accepts.
+///
+/// Failed operations are left out, and operations of unknown outcome may take effect at any
+/// time after their invocation, or never. Every partition of the model is checked on its own with
+/// the search of Wing & Gong, pruned by caching the visited pairs of linearized operations and
+/// model state as Lowe does, which is what Knossos and Porcupine implement.
+pub fn check<M: Model>(model: &M, history: &[Event<M::Input, M::Output>]) -> Result<(), Violation> {
+    let mut ops: BTreeMap<usize, Operation<M::Input, M::Output>> = BTreeMap::new();
+    for (pos, event) in history.iter().enumerate() {
+        match &event.kind {
+            EventKind::Invoke(input) => {
+                let op = Operation {
+                    process: event.process,
+                    call: pos,
+                    ret: NEVER,
+                    input: input.clone(),
+                    output: None,
+                };
+                ops.insert(event.op, op);
+            }
+            EventKind::Ok(output) => {
+                if let Some(op) = ops.get_mut(&event.op) {
+                    op.ret = pos;
+                    op.output = Some(output.clone());
+                }
+            }
+            EventKind::Fail => {
+                ops.remove(&event.op);
+            }
+            EventKind::Info => {}
+        }
+    }
+
+    let mut partitions: BTreeMap<String, Operations<M>> = BTreeMap::new();
+    for op in ops.into_values() {
+        partitions.entry(model.partition(&op.input)).or_default().push(op);
+    }
+    for (partition, mut ops) in partitions {
+        ops.sort_by_key(|op| op.call);
+        let mut search = Search {
+            model,
+            ops: &ops,
+            linearized: vec![0; ops.len().div_ceil(64)],
+            visited: HashSet::new(),
+        };
+        let completed = ops.iter().filter(|op| op.ret != NEVER).count();
+        if !search.run(model.init(), completed) {
+            let operations = ops
+                .iter()
+                .map(|op| format!("process {}: {:?} -> {:?}", op.process, op.input, op.output))
+                .collect();
+            return Err(Violation { partition, operations });
+        }
+    }
+    Ok(())
+}
+
+struct Search<'a, M: Model> {
+    model: &'a M,
+    /// the operations of a partition, ordered by invocation
+    ops: &'a [Operation<M::Input, M::Output>],
+    /// a bitset of the operations linearized so far
+    linearized: Vec<u64>,
+    /// the pairs of linearized operations and model state already searched without success
+    visited: HashSet<(Vec<u64>, M::State)>,
+}
+
+impl<M: Model> Search<'_, M> {
+    fn is_linearized(&self, i: usize) -> bool {
+        self.linearized[i / 64] & (1 << (i % 64)) != 0
+    }
+
+    fn toggle(&mut self, i: usize) {
+        self.linearized[i / 64] ^= 1 << (i % 64);
+    }
+
+    /// Whether the remaining operations can be linearized from `state`, with `completed` of them
+    /// having returned. Operations of unknown outcome need not be linearized.
+    fn run(&mut self, state: M::State, completed: usize) -> bool {
+        if completed == 0 {
+            return true;
+        }
+        if !self.visited.insert((self.linearized.clone(), state.clone())) {
+            return false;
+        }
+        // an operation can take effect next only if it is invoked before every remaining one returns
+        let first_ret = (0..self.ops.len())
+            .filter(|i| !self.is_linearized(*i))
+            .map(|i| self.ops[i].ret)
+            .min()
+            .unwrap_or(NEVER);
+        for i in 0..self.ops.len() {
+            let op = &self.ops[i];
+            if op.call > first_ret {
+                break;
+            }
+            if self.is_linearized(i) {
+                continue;
+            }
+            let Some(next) = self.model.step(&state, &op.input, op.output.as_ref()) else {
+                continue;
+            };
+            let completed = completed - (op.ret != NEVER) as usize;
+            self.toggle(i);
+            if self.run(next, completed) {
+                return true;
+            }
+            self.toggle(i);
+        }
+        false
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::history::Recorder;
+    use crate::model::{KvInput, KvModel, KvOutput};
+
+    fn put(value: &str) -> KvInput {
+        KvInput::Put {
+            key: "k".to_string(),
+            value: value.to_string(),
+        }
+    }
+
+    fn get() -> KvInput {
+        KvInput::Get { key: "k".to_string() }
+    }
+
+    fn value(value: Option<&str>) -> KvOutput {
+        KvOutput::Value(value.map(|v| v.to_string()))
+    }
+
+    fn prev(value: Option<&str>) -> KvOutput {
+        KvOutput::Prev(value.map(|v| v.to_string()))
+    }
+
+    #[test]
+    fn test_concurrent_operations_may_take_effect_in_any_order() {
+        let history = Recorder::new();
+        let w1 = history.invoke(0, put("a"));
+        let w2 = history.invoke(1, put("b"));
+        let r = history.invoke(2, get());
+        history.ok(r, value(Some("a")));
+        history.ok(w2, prev(None));
+        history.ok(w1, prev(Some("b")));
+        assert_eq!(check(&KvModel, &history.history()), Ok(()));
+    }
+
+    #[test]
+    fn test_stale_read_is_not_linearizable() {
+        let history = Recorder::new();
+        let w = history.invoke(0, put("a"));
+        history.ok(w, prev(None));
+        let w = history.invoke(0, put("b"));
+        history.ok(w, prev(Some("a")));
+        // the read starts after "b" is written, it must not see "a"
+        let r = history.invoke(1, get());
+        history.ok(r, value(Some("a")));
+
+        let violation = check(&KvModel, &history.history()).unwrap_err();
+        assert_eq!(violation.partition, "k");
+        assert_eq!(violation.operations.len(), 3);
+    }
+
+    #[test]
+    fn test_unknown_outcomes() {
+        // a timed out write may take effect long after it is invoked
+        let history = Recorder::new();
+        let w = history.invoke(0, put("a"));
+        history.info(w);
+        let r = history.invoke(1, get());
+        history.ok(r, value(None));
+        let r = history.invoke(1, get());
+        history.ok(r, value(Some("a")));
+        assert_eq!(check(&KvModel, &history.history()), Ok(()));
+
+        // but a failed one never does
+        let history = Recorder::new();
+        let w = history.invoke(0, put("a"));
+        history.fail(w);
+        let r = history.invoke(1, get());
+        history.ok(r, value(Some("a")));
+        assert!(check(&KvModel, &history.history()).is_err());
+    }
+
+    #[test]
+    fn test_keys_are_checked_independently() {
+        let history = Recorder::new();
+        let w = history.invoke(0, put("a"));
+        let other = history.invoke(
+            1,
+            KvInput::Put {
+                key: "other".to_string(),
+                value: "x".to_string(),
+            },
+        );
+        history.ok(other, prev(None));
+        let r = history.invoke(1, get());
+        history.ok(r, value(None));
+        history.ok(w, prev(None));
+        let r = history.invoke(
+            1,
+            KvInput::Get {
+                key: "other".to_string(),
+            },
+        );
+        history.ok(r, value(None));
+
+        let violation = check(&KvModel, &history.history()).unwrap_err();
+        assert_eq!(violation.partition, "other");
+    }
+}
@@ -0,0 +1,100 @@
+use std::sync::{Arc, Mutex};
+
+/// What happened to an operation, in the terms of Jepsen.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EventKind<I, O> {
+    /// the client sent the operation
+    Invoke(I),
+    /// the operation took effect and returned the output
+    Ok(O),
+    /// the operation certainly did not take effect, e.g. a write rejected by a follower
+    Fail,
+    /// the outcome is unknown, e.g. a write timed out, so it may take effect at any time after the invocation
+    Info,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Event<I, O> {
+    /// the operation the event belongs to
+    pub op: usize,
+    /// the client running the operation, a client runs one operation at a time
+    pub process: usize,
+    pub kind: EventKind<I, O>,
+}
+
+/// Records the events of the operations run by concurrent clients in the real time order they happen.
+///
+/// Every operation must be completed with one of `ok`, `fail` or `info`, an operation left
+/// invoked is taken as `info`.
+#[derive(Debug)]
+pub struct Recorder<I, O> {
+    inner: Arc<Mutex<Recorded<I, O>>>,
+}
+
+#[derive(Debug)]
+struct Recorded<I, O> {
+    events: Vec<Event<I, O>>,
+    /// the process of every operation, indexed by the operation id
+    processes: Vec<usize>,
+}
+
+impl<I, O> Clone for Recorder<I, O> {
+    fn clone(&self) -> Self {
+        Recorder {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<I, O> Default for Recorder<I, O> {
+    fn default() -> Self {
+        Recorder {
+            inner: Arc::new(Mutex::new(Recorded {
+                events: Vec::new(),
+                processes: Vec::new(),
+            })),
+        }
+    }
+}
+
+impl<I: Clone, O: Clone> Recorder<I, O> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record that `process` sends `input`, and return the id of the operation.
+    pub fn invoke(&self, process: usize, input: I) -> usize {
+        let mut recorded = self.inner.lock().unwrap();
+        let op = recorded.processes.len();
+        recorded.processes.push(process);
+        recorded.events.push(Event {
+            op,
+            process,
+            kind: EventKind::Invoke(input),
+        });
+        op
+    }
+
+    pub fn ok(&self, op: usize, output: O) {
+        self.complete(op, EventKind::Ok(output));
+    }
+
+    pub fn fail(&self, op: usize) {
+        self.complete(op, EventKind::Fail);
+    }
+
+    pub fn info(&self, op: usize) {
+        self.complete(op, EventKind::Info);
+    }
+
+    fn complete(&self, op: usize, kind: EventKind<I, O>) {
+        let mut recorded = self.inner.lock().unwrap();
+        let process = recorded.processes[op];
+        recorded.events.push(Event { op, process, kind });
+    }
+
+    /// The events recorded so far.
+    pub fn history(&self) -> Vec<Event<I, O>> {
+        self.inner.lock().unwrap().events.clone()
+    }
+}
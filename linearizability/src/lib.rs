@@ -0,0 +1,7 @@
+pub mod checker;
+pub mod history;
+pub mod model;
+
+pub use checker::{check, Violation};
+pub use history::{Event, EventKind, Recorder};
+pub use model::{KvInput, KvModel, KvOutput, Model};
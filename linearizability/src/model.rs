@@ -0,0 +1,137 @@
+use std::{fmt::Debug, hash::Hash};
+
+/// The sequential specification a history is checked against.
+pub trait Model {
+    type State: Clone + Eq + Hash + Debug;
+    type Input: Clone + Debug;
+    type Output: Clone + Debug;
+
+    /// The state before any operation.
+    fn init(&self) -> Self::State;
+
+    /// The state after `input` is applied to `state`, or `None` if the operation cannot return
+    /// `output` in `state`. `output` is `None` for an operation whose outcome is unknown.
+    fn step(&self, state: &Self::State, input: &Self::Input, output: Option<&Self::Output>) -> Option<Self::State>;
+
+    /// The partition of the operation. Operations of different partitions never affect each other,
+    /// so every partition is checked on its own, which keeps the search small.
+    fn partition(&self, _input: &Self::Input) -> String {
+        String::new()
+    }
+}
+
+/// An operation on a key-value store.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KvInput {
+    Get {
+        key: String,
+    },
+    Put {
+        key: String,
+        value: String,
+    },
+    Delete {
+        key: String,
+    },
+    /// set `key` to `value` only if its current value equals `expected`, `None` means absent
+    CompareAndSwap {
+        key: String,
+        expected: Option<String>,
+        value: String,
+    },
+}
+
+impl KvInput {
+    pub fn key(&self) -> &str {
+        match self {
+            KvInput::Get { key }
+            | KvInput::Put { key, .. }
+            | KvInput::Delete { key }
+            | KvInput::CompareAndSwap { key, .. } => key,
+        }
+    }
+}
+
+/// The output of a `KvInput`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KvOutput {
+    /// the value read by a get
+    Value(Option<String>),
+    /// the value before a put or a delete
+    Prev(Option<String>),
+    /// whether a compare-and-swap matched, and the value before it
+    Swapped { ok: bool, prev: Option<String> },
+}
+
+/// A key-value store where every key is an independent register, so the history is checked key by key.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct KvModel;
+
+impl Model for KvModel {
+    /// the value of the key of the partition
+    type State = Option<String>;
+    type Input = KvInput;
+    type Output = KvOutput;
+
+    fn init(&self) -> Self::State {
+        None
+    }
+
+    fn step(&self, state: &Self::State, input: &Self::Input, output: Option<&Self::Output>) -> Option<Self::State> {
+        let (next, expected) = match input {
+            KvInput::Get { .. } => (state.clone(), KvOutput::Value(state.clone())),
+            KvInput::Put { value, .. } => (Some(value.clone()), KvOutput::Prev(state.clone())),
+            KvInput::Delete { .. } => (None, KvOutput::Prev(state.clone())),
+            KvInput::CompareAndSwap { expected, value, .. } => {
+                let ok = state == expected;
+                let next = if ok { Some(value.clone()) } else { state.clone() };
+                (
+                    next,
+                    KvOutput::Swapped {
+                        ok,
+                        prev: state.clone(),
+                    },
+                )
+            }
+        };
+        match output {
+            Some(output) if *output != expected => None,
+            _ => Some(next),
+        }
+    }
+
+    fn partition(&self, input: &Self::Input) -> String {
+        input.key().to_string()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_kv_model_step() {
+        let model = KvModel;
+        let put = KvInput::Put {
+            key: "k".to_string(),
+            value: "v".to_string(),
+        };
+        let state = model.step(&model.init(), &put, Some(&KvOutput::Prev(None))).unwrap();
+        assert_eq!(state, Some("v".to_string()));
+        assert_eq!(model.step(&state, &put, Some(&KvOutput::Prev(None))), None);
+
+        let cas = KvInput::CompareAndSwap {
+            key: "k".to_string(),
+            expected: None,
+            value: "w".to_string(),
+        };
+        let failed = KvOutput::Swapped {
+            ok: false,
+            prev: Some("v".to_string()),
+        };
+        assert_eq!(model.step(&state, &cas, Some(&failed)), Some(state.clone()));
+        // an operation of unknown outcome is applied whatever it returned
+        assert_eq!(model.step(&model.init(), &cas, None), Some(Some("w".to_string())));
+        assert_eq!(model.partition(&cas), "k");
+    }
+}
@@ -114,3 +114,84 @@ async fn test_proposer_run_paxos() {
         assert_eq!(res, val);
     }
 }
+
+// run reads and writes on random versions of key "lin" with proposer `id`, each phase only reaches
+// a random subset of the acceptors as if the rpcs to the others were lost
+#[cfg(test)]
+async fn run_lossy_proposer(
+    id: i64,
+    acceptor_ids: Vec<i64>,
+    history: linearizability::Recorder<linearizability::KvInput, linearizability::KvOutput>,
+) {
+    use linearizability::{KvInput, KvOutput};
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    let quorum = acceptor_ids.len() / 2 + 1;
+    let mut rng = StdRng::seed_from_u64(id as u64);
+    for n in 1..=30 {
+        let ver = rng.gen_range(0..3);
+        let key = format!("lin@{}", ver);
+        let value = id * 1000 + n;
+        // a write sets the version only if no value is chosen yet, and returns the chosen one
+        let write = rng.gen_bool(0.5);
+        let input = match write {
+            true => KvInput::CompareAndSwap { key, expected: None, value: value.to_string() },
+            false => KvInput::Get { key },
+        };
+        let op = history.invoke(id as usize, input);
+
+        let mut px = Proposer {
+            id: Some(PaxosInstanceId { key: "lin".to_string(), ver }),
+            bal: Some(BallotNum { n, proposer_id: id }),
+            val: None,
+        };
+        let reachable: Vec<i64> = acceptor_ids.iter().copied().filter(|_| rng.gen_bool(0.7)).collect();
+        let chosen = match px.phase1(reachable, quorum).await {
+            Ok(chosen) => chosen,
+            // prepare never changes a value
+            Err(_) => {
+                history.fail(op);
+                continue;
+            }
+        };
+        px.val = match (&chosen, write) {
+            (Some(_), _) => chosen.clone(),
+            (None, true) => Some(Value { vi64: value }),
+            (None, false) => {
+                history.ok(op, KvOutput::Value(None));
+                continue;
+            }
+        };
+        let reachable: Vec<i64> = acceptor_ids.iter().copied().filter(|_| rng.gen_bool(0.7)).collect();
+        let accepted = px.phase2(reachable, quorum).await.is_ok();
+        let chosen = chosen.map(|val| val.vi64.to_string());
+        match (accepted, chosen) {
+            (true, Some(prev)) if write => history.ok(op, KvOutput::Swapped { ok: false, prev: Some(prev) }),
+            (true, Some(prev)) => history.ok(op, KvOutput::Value(Some(prev))),
+            (true, None) => history.ok(op, KvOutput::Swapped { ok: true, prev: None }),
+            // some acceptors may have accepted our value, it may be chosen later
+            (false, None) => history.info(op),
+            // only the value found in phase 1 is sent, which belongs to another write
+            (false, Some(_)) => history.fail(op),
+        }
+        tokio::time::sleep(tokio::time::Duration::from_millis(rng.gen_range(0..10))).await;
+    }
+}
+
+// test linearizability of concurrent proposers with lost rpcs
+#[tokio::test]
+async fn test_linearizable_under_faults() {
+    let acceptor_ids = vec![11, 12, 13];
+    serve_acceptors(&acceptor_ids).await.unwrap();
+    tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
+
+    let history = linearizability::Recorder::new();
+    tokio::join!(
+        run_lossy_proposer(1, acceptor_ids.clone(), history.clone()),
+        run_lossy_proposer(2, acceptor_ids.clone(), history.clone()),
+        run_lossy_proposer(3, acceptor_ids.clone(), history.clone()),
+    );
+    if let Err(violation) = linearizability::check(&linearizability::KvModel, &history.history()) {
+        panic!("{}", violation);
+    }
+}
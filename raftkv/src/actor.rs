@@ -0,0 +1,1642 @@
+use core::time;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    future::Future,
+    io,
+    pin::Pin,
+    result::Result,
+    sync::Arc,
+};
+
+use prost::Message;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use tokio::{
+    sync::{mpsc, oneshot, watch},
+    time::MissedTickBehavior,
+};
+use tracing::{debug, debug_span, info, info_span, warn, Instrument, Span};
+
+use crate::clock::{Clock, SystemClock};
+use crate::membership::{ChangeError, Membership};
+use crate::node::*;
+use crate::raft::*;
+use crate::state_machine::StateMachine;
+use crate::telemetry::Telemetry;
+use crate::trace::TraceContext;
+use crate::transport::{GrpcTransport, RpcError, Transport};
+
+/// proposals waiting for the output of the state machine, keyed by log index
+type Pending = BTreeMap<u64, (LogId, oneshot::Sender<String>)>;
+/// a membership change, from the membership in effect to the next one
+pub(crate) type Change = Box<dyn FnOnce(&Membership) -> Result<Membership, ChangeError> + Send>;
+/// the reply of a proposal, the log id of the new entry and a receiver of the state machine output
+pub(crate) type Proposed = (LogId, oneshot::Receiver<String>);
+type Rpc = Pin<Box<dyn Future<Output = Event> + Send>>;
+/// an event with the span it is handled in, i.e. the span it is sent from
+pub(crate) type Envelope = (Event, Span);
+
+/// Everything the core of a raft instance reacts to. Requests carry a oneshot sender the core
+/// replies to, and the rpcs the core sends to its peers come back as the `*Resp` events.
+pub(crate) enum Event {
+    Elect {
+        req: ElectRequest,
+        tx: oneshot::Sender<ElectResponse>,
+    },
+    PreVote {
+        req: ElectRequest,
+        tx: oneshot::Sender<ElectResponse>,
+    },
+    AppendLog {
+        req: AppendLogRequest,
+        tx: oneshot::Sender<AppendLogResponse>,
+    },
+    InstallSnapshot {
+        leader_id: u32,
+        term: u64,
+        snapshot: Snapshot,
+        tx: oneshot::Sender<InstallSnapshotResponse>,
+    },
+    TimeoutNow {
+        req: TimeoutNowRequest,
+        tx: oneshot::Sender<TimeoutNowResponse>,
+    },
+    Propose {
+        entry_type: EntryType,
+        data: Vec<u8>,
+        tx: oneshot::Sender<Option<Proposed>>,
+    },
+    ChangeMembership {
+        change: Change,
+        tx: oneshot::Sender<Result<Proposed, ChangeError>>,
+    },
+    ReadIndex {
+        tx: oneshot::Sender<Option<u64>>,
+    },
+    LeaseReadIndex {
+        tx: oneshot::Sender<Option<u64>>,
+    },
+    TransferLeader {
+        target: u64,
+        tx: oneshot::Sender<bool>,
+    },
+    Query {
+        query: String,
+        tx: oneshot::Sender<String>,
+    },
+    Status {
+        tx: oneshot::Sender<ClusterStatusResponse>,
+    },
+    /// stop the core once the queued events are handled, `tx` is answered with the result of
+    /// flushing the store
+    Shutdown {
+        tx: oneshot::Sender<io::Result<()>>,
+    },
+    VoteResp {
+        round: u64,
+        from: u64,
+        resp: Result<ElectResponse, RpcError>,
+    },
+    AppendLogResp {
+        id: u64,
+        term: u64,
+        seq: u64,
+        sent_last: LogId,
+        sent_at: u128,
+        resp: Result<AppendLogResponse, RpcError>,
+    },
+    ReadAck {
+        seq: u64,
+        from: u64,
+        resp: Result<AppendLogResponse, RpcError>,
+    },
+    TimeoutNowResp {
+        target: u64,
+        resp: Result<TimeoutNowResponse, RpcError>,
+    },
+}
+
+/// A round of votes or pre-votes this instance asked for.
+struct Election {
+    round: u64,
+    pre_vote: bool,
+    term: u64,
+    membership: Membership,
+    granted: BTreeSet<u64>,
+}
+
+/// A read index waiting for a heartbeat round to confirm the leadership.
+struct PendingRead {
+    index: u64,
+    granted: BTreeSet<u64>,
+    /// when the read gives up, in milliseconds
+    deadline: u128,
+    tx: oneshot::Sender<Option<u64>>,
+}
+
+/// A leadership transfer in progress, no proposal is accepted meanwhile.
+struct Transfer {
+    target: u64,
+    /// when the transfer is aborted, in milliseconds
+    deadline: u128,
+    timeout_now_sent: bool,
+    tx: oneshot::Sender<bool>,
+}
+
+/// The state of a raft instance. It is owned by a single task which handles the events one at a
+/// time, so nothing here is shared or locked. The rpcs to the peers run on their own tasks and post
+/// their responses back as events.
+pub(crate) struct Core {
+    /// raft instance id
+    pub(crate) id: u32,
+    /// the raft group this instance belongs to, carried by every rpc it sends
+    pub(crate) group: u64,
+    /// the role this instance currently plays
+    pub(crate) role: Role,
+    /// the leader of the current term as far as this instance knows
+    pub(crate) leader_id: Option<u32>,
+    /// raft peers process info, for leader
+    pub(crate) leading: Option<Leading>,
+    /// raft instance committed log index
+    pub(crate) commit: u64,
+    /// the addresses of the peers keyed by raft instance id, including the ones only known
+    /// from membership changes
+    pub(crate) peers: BTreeMap<u64, String>,
+    /// raft instance backend storage impl
+    pub(crate) sto: Store,
+    /// last time heart beat timestamp
+    last_hb: u128,
+    /// the randomized election timeout of the current round, in milliseconds
+    election_timeout: u64,
+    /// the range the election timeout is picked from and the heartbeat interval
+    pub(crate) timeouts: Timeouts,
+    /// the state machine committed logs are applied to
+    pub(crate) sm: Box<dyn StateMachine>,
+    /// proposals waiting for the apply result
+    pending: Pending,
+    /// proposals received since the last batch was appended to the log
+    proposals: Vec<(EntryType, Vec<u8>, oneshot::Sender<Option<Proposed>>)>,
+    /// the votes of the latest election, `None` once it is decided
+    election: Option<Election>,
+    /// the number of elections started, responses of an earlier one are ignored
+    round: u64,
+    /// read indexes waiting for their heartbeat round, keyed by sequence number
+    reads: BTreeMap<u64, PendingRead>,
+    next_read: u64,
+    transfer: Option<Transfer>,
+    /// the reply of a shutdown request, the core stops once it is set
+    shutdown: Option<oneshot::Sender<io::Result<()>>>,
+    /// when the leader sent the latest heartbeats, in milliseconds
+    last_heartbeat: u128,
+    /// when to take a snapshot and compact the log
+    pub(crate) snapshot_policy: SnapshotPolicy,
+    /// how proposals are batched and the log is replicated
+    pub(crate) replication: ReplicationPolicy,
+    /// whether the leader steps down once it has not heard from a majority within an election timeout
+    pub(crate) check_quorum: bool,
+    /// how rpcs reach the peers
+    pub(crate) transport: Arc<dyn Transport>,
+    /// where the time is read from
+    pub(crate) clock: Arc<dyn Clock>,
+    /// the source of the randomized election timeouts
+    pub(crate) rng: StdRng,
+    /// where the rpcs post their responses, it does not keep the core alive
+    tx: mpsc::WeakUnboundedSender<Envelope>,
+    /// the metrics published after every event
+    metrics: watch::Sender<RaftMetrics>,
+    /// the Prometheus metrics, shared with the handles
+    pub(crate) telemetry: Telemetry,
+}
+
+impl Core {
+    pub(crate) fn new(
+        id: u32,
+        peers: BTreeMap<u64, String>,
+        sto: Store,
+        mut sm: Box<dyn StateMachine>,
+        tx: mpsc::WeakUnboundedSender<Envelope>,
+    ) -> Self {
+        let clock = Arc::new(SystemClock);
+        let mut rng = StdRng::from_entropy();
+        // the state machine starts from the snapshot, which only covers committed entries
+        let snapshot_last = sto.snapshot_last();
+        if snapshot_last.index > 0 {
+            sm.restore(snapshot_last.clone(), &sto.snapshot.data);
+        }
+
+        let core = Core {
+            id,
+            group: 0,
+            role: Role::Follower,
+            leader_id: None,
+            leading: None,
+            commit: snapshot_last.index,
+            peers,
+            sto,
+            last_hb: clock.now(),
+            election_timeout: rng.gen_range(ELECTION_TIMEOUT.0..ELECTION_TIMEOUT.1),
+            timeouts: Timeouts::default(),
+            sm,
+            pending: BTreeMap::new(),
+            proposals: Vec::new(),
+            election: None,
+            round: 0,
+            reads: BTreeMap::new(),
+            next_read: 0,
+            transfer: None,
+            shutdown: None,
+            last_heartbeat: 0,
+            snapshot_policy: SnapshotPolicy::default(),
+            replication: ReplicationPolicy::default(),
+            check_quorum: true,
+            transport: Arc::new(GrpcTransport::default()),
+            clock,
+            rng,
+            tx,
+            metrics: watch::Sender::new(RaftMetrics::default()),
+            telemetry: Telemetry::new(id, 0),
+        };
+        let metrics = core.current_metrics();
+        core.telemetry.observe(&metrics, &metrics);
+        core.metrics.send_replace(metrics);
+        core
+    }
+
+    /// Join `group`, reporting to `telemetry` labelled with it from now on.
+    pub(crate) fn set_group(&mut self, group: u64, telemetry: Telemetry) {
+        self.group = group;
+        self.telemetry = telemetry;
+        let metrics = self.metrics.borrow().clone();
+        self.telemetry.observe(&metrics, &metrics);
+    }
+
+    /// Make the voters `ids` of the initial membership witnesses, the memberships changed since are
+    /// left as they are.
+    pub(crate) fn set_witnesses(&mut self, ids: &[u64]) {
+        if let Some(initial) = self.sto.configs.get_mut(&0) {
+            let voters = initial.voters();
+            initial.witnesses = ids.iter().filter(|id| voters.contains(id)).copied().collect();
+            self.metrics.send_replace(self.current_metrics());
+        }
+    }
+
+    pub(crate) fn subscribe(&self) -> watch::Receiver<RaftMetrics> {
+        self.metrics.subscribe()
+    }
+
+    /// Handle the events and ticks until every handle of this instance is dropped or it is shut down.
+    pub(crate) async fn run(mut self, mut rx: mpsc::UnboundedReceiver<Envelope>) {
+        let mut ticker = tokio::time::interval(time::Duration::from_millis(TICK_INTERVAL));
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        loop {
+            tokio::select! {
+                biased;
+                _ = ticker.tick() => self.tick(),
+                event = rx.recv() => match event {
+                    Some((event, span)) => {
+                        span.in_scope(|| self.handle(event));
+                        // take whatever else is queued, so that concurrent proposals go out together
+                        for _ in 1..self.replication.max_batch {
+                            let Ok((event, span)) = rx.try_recv() else {
+                                break;
+                            };
+                            span.in_scope(|| self.handle(event));
+                        }
+                        self.flush_proposals();
+                        if let Some(tx) = self.shutdown.take() {
+                            let _ = tx.send(self.sto.flush());
+                            return;
+                        }
+                    }
+                    None => return,
+                },
+            }
+            self.notify();
+        }
+    }
+
+    fn handle(&mut self, event: Event) {
+        self.telemetry.events.inc();
+        match event {
+            Event::Elect { req, tx } => {
+                let resp = self.handle_elect(req);
+                self.telemetry
+                    .votes
+                    .with_label_values(&[&resp.granted.to_string()])
+                    .inc();
+                let _ = tx.send(resp);
+            }
+            Event::PreVote { req, tx } => {
+                let _ = tx.send(self.handle_pre_vote(req));
+            }
+            Event::AppendLog { req, tx } => {
+                let resp = self.handle_append_log(req);
+                let success = resp.success.to_string();
+                self.telemetry.append_log_received.with_label_values(&[&success]).inc();
+                self.apply_committed();
+                let _ = tx.send(resp);
+            }
+            Event::InstallSnapshot {
+                leader_id,
+                term,
+                snapshot,
+                tx,
+            } => {
+                let _ = tx.send(self.handle_install_snapshot(leader_id, term, snapshot));
+            }
+            Event::TimeoutNow { req, tx } => {
+                let _ = tx.send(self.handle_timeout_now(req));
+            }
+            Event::Propose { entry_type, data, tx } => self.proposals.push((entry_type, data, tx)),
+            Event::ChangeMembership { change, tx } => {
+                let _ = tx.send(self.propose_membership(change));
+            }
+            Event::ReadIndex { tx } => self.read_index(tx),
+            Event::LeaseReadIndex { tx } => {
+                let _ = tx.send(self.lease_read_index());
+            }
+            Event::TransferLeader { target, tx } => self.transfer_leader(target, tx),
+            Event::Query { query, tx } => {
+                let _ = tx.send(self.sm.query(&query));
+            }
+            Event::Status { tx } => {
+                let _ = tx.send(self.status());
+            }
+            Event::Shutdown { tx } => self.shutdown = Some(tx),
+            Event::VoteResp { round, from, resp } => self.handle_vote_resp(round, from, resp),
+            Event::AppendLogResp {
+                id,
+                term,
+                seq,
+                sent_last,
+                sent_at,
+                resp,
+            } => self.handle_append_log_resp(id, term, seq, sent_last, sent_at, resp),
+            Event::ReadAck { seq, from, resp } => self.handle_read_ack(seq, from, resp),
+            Event::TimeoutNowResp { target, resp } => {
+                if let Err(e) = resp {
+                    self.rpc_failed("send timeout now to", target, &e);
+                    if let Some(transfer) = self.transfer.as_mut().filter(|t| t.target == target) {
+                        transfer.timeout_now_sent = false;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Run `rpc` on its own task in `span`, and handle the event it resolves to in the same span.
+    /// The rpc carries a child of `ctx` to the peer.
+    fn spawn(&self, ctx: TraceContext, span: Span, rpc: impl Future<Output = Event> + Send + 'static) {
+        let tx = self.tx.clone();
+        tokio::spawn(async move {
+            let event = ctx.scope(rpc).instrument(span.clone()).await;
+            if let Some(tx) = tx.upgrade() {
+                let _ = tx.send((event, span));
+            }
+        });
+    }
+
+    pub(crate) fn tick(&mut self) {
+        let now = self.clock.now();
+        for seq in self
+            .reads
+            .iter()
+            .filter(|(_, read)| now >= read.deadline)
+            .map(|(seq, _)| *seq)
+            .collect::<Vec<_>>()
+        {
+            self.reads.remove(&seq);
+        }
+        match self.role {
+            Role::Leader => {
+                let heartbeat = now - self.last_heartbeat >= self.timeouts.heartbeat as u128;
+                if heartbeat {
+                    self.last_heartbeat = now;
+                }
+                if self.check_quorum && self.quorum_lost() {
+                    info!("raft {} has not heard from a majority, steps down", self.id);
+                    self.step_down();
+                } else {
+                    self.replicate(heartbeat);
+                }
+            }
+            Role::Follower | Role::PreCandidate | Role::Candidate => {
+                // learners, witnesses and removed members never campaign
+                if self.election_timeout_elapsed() && self.can_lead() {
+                    self.pre_campaign();
+                }
+            }
+        }
+    }
+
+    /// Answer whoever waits on the state changed by the last event, and publish the metrics.
+    fn notify(&mut self) {
+        self.resolve_reads();
+        self.advance_transfer();
+        let metrics = self.current_metrics();
+        self.metrics.send_if_modified(|current| {
+            if *current == metrics {
+                return false;
+            }
+            self.telemetry.observe(current, &metrics);
+            *current = metrics;
+            true
+        });
+    }
+
+    fn current_metrics(&self) -> RaftMetrics {
+        let (membership_log_index, membership) = self.sto.membership();
+        let matched = self
+            .leading
+            .iter()
+            .flat_map(|leading| leading.progresses.iter())
+            .map(|(id, progress)| (*id, progress.acked.clone()))
+            .collect();
+        RaftMetrics {
+            id: self.id,
+            role: self.role,
+            term: self.sto.term,
+            voted_for: self.sto.voted_for,
+            leader_id: self.leader_id,
+            last_log_id: self.sto.get_last(),
+            snapshot_last: self.sto.snapshot_last(),
+            commit: self.commit,
+            applied: self.sm.last_applied(),
+            membership,
+            membership_log_index,
+            peers: self.peers.clone(),
+            matched,
+        }
+    }
+
+    /// The status of this instance, with the replication progress of every follower on the leader.
+    fn status(&self) -> ClusterStatusResponse {
+        let last = self.sto.get_last();
+        let now = self.clock.now();
+        let (configs, learners) = self.sto.membership().1.encode();
+        let peers = self
+            .leading
+            .iter()
+            .flat_map(|leading| leading.progresses.iter())
+            .map(|(id, progress)| PeerStatus {
+                id: *id as u32,
+                addr: self.peers.get(id).cloned().unwrap_or_default(),
+                matched: Some(progress.acked.clone()),
+                lag: last.index.saturating_sub(progress.acked.index),
+                inflight: progress.inflight.len() as u32,
+                last_ack_ms: now.saturating_sub(progress.acked_at) as u64,
+            })
+            .collect();
+        let connections = self
+            .peers
+            .iter()
+            .filter(|(id, _)| **id != self.id as u64)
+            .filter_map(|(id, addr)| {
+                let health = self.transport.peer_health(addr)?;
+                Some(PeerHealth {
+                    id: *id as u32,
+                    ..health
+                })
+            })
+            .collect();
+        ClusterStatusResponse {
+            id: self.id,
+            role: format!("{:?}", self.role),
+            term: self.sto.term,
+            voted_for: self.sto.voted_for,
+            leader_id: self.leader_id,
+            last_log_id: Some(last),
+            commit: self.commit,
+            applied: self.sm.last_applied().index,
+            configs,
+            learners,
+            peers,
+            group: self.group,
+            granted_by: self
+                .leading
+                .iter()
+                .flat_map(|leading| leading.granted_by.iter().map(|id| *id as u32))
+                .collect(),
+            connections,
+        }
+    }
+
+    /// Append the proposals received since the last batch to the log with a single write, and
+    /// replicate them together. They are rejected if this instance is not the leader or a leadership
+    /// transfer is in progress.
+    fn flush_proposals(&mut self) {
+        if self.proposals.is_empty() {
+            return;
+        }
+        let proposals = std::mem::take(&mut self.proposals);
+        let Some(leading) = self.leading.as_mut().filter(|_| self.transfer.is_none()) else {
+            for (_, _, tx) in proposals {
+                let _ = tx.send(None);
+            }
+            return;
+        };
+        let term = self.sto.term;
+        let mut index = self.sto.get_last().index;
+        let mut logs = Vec::with_capacity(proposals.len());
+        let mut replies = Vec::with_capacity(proposals.len());
+        for (entry_type, data, tx) in proposals {
+            index += 1;
+            let log_id = LogId { term, index };
+            let (applied, rx) = oneshot::channel();
+            self.pending.insert(index, (log_id.clone(), applied));
+            replies.push((tx, (log_id.clone(), rx)));
+            let mut log = Log {
+                id: Some(log_id),
+                data,
+                ..Default::default()
+            };
+            log.set_entry_type(entry_type);
+            logs.push(log);
+        }
+        self.sto.append_batch(logs);
+        leading.log_index_range.1 = index + 1;
+        for (tx, proposed) in replies {
+            let _ = tx.send(Some(proposed));
+        }
+        // a single node cluster commits right away
+        self.advance_commit();
+        self.replicate(false);
+    }
+
+    /// Propose a membership change if this instance is the leader and no other change is in progress.
+    /// A change of the voters appends the joint membership first, the leader moves on to the new
+    /// membership by itself once the joint one is committed.
+    fn propose_membership(&mut self, change: Change) -> Result<Proposed, ChangeError> {
+        if self.leading.is_none() {
+            return Err(ChangeError::NotLeader);
+        }
+        if self.transfer.is_some() {
+            return Err(ChangeError::Transferring);
+        }
+        let (index, current) = self.sto.membership();
+        if current.is_joint() || index > self.commit {
+            return Err(ChangeError::InProgress);
+        }
+        let mut next = change(&current)?;
+        if next.voters() != current.voters() {
+            next = current.joint(&next);
+        }
+        let (tx, rx) = oneshot::channel();
+        let log_id = self.append_entry(EntryType::Config, Some(next)).unwrap();
+        self.pending.insert(log_id.index, (log_id.clone(), tx));
+        self.advance_commit();
+        self.replicate(false);
+        Ok((log_id, rx))
+    }
+
+    /// Append a new entry without a command of the leader's term, a membership change takes effect
+    /// right away. It is `None` if this instance is not the leader.
+    fn append_entry(&mut self, entry_type: EntryType, membership: Option<Membership>) -> Option<LogId> {
+        let leading = self.leading.as_mut()?;
+        let last = self.sto.get_last();
+        let log_id = LogId {
+            term: self.sto.term,
+            index: last.index + 1,
+        };
+        let (configs, learners) = membership.as_ref().map(Membership::encode).unwrap_or_default();
+        let mut log = Log {
+            id: Some(log_id.clone()),
+            configs,
+            learners,
+            ..Default::default()
+        };
+        log.set_entry_type(entry_type);
+        self.sto.append_log(log);
+        leading.log_index_range.1 = log_id.index + 1;
+
+        if let Some(membership) = membership {
+            // replicate to new members from the leader's log end, and stop replicating to removed ones
+            let now = self.clock.now();
+            let members = membership.members();
+            leading.progresses.retain(|id, _| members.contains(id));
+            for id in members.into_iter().filter(|id| *id != self.id as u64) {
+                leading
+                    .progresses
+                    .entry(id)
+                    .or_insert_with(|| Progress::new(LogId::default(), last.index, now));
+            }
+            self.update_peers(&membership);
+        }
+        Some(log_id)
+    }
+
+    /// Log an rpc that failed to reach `peer`. Once the transport has seen the rpcs to the peer fail, it
+    /// has warned about the peer already, and the failures until it is reached again are only logged for
+    /// debugging.
+    fn rpc_failed(&self, what: &str, peer: u64, e: &RpcError) {
+        let health = self.peers.get(&peer).and_then(|addr| self.transport.peer_health(addr));
+        if health.is_some_and(|health| health.failures > 0) {
+            debug!("raft {} failed to {} {}: {}", self.id, what, peer, e);
+        } else {
+            warn!("raft {} failed to {} {}: {}", self.id, what, peer, e);
+        }
+    }
+
+    /// Learn the addresses of the members of `membership`.
+    fn update_peers(&mut self, membership: &Membership) {
+        self.peers.extend(membership.nodes.clone());
+    }
+
+    /// Whether this instance is a voter of the membership in effect that may become the leader.
+    fn can_lead(&self) -> bool {
+        self.sto.membership().1.can_lead(self.id as u64)
+    }
+
+    /// Start a read index, it is answered once a heartbeat round to a majority confirms the
+    /// leadership, or with `None` if this instance is not the leader or fails to confirm it in time.
+    /// A learner asks the leader for its read index instead, so that it serves linearizable reads
+    /// once it has applied the log up to there.
+    fn read_index(&mut self, tx: oneshot::Sender<Option<u64>>) {
+        let Some(leading) = self.leading.as_ref() else {
+            self.forward_read_index(tx);
+            return;
+        };
+        // the commit index of a new leader is not up to date until it commits its no-op entry,
+        // which the read waits for
+        let index = self.commit.max(leading.log_index_range.0);
+
+        let seq = self.next_read;
+        self.next_read += 1;
+        let membership = self.sto.membership().1;
+        let mut granted = BTreeSet::new();
+        if membership.voters().contains(&(self.id as u64)) {
+            granted.insert(self.id as u64);
+        }
+        let leading = self.leading.as_ref().unwrap();
+        let ctx = TraceContext::root();
+        let round = debug_span!(
+            parent: None,
+            "read_index",
+            node = self.id,
+            group = self.group,
+            term = self.sto.term,
+            index,
+            seq,
+            trace_id = %ctx.trace_id(),
+        );
+        for id in membership.voters() {
+            let (Some(progress), Some(addr)) = (leading.progresses.get(&id), self.peers.get(&id)) else {
+                continue;
+            };
+            // the acked log id always matches, so the heartbeat cannot be rejected
+            let request = AppendLogRequest {
+                id: self.id,
+                term: self.sto.term,
+                last_log_id: Some(self.sto.get_last()),
+                prev_log_id: Some(progress.acked.clone()),
+                log: vec![],
+                leader_commit: self.commit,
+                group: self.group,
+            };
+            let (transport, addr) = (self.transport.clone(), addr.clone());
+            let span = debug_span!(parent: &round, "heartbeat", peer = id);
+            self.spawn(ctx, span, async move {
+                let resp = transport.append_log(addr, request).await;
+                Event::ReadAck { seq, from: id, resp }
+            });
+        }
+        let read = PendingRead {
+            index,
+            granted,
+            deadline: self.clock.now() + self.timeouts.election.0 as u128,
+            tx,
+        };
+        self.reads.insert(seq, read);
+    }
+
+    /// Ask the known leader for a read index on behalf of a learner.
+    fn forward_read_index(&self, tx: oneshot::Sender<Option<u64>>) {
+        let learner = self.sto.membership().1.learners.contains(&(self.id as u64));
+        let leader = self.leader_id.and_then(|id| self.peers.get(&(id as u64)));
+        let Some(addr) = leader.filter(|_| learner).cloned() else {
+            let _ = tx.send(None);
+            return;
+        };
+        let request = ReadIndexRequest { group: self.group };
+        let transport = self.transport.clone();
+        let span = debug_span!("forward_read_index", node = self.id, group = self.group, leader = %addr);
+        tokio::spawn(
+            async move {
+                let resp = transport.read_index(addr, request).await;
+                let _ = tx.send(resp.ok().map(|resp| resp.index));
+            }
+            .instrument(span),
+        );
+    }
+
+    fn handle_read_ack(&mut self, seq: u64, from: u64, resp: Result<AppendLogResponse, RpcError>) {
+        let Ok(resp) = resp else {
+            return;
+        };
+        if resp.term > self.sto.term {
+            if self.sto.update_term(resp.term) {
+                self.step_down();
+            }
+            return;
+        }
+        if let Some(read) = self.reads.get_mut(&seq) {
+            read.granted.insert(from);
+        }
+    }
+
+    /// Answer the reads confirmed by a majority once an entry of the leader's term is committed.
+    fn resolve_reads(&mut self) {
+        let Some(leading) = self.leading.as_ref() else {
+            return;
+        };
+        if self.commit < leading.log_index_range.0 {
+            return;
+        }
+        let membership = self.sto.membership().1;
+        let confirmed: Vec<u64> = self
+            .reads
+            .iter()
+            .filter(|(_, read)| membership.is_quorum(&read.granted))
+            .map(|(seq, _)| *seq)
+            .collect();
+        for seq in confirmed {
+            let read = self.reads.remove(&seq).unwrap();
+            let _ = read.tx.send(Some(read.index));
+        }
+    }
+
+    /// Get the read index of a lease read without a round trip to the followers. It is `None` if
+    /// this instance is not the leader, or its lease has expired.
+    fn lease_read_index(&self) -> Option<u64> {
+        // the transferee may be elected before the lease expires
+        let leading = self.leading.as_ref().filter(|_| self.transfer.is_none())?;
+        let now = self.clock.now();
+        let mut acked_at: BTreeMap<u64, u64> = leading
+            .progresses
+            .iter()
+            .map(|(id, p)| (*id, p.acked_at as u64))
+            .collect();
+        acked_at.insert(self.id as u64, now as u64);
+        // the lease starts when the latest rpc acknowledged by a majority was sent
+        let lease_start = self.sto.membership().1.quorum_acked(&acked_at) as u128;
+        if now >= lease_start + self.timeouts.lease() as u128 {
+            return None;
+        }
+        (self.commit >= leading.log_index_range.0).then_some(self.commit)
+    }
+
+    /// Start handing the leadership over to the voter `target`, `tx` is answered with whether
+    /// `target` took over before the transfer is aborted.
+    fn transfer_leader(&mut self, target: u64, tx: oneshot::Sender<bool>) {
+        let witness = self.sto.membership().1.witnesses.contains(&target);
+        if self.leading.is_none() || self.transfer.is_some() || witness {
+            let _ = tx.send(false);
+            return;
+        }
+        self.transfer = Some(Transfer {
+            target,
+            deadline: self.clock.now() + self.timeouts.election.0 as u128,
+            timeout_now_sent: false,
+            tx,
+        });
+        info!("raft {} starts transferring the leadership to {}", self.id, target);
+        self.replicate(false);
+    }
+
+    /// Finish the leadership transfer once the target is the leader or the deadline has passed,
+    /// otherwise make the target campaign right away once it has caught up.
+    fn advance_transfer(&mut self) {
+        let Some(transfer) = self.transfer.as_mut() else {
+            return;
+        };
+        let done = self.leader_id == Some(transfer.target as u32);
+        if done || self.clock.now() >= transfer.deadline {
+            let transfer = self.transfer.take().unwrap();
+            if !done && self.leading.is_some() {
+                info!("raft {} aborts transferring the leadership to {}", self.id, transfer.target);
+            }
+            let _ = transfer.tx.send(done);
+            return;
+        }
+        // after stepping down, it waits for the heartbeat of the new leader
+        let Some(leading) = self.leading.as_ref().filter(|_| !transfer.timeout_now_sent) else {
+            return;
+        };
+        let target = transfer.target;
+        let caught_up = leading
+            .progresses
+            .get(&target)
+            .is_some_and(|p| p.acked == self.sto.get_last());
+        let Some(addr) = self.peers.get(&target).filter(|_| caught_up).cloned() else {
+            return;
+        };
+        transfer.timeout_now_sent = true;
+        let request = TimeoutNowRequest {
+            id: self.id,
+            term: self.sto.term,
+            group: self.group,
+        };
+        let ctx = TraceContext::root();
+        let span = info_span!(
+            parent: None,
+            "timeout_now",
+            node = self.id,
+            group = self.group,
+            term = self.sto.term,
+            target,
+            trace_id = %ctx.trace_id(),
+        );
+        let transport = self.transport.clone();
+        self.spawn(ctx, span, async move {
+            let resp = transport.timeout_now(addr, request).await;
+            Event::TimeoutNowResp { target, resp }
+        });
+    }
+
+    /// Whether a majority has not acknowledged this leader within the minimum election timeout.
+    pub(crate) fn quorum_lost(&self) -> bool {
+        let Some(leading) = self.leading.as_ref() else {
+            return false;
+        };
+        let now = self.clock.now();
+        let mut acked_at: BTreeMap<u64, u64> = leading
+            .progresses
+            .iter()
+            .map(|(id, p)| (*id, p.acked_at as u64))
+            .collect();
+        acked_at.insert(self.id as u64, now as u64);
+        let heard_at = self.sto.membership().1.quorum_acked(&acked_at) as u128;
+        now.saturating_sub(heard_at) > self.timeouts.election.0 as u128
+    }
+
+    /// Whether the leader may still hold a lease, i.e. this instance is the leader or has heard
+    /// from it within the minimum election timeout.
+    fn in_leader_lease(&self) -> bool {
+        let cur_ts = self.clock.now();
+        self.role == Role::Leader
+            || (self.leader_id.is_some() && cur_ts.saturating_sub(self.last_hb) < self.timeouts.election.0 as u128)
+    }
+
+    /// Send append log rpcs to every follower that is behind the leader, as many as its window of rpcs
+    /// in flight allows. With `heartbeat` set, a follower with room in its window gets at least an
+    /// empty one to keep its election timer quiet.
+    fn replicate(&mut self, heartbeat: bool) {
+        let Some(leading) = self.leading.as_mut() else {
+            return;
+        };
+        let sto = &self.sto;
+        let policy = &self.replication;
+        let last = sto.get_last();
+        let mut rpcs: Vec<(Span, Rpc)> = Vec::new();
+        let ctx = TraceContext::root();
+        let round = debug_span!(
+            parent: None,
+            "replicate",
+            node = self.id,
+            group = self.group,
+            term = sto.term,
+            last_index = last.index,
+            commit = self.commit,
+            trace_id = %ctx.trace_id(),
+        );
+
+        let witnesses = sto.membership().1.witnesses;
+        for (id, progress) in leading.progresses.iter_mut() {
+            let Some(addr) = self.peers.get(id) else {
+                continue;
+            };
+            let (id, term) = (*id, sto.term);
+            let peer = id.to_string();
+            let mut heartbeat = heartbeat;
+            while !progress.is_paused(policy) && (heartbeat || progress.len < last.index) {
+                heartbeat = false;
+                let (addr, transport) = (addr.clone(), self.transport.clone());
+                let sent_at = self.clock.now();
+                // the entries the follower needs are compacted, catch it up with the snapshot
+                if progress.len < sto.snapshot_last().index {
+                    let mut snapshot = sto.snapshot.clone();
+                    // a witness keeps no state machine, it only needs the log id and the membership
+                    if witnesses.contains(&id) {
+                        snapshot.data.clear();
+                    }
+                    let sent_last = sto.snapshot_last();
+                    let chunks = snapshot_chunks(self.id, self.group, term, snapshot);
+                    let seq = progress.send(Inflight {
+                        bytes: 0,
+                        snapshot: true,
+                    });
+                    progress.len = sent_last.index;
+                    self.telemetry.snapshots_sent.with_label_values(&[&peer]).inc();
+                    let span = debug_span!(parent: &round, "install_snapshot", peer = id, last_index = sent_last.index);
+                    rpcs.push((
+                        span,
+                        Box::pin(async move {
+                            let resp = transport.install_snapshot(addr, chunks).await;
+                            // a follower always accepts the snapshot of a leader with a valid term
+                            let resp = resp.map(|resp| AppendLogResponse {
+                                success: true,
+                                term: resp.term,
+                                ..Default::default()
+                            });
+                            Event::AppendLogResp {
+                                id,
+                                term,
+                                seq,
+                                sent_last,
+                                sent_at,
+                                resp,
+                            }
+                        }),
+                    ));
+                    continue;
+                }
+                let Some(prev_log_id) = sto.get_log_id(progress.len) else {
+                    // the assumed length is beyond the leader log, start over from what is acked
+                    progress.len = progress.acked.index;
+                    progress.inflight.clear();
+                    break;
+                };
+                let log = sto.get_logs_within(progress.len + 1, policy.max_entries, policy.max_bytes);
+                let sent_last = log.last().and_then(|log| log.id.clone()).unwrap_or(prev_log_id.clone());
+                let bytes = log.iter().map(|log| log.encoded_len() as u64).sum();
+                let request = AppendLogRequest {
+                    id: self.id,
+                    term,
+                    last_log_id: Some(last.clone()),
+                    prev_log_id: Some(prev_log_id),
+                    log,
+                    leader_commit: self.commit,
+                    group: self.group,
+                };
+                let span = debug_span!(
+                    parent: &round,
+                    "append_log",
+                    peer = id,
+                    prev_index = progress.len,
+                    entries = request.log.len(),
+                );
+                let seq = progress.send(Inflight { bytes, snapshot: false });
+                // the following entries go out without waiting for the response
+                progress.len = sent_last.index;
+                self.telemetry.append_log_sent.with_label_values(&[&peer]).inc();
+                rpcs.push((
+                    span,
+                    Box::pin(async move {
+                        let resp = transport.append_log(addr, request).await;
+                        Event::AppendLogResp {
+                            id,
+                            term,
+                            seq,
+                            sent_last,
+                            sent_at,
+                            resp,
+                        }
+                    }),
+                ));
+            }
+        }
+        for (span, rpc) in rpcs {
+            self.spawn(ctx, span, rpc);
+        }
+    }
+
+    /// Update the follower's `Progress` with the result of the append log or install snapshot rpc `seq`
+    /// sent in `term` at `sent_at`.
+    fn handle_append_log_resp(
+        &mut self,
+        id: u64,
+        term: u64,
+        seq: u64,
+        sent_last: LogId,
+        sent_at: u128,
+        resp: Result<AppendLogResponse, RpcError>,
+    ) {
+        let peer = id.to_string();
+        let latency = self.clock.now().saturating_sub(sent_at) as f64 / 1000.0;
+        self.telemetry
+            .append_log_latency
+            .with_label_values(&[&peer])
+            .observe(latency);
+        match resp.as_ref() {
+            Ok(resp) if !resp.success => self.telemetry.append_log_rejections.with_label_values(&[&peer]).inc(),
+            Err(_) => self.telemetry.append_log_failures.with_label_values(&[&peer]).inc(),
+            Ok(_) => {}
+        }
+        if let Ok(resp) = resp.as_ref() {
+            if self.sto.update_term(resp.term) {
+                self.step_down();
+                return;
+            }
+        }
+        let Some(leading) = self.leading.as_mut() else {
+            return;
+        };
+        if self.sto.term != term {
+            return;
+        }
+        let Some(progress) = leading.progresses.get_mut(&id) else {
+            return;
+        };
+        // an rpc sent before the leader started over only tells what the follower has
+        let current = progress.inflight.remove(&seq).is_some();
+        if resp.is_ok() {
+            // any response in the leader's term, success or not, acknowledges the leadership
+            progress.acked_at = progress.acked_at.max(sent_at);
+        }
+
+        match resp {
+            Ok(resp) if resp.success => {
+                if sent_last > progress.acked {
+                    progress.acked = sent_last;
+                }
+                progress.len = progress.len.max(progress.acked.index);
+            }
+            Ok(_) | Err(_) if !current => return,
+            Ok(resp) => {
+                // back off to the hint of the follower, but never behind what is already acked.
+                // The rpcs still in flight after the rejected one are rejected as well.
+                let hint = resp.conflict_index.unwrap_or_default();
+                let len = hint.index.min(progress.len.saturating_sub(1));
+                progress.len = len.max(progress.acked.index);
+                progress.inflight.clear();
+            }
+            Err(e) => {
+                // the entries sent after the lost rpc cannot be appended, start over from what is acked
+                progress.len = progress.acked.index;
+                progress.inflight.clear();
+                self.rpc_failed("append log to", id, &e);
+                return;
+            }
+        }
+        self.advance_commit();
+        self.replicate(false);
+    }
+
+    /// Commit the highest index replicated on a majority of the membership, counting the leader
+    /// itself if it is a voter. Only entries of the leader's own term are committed by counting
+    /// replicas, entries of previous terms are committed along with them.
+    fn advance_commit(&mut self) {
+        let Some(leading) = self.leading.as_ref() else {
+            return;
+        };
+        let mut acked: BTreeMap<u64, u64> = leading.progresses.iter().map(|(id, p)| (*id, p.acked.index)).collect();
+        acked.insert(self.id as u64, self.sto.get_last().index);
+        let index = self.sto.membership().1.quorum_acked(&acked);
+        if index < leading.log_index_range.0 || index <= self.commit {
+            return;
+        }
+        self.commit = index;
+        self.apply_committed();
+        self.advance_membership();
+    }
+
+    /// Move on once the membership in effect is committed: leave the joint membership, or give up
+    /// the leadership if this instance has been removed from the voters.
+    fn advance_membership(&mut self) {
+        if self.leading.is_none() {
+            return;
+        }
+        let (index, membership) = self.sto.membership();
+        if index > self.commit {
+            return;
+        }
+        if membership.is_joint() {
+            self.append_entry(EntryType::Config, Some(membership.leave_joint()));
+            self.replicate(false);
+        } else if !membership.voters().contains(&(self.id as u64)) {
+            info!("raft {} is removed from the cluster, steps down", self.id);
+            self.step_down();
+        }
+    }
+
+    /// Apply the committed but not yet applied logs to the state machine in log order.
+    fn apply_committed(&mut self) {
+        let last_applied = self.sm.last_applied().index;
+        if self.commit <= last_applied {
+            return;
+        }
+        let logs = self
+            .sto
+            .get_logs(last_applied + 1, (self.commit - last_applied) as usize);
+        for log in logs.iter() {
+            let output = self.sm.apply(log);
+            let log_id = log.id.as_ref().unwrap();
+            // the proposal is dropped if another leader has overwritten its entry
+            if let Some((proposed, tx)) = self.pending.remove(&log_id.index) {
+                if proposed == *log_id {
+                    let _ = tx.send(output);
+                }
+            }
+        }
+
+        let last_applied = self.sm.last_applied();
+        let entries = last_applied.index - self.sto.snapshot_last().index;
+        let policy = self.snapshot_policy;
+        if entries >= policy.max_entries || self.sto.logs_size(last_applied.index) >= policy.max_bytes {
+            self.sto.compact(Snapshot {
+                last_log_id: Some(last_applied),
+                data: self.sm.snapshot(),
+                ..Default::default()
+            });
+        }
+    }
+
+    fn election_timeout_elapsed(&self) -> bool {
+        let cur_ts = self.clock.now();
+        cur_ts.saturating_sub(self.last_hb) > self.election_timeout as u128
+    }
+
+    /// Restart the election timer with a freshly randomized timeout.
+    pub(crate) fn reset_election_timer(&mut self) {
+        self.last_hb = self.clock.now();
+        self.election_timeout = self.rng.gen_range(self.timeouts.election.0..self.timeouts.election.1);
+    }
+
+    /// Ask every voter whether it would vote for this instance in the next term, without touching
+    /// the term. A node cut off from the cluster keeps failing here instead of increasing its term,
+    /// so it cannot force the healthy leader to step down when it rejoins.
+    pub(crate) fn pre_campaign(&mut self) {
+        self.role = Role::PreCandidate;
+        self.leader_id = None;
+        self.reset_election_timer();
+        self.telemetry.pre_votes_started.inc();
+
+        let request = ElectRequest {
+            id: self.id,
+            term: self.sto.term + 1,
+            last_log_id: Some(self.sto.get_last()),
+            transfer: false,
+            group: self.group,
+        };
+        self.request_votes(request, true);
+    }
+
+    /// Become a candidate of the next term, `transfer` is set if the leader hands its leadership
+    /// over to this instance, so that the voters need not wait for the lease of the leader to expire.
+    fn campaign(&mut self, transfer: bool) {
+        let term = self.sto.term + 1;
+        self.sto.update_term(term);
+        self.sto.vote_for(self.id);
+        self.role = Role::Candidate;
+        self.leader_id = None;
+        self.reset_election_timer();
+        info!("raft {} starts election for term {}", self.id, term);
+        self.telemetry.elections_started.inc();
+
+        let request = ElectRequest {
+            id: self.id,
+            term,
+            last_log_id: Some(self.sto.get_last()),
+            transfer,
+            group: self.group,
+        };
+        self.request_votes(request, false);
+    }
+
+    /// Send a vote or pre-vote `request` to every voter in parallel, the responses are counted as
+    /// they come back.
+    fn request_votes(&mut self, request: ElectRequest, pre_vote: bool) {
+        self.round += 1;
+        let round = self.round;
+        let membership = self.sto.membership().1;
+        let ctx = TraceContext::root();
+        let span = info_span!(
+            parent: None,
+            "election",
+            node = self.id,
+            group = self.group,
+            term = request.term,
+            pre_vote,
+            round,
+            last_index = request.last_log_id.as_ref().map(|id| id.index),
+            trace_id = %ctx.trace_id(),
+        );
+        for id in membership.voters() {
+            let Some(addr) = self.peers.get(&id).cloned() else {
+                continue;
+            };
+            if id == self.id as u64 {
+                continue;
+            }
+            let request = request.clone();
+            let transport = self.transport.clone();
+            let vote = info_span!(parent: &span, "request_vote", peer = id);
+            self.spawn(ctx, vote, async move {
+                let resp = if pre_vote {
+                    transport.pre_vote(addr, request).await
+                } else {
+                    transport.elect(addr, request).await
+                };
+                Event::VoteResp { round, from: id, resp }
+            });
+        }
+        self.election = Some(Election {
+            round,
+            pre_vote,
+            term: request.term,
+            membership,
+            granted: BTreeSet::from([self.id as u64]),
+        });
+        // a single voter grants itself
+        span.in_scope(|| self.tally());
+    }
+
+    fn handle_vote_resp(&mut self, round: u64, from: u64, resp: Result<ElectResponse, RpcError>) {
+        let Some(election) = self.election.as_mut().filter(|election| election.round == round) else {
+            return;
+        };
+        let resp = match resp {
+            Ok(resp) => resp,
+            Err(e) => {
+                self.rpc_failed("request vote from", from, &e);
+                return;
+            }
+        };
+        if resp.term > election.term {
+            self.election = None;
+            if self.sto.update_term(resp.term) {
+                self.step_down();
+            }
+            return;
+        }
+        // only the votes of voters count, a learner never gets to grant one
+        if resp.granted && election.membership.voters().contains(&from) {
+            election.granted.insert(from);
+        }
+        self.tally();
+    }
+
+    /// Move on once a quorum granted the votes of the current election: campaign after the
+    /// pre-votes, or take the leadership after the votes.
+    fn tally(&mut self) {
+        if !self
+            .election
+            .as_ref()
+            .is_some_and(|e| e.membership.is_quorum(&e.granted))
+        {
+            return;
+        }
+        let election = self.election.take().unwrap();
+        if !election.pre_vote {
+            self.become_leader(election.term, election.granted);
+        } else if self.role == Role::PreCandidate {
+            // a leader may have shown up in the meantime
+            self.campaign(false);
+        }
+    }
+
+    /// Take the leadership of `term` if this instance is still its candidate.
+    pub(crate) fn become_leader(&mut self, term: u64, mut granted_by: BTreeSet<u64>) {
+        if self.sto.term != term || self.role != Role::Candidate {
+            return;
+        }
+        let voters = self.sto.membership().1.voters();
+        granted_by.retain(|id| voters.contains(id));
+        self.role = Role::Leader;
+        self.leader_id = Some(self.id);
+        // a transfer started in an earlier term has failed
+        if let Some(transfer) = self.transfer.take() {
+            let _ = transfer.tx.send(false);
+        }
+
+        let last = self.sto.get_last();
+        // every follower starts with a full election timeout before check quorum counts it lost
+        let now = self.clock.now();
+        let members = self.sto.membership().1.members();
+        let progresses = members
+            .into_iter()
+            .filter(|id| *id != self.id as u64)
+            .map(|id| (id, Progress::new(LogId::default(), last.index, now)))
+            .collect();
+        self.leading = Some(Leading::new(granted_by, progresses, (last.index + 1, last.index + 1)));
+        let granted_by = &self.leading.as_ref().unwrap().granted_by;
+        info!("raft {} becomes leader of term {} with the votes of {:?}", self.id, term, granted_by);
+        self.telemetry.elections_won.inc();
+        // entries of earlier terms are only committed along with an entry of this term, the no-op
+        // entry commits them without waiting for a proposal
+        self.append_entry(EntryType::Noop, None);
+        self.advance_commit();
+        // announce the leadership right away
+        self.last_heartbeat = now;
+        self.replicate(true);
+    }
+
+    /// Give up leadership or candidacy, e.g. when a higher term is seen.
+    fn step_down(&mut self) {
+        self.role = Role::Follower;
+        self.leader_id = None;
+        self.leading = None;
+        self.election = None;
+        // the reads waiting for a heartbeat round fail
+        self.reads.clear();
+    }
+
+    pub(crate) fn handle_elect(&mut self, req: ElectRequest) -> ElectResponse {
+        // candidates are ignored while the current leader may still hold its lease,
+        // unless the leader itself hands over to the candidate
+        if !req.transfer && self.in_leader_lease() {
+            return ElectResponse {
+                granted: false,
+                term: self.sto.term,
+            };
+        }
+        if self.sto.update_term(req.term) {
+            self.step_down();
+        }
+        let mut resp = ElectResponse {
+            granted: false,
+            term: self.sto.term,
+        };
+        // a candidate from a stale term can never win
+        if req.term < self.sto.term {
+            return resp;
+        }
+        // at most one vote per term, but the same candidate may ask again
+        if self.sto.voted_for.is_some_and(|id| id != req.id) {
+            return resp;
+        }
+        // only vote for a candidate whose log is at least as up-to-date as ours
+        let candidate_last = req.last_log_id.unwrap_or_default();
+        if candidate_last < self.sto.get_last() {
+            return resp;
+        }
+
+        self.sto.vote_for(req.id);
+        resp.granted = true;
+        info!("raft {} votes for {} in term {}", self.id, req.id, req.term);
+        // granting a vote resets the election timer
+        self.reset_election_timer();
+        resp
+    }
+
+    pub(crate) fn handle_pre_vote(&self, req: ElectRequest) -> ElectResponse {
+        let mut resp = ElectResponse {
+            granted: false,
+            term: self.sto.term,
+        };
+        // nothing changes here, it only tells whether the vote of `req.term` would be granted
+        if req.term <= self.sto.term || self.in_leader_lease() {
+            return resp;
+        }
+        resp.granted = req.last_log_id.unwrap_or_default() >= self.sto.get_last();
+        resp
+    }
+
+    fn handle_timeout_now(&mut self, req: TimeoutNowRequest) -> TimeoutNowResponse {
+        let term = self.sto.term;
+        // only the leader of the current term may cut the election timeout short
+        if req.term == term && self.leader_id == Some(req.id) && self.can_lead() {
+            info!("raft {} is asked by the leader {} to campaign", self.id, req.id);
+            self.campaign(true);
+        }
+        TimeoutNowResponse { term }
+    }
+
+    /// Accept `leader_id` as the leader of `term`, returns `false` if the term is stale.
+    fn accept_leader(&mut self, leader_id: u32, term: u64) -> bool {
+        // a leader of a stale term is ignored, it learns the new term from the response
+        if term < self.sto.term {
+            return false;
+        }
+        if self.sto.update_term(term) || self.role != Role::Follower {
+            self.step_down();
+        }
+        if self.leader_id != Some(leader_id) {
+            info!("raft {} follows the leader {} of term {}", self.id, leader_id, term);
+        }
+        self.leader_id = Some(leader_id);
+        // heartbeats and log entries from the current leader postpone the election
+        self.reset_election_timer();
+        true
+    }
+
+    fn handle_append_log(&mut self, req: AppendLogRequest) -> AppendLogResponse {
+        let mut resp = AppendLogResponse::default();
+        let accepted = self.accept_leader(req.id, req.term);
+        resp.term = self.sto.term;
+        if !accepted {
+            return resp;
+        }
+
+        let prev_log_id = req.prev_log_id.unwrap_or_default();
+        let result = self.sto.append_logs(&prev_log_id, req.log);
+        self.update_peers(&self.sto.membership().1);
+        match result {
+            Ok(last_new) => {
+                self.commit = self.commit.max(req.leader_commit.min(last_new));
+                resp.success = true;
+            }
+            Err(hint) => {
+                debug!("raft {} rejects the entries after {:?}, retry after {:?}", self.id, prev_log_id, hint);
+                resp.conflict_index = Some(hint);
+            }
+        }
+        resp
+    }
+
+    fn handle_install_snapshot(&mut self, leader_id: u32, term: u64, snapshot: Snapshot) -> InstallSnapshotResponse {
+        self.accept_leader(leader_id, term);
+        let resp = InstallSnapshotResponse { term: self.sto.term };
+        if term < self.sto.term {
+            return resp;
+        }
+
+        // nothing to do if the state machine is already beyond the snapshot
+        let last = snapshot.last_log_id.clone().unwrap_or_default();
+        if last.index <= self.sm.last_applied().index {
+            return resp;
+        }
+        self.sm.restore(last.clone(), &snapshot.data);
+        self.sto.install_snapshot(snapshot);
+        self.update_peers(&self.sto.membership().1);
+        self.commit = self.commit.max(last.index);
+        resp
+    }
+}
+
+/// Split `snapshot` into the chunks of an install snapshot stream.
+fn snapshot_chunks(id: u32, group: u64, term: u64, snapshot: Snapshot) -> Vec<InstallSnapshotRequest> {
+    let mut chunks: Vec<InstallSnapshotRequest> = snapshot
+        .data
+        .chunks(SNAPSHOT_CHUNK_SIZE)
+        .enumerate()
+        .map(|(i, data)| InstallSnapshotRequest {
+            id,
+            term,
+            last_log_id: snapshot.last_log_id.clone(),
+            offset: (i * SNAPSHOT_CHUNK_SIZE) as u64,
+            data: data.to_vec(),
+            group,
+            ..Default::default()
+        })
+        .collect();
+    if chunks.is_empty() {
+        chunks.push(InstallSnapshotRequest {
+            id,
+            term,
+            last_log_id: snapshot.last_log_id.clone(),
+            group,
+            ..Default::default()
+        });
+    }
+    // the membership only goes with the last chunk
+    let last_chunk = chunks.last_mut().unwrap();
+    last_chunk.done = true;
+    last_chunk.configs = snapshot.configs;
+    last_chunk.learners = snapshot.learners;
+    chunks
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::clock::VirtualClock;
+    use crate::state_machine::KvCommand;
+
+    fn vote_req(id: u32, term: u64, last_log_id: LogId) -> ElectRequest {
+        ElectRequest {
+            id,
+            term,
+            last_log_id: Some(last_log_id),
+            transfer: false,
+            group: 0,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_elect_one_vote_per_term() {
+        let raft = Raft::new(0, "127.0.0.1:9001,127.0.0.1:9002,127.0.0.1:9003".to_string());
+        let mut core = raft.take_core().unwrap();
+
+        let resp = core.handle_elect(vote_req(1, 1, LogId::default()));
+        assert_eq!(resp, ElectResponse { granted: true, term: 1 });
+        // the same candidate may retry, another one may not
+        assert!(core.handle_elect(vote_req(1, 1, LogId::default())).granted);
+        assert!(!core.handle_elect(vote_req(2, 1, LogId::default())).granted);
+        // a higher term resets the vote
+        let resp = core.handle_elect(vote_req(2, 2, LogId::default()));
+        assert_eq!(resp, ElectResponse { granted: true, term: 2 });
+        // a stale candidate learns the newer term
+        let resp = core.handle_elect(vote_req(1, 1, LogId::default()));
+        assert_eq!(
+            resp,
+            ElectResponse {
+                granted: false,
+                term: 2
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_elect_rejects_outdated_log() {
+        let raft = Raft::new(0, "127.0.0.1:9001,127.0.0.1:9002,127.0.0.1:9003".to_string());
+        let mut core = raft.take_core().unwrap();
+        core.sto.term = 2;
+        core.sto.logs.push(Log {
+            id: Some(LogId { term: 2, index: 1 }),
+            ..Default::default()
+        });
+
+        let resp = core.handle_elect(vote_req(1, 3, LogId { term: 1, index: 5 }));
+        assert_eq!(
+            resp,
+            ElectResponse {
+                granted: false,
+                term: 3
+            }
+        );
+        assert!(core.handle_elect(vote_req(1, 3, LogId { term: 2, index: 1 })).granted);
+    }
+
+    #[tokio::test]
+    async fn test_pre_vote_keeps_term() {
+        // nobody listens on the peers, so the pre-vote can never win
+        let raft = Raft::new(0, "127.0.0.1:19161,127.0.0.1:19162,127.0.0.1:19163".to_string());
+        let mut core = raft.take_core().unwrap();
+        let resp = core.handle_pre_vote(vote_req(1, 1, LogId::default()));
+        assert_eq!(resp, ElectResponse { granted: true, term: 0 });
+        assert!(!core.handle_pre_vote(vote_req(1, 0, LogId::default())).granted);
+        assert_eq!(core.sto.voted_for, None);
+
+        for _ in 0..3 {
+            core.pre_campaign();
+        }
+        assert_eq!(core.role, Role::PreCandidate);
+        assert_eq!(core.sto.term, 0);
+    }
+
+    #[tokio::test]
+    async fn test_leader_commits_earlier_terms_with_noop() {
+        let raft = Raft::new(0, "127.0.0.1:19167".to_string());
+        let mut core = raft.take_core().unwrap();
+        let cmd = KvCommand::Put {
+            key: "k".to_string(),
+            value: "v".to_string(),
+        };
+        core.sto.logs.push(Log {
+            id: Some(LogId { term: 1, index: 1 }),
+            data: cmd.encode(),
+            ..Default::default()
+        });
+        core.sto.update_term(2);
+        core.role = Role::Candidate;
+        core.become_leader(2, BTreeSet::from([0]));
+
+        let noop = core.sto.get_logs(2, 1).pop().unwrap();
+        assert_eq!(noop.id, Some(LogId { term: 2, index: 2 }));
+        assert_eq!(noop.entry_type(), EntryType::Noop);
+        // the entry of term 1 is committed and applied along with the no-op entry
+        assert_eq!(core.commit, 2);
+        assert_eq!(core.sm.query("k"), "\"v\"");
+
+        // a barrier is answered once the entries before it are applied
+        let (tx, rx) = oneshot::channel();
+        core.proposals.push((EntryType::Barrier, Vec::new(), tx));
+        core.flush_proposals();
+        let (log_id, applied) = rx.await.unwrap().unwrap();
+        assert_eq!(log_id, LogId { term: 2, index: 3 });
+        assert_eq!(applied.await.unwrap(), "");
+        assert_eq!(core.sm.last_applied(), log_id);
+    }
+
+    #[tokio::test]
+    async fn test_only_voters_grant_the_leadership() {
+        // nobody listens on the peers, the votes are handed in by the test
+        let addrs: Vec<String> = (0..3).map(|id| format!("127.0.0.1:{}", 19311 + id)).collect();
+        let raft = Raft::new(0, addrs.join(","));
+        let mut core = raft.take_core().unwrap();
+        let membership = core.sto.membership().1.with_witness(2, addrs[2].clone());
+        let membership = membership.with_learner(3, "127.0.0.1:19314".to_string());
+        core.sto.configs.insert(0, membership);
+
+        core.campaign(false);
+        let round = core.round;
+        let granted = || Ok(ElectResponse { granted: true, term: 1 });
+        // a learner granting its vote does not make a majority
+        core.handle_vote_resp(round, 3, granted());
+        assert_eq!(core.role, Role::Candidate);
+        core.handle_vote_resp(round, 2, granted());
+        assert_eq!(core.role, Role::Leader);
+        assert_eq!(core.leading.as_ref().unwrap().granted_by, BTreeSet::from([0, 2]));
+        assert_eq!(core.status().granted_by, vec![0, 2]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_check_quorum_steps_down() {
+        let raft = Raft::new(0, "127.0.0.1:19164,127.0.0.1:19165,127.0.0.1:19166".to_string())
+            .with_clock(Arc::new(VirtualClock::new()));
+        let mut core = raft.take_core().unwrap();
+        core.sto.update_term(1);
+        core.role = Role::Candidate;
+        core.become_leader(1, BTreeSet::from([0, 1]));
+        assert!(!core.quorum_lost());
+
+        // the followers never respond, so the leader gives up after an election timeout
+        tokio::time::sleep(time::Duration::from_millis(ELECTION_TIMEOUT.0 / 2)).await;
+        core.tick();
+        assert_eq!(core.role, Role::Leader);
+        tokio::time::sleep(time::Duration::from_millis(ELECTION_TIMEOUT.0)).await;
+        core.tick();
+        assert_ne!(core.role, Role::Leader);
+        assert_eq!(core.sto.term, 1);
+    }
+}
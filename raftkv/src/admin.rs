@@ -0,0 +1,312 @@
+use core::time;
+use std::result::Result;
+
+use tokio::time::Instant;
+use tonic::{Request, Response, Status};
+
+use crate::kv::PROPOSE_TIMEOUT;
+use crate::membership::{ChangeError, Membership};
+use crate::node::{not_member, Raft, Role};
+use crate::raft::{admin_server::Admin, *};
+
+impl Raft {
+    /// Propose a membership change on the leader and wait until the cluster has moved to the
+    /// resulting membership, including leaving the joint membership for a change of the voters.
+    async fn change_membership<F>(&self, change: F) -> Result<Membership, Status>
+    where
+        F: FnOnce(&Membership) -> Result<Membership, ChangeError> + Send + 'static,
+    {
+        let deadline = Instant::now() + time::Duration::from_millis(PROPOSE_TIMEOUT);
+        let (_, rx) = self
+            .propose_membership(change)
+            .await
+            .map_err(|e| self.change_rejected(e))?;
+        tokio::time::timeout_at(deadline, rx)
+            .await
+            .map_err(|_| Status::deadline_exceeded("timeout waiting for the membership change to be applied"))?
+            .map_err(|_| Status::aborted("the membership change is overwritten by another leader"))?;
+
+        // the leader proposes the new membership by itself once the joint one is committed
+        let mut metrics = self.subscribe();
+        let left = metrics.wait_for(|m| !m.membership.is_joint() && m.membership_log_index <= m.commit);
+        let metrics = tokio::time::timeout_at(deadline, left)
+            .await
+            .map_err(|_| Status::deadline_exceeded("timeout waiting for leaving the joint membership"))?
+            .map_err(|e| Status::internal(e.to_string()))?;
+        Ok(metrics.membership.clone())
+    }
+
+    fn change_rejected(&self, e: ChangeError) -> Status {
+        match e {
+            ChangeError::NotLeader => self.not_leader(),
+            ChangeError::Transferring => Status::unavailable(e.to_string()),
+            ChangeError::InProgress | ChangeError::LastVoter => Status::failed_precondition(e.to_string()),
+            ChangeError::AlreadyVoter(_) | ChangeError::AlreadyLearner(_) => Status::already_exists(e.to_string()),
+            ChangeError::NotMember(_) => Status::not_found(e.to_string()),
+        }
+    }
+}
+
+fn membership_response(membership: Membership) -> ChangeMembershipResponse {
+    let (configs, learners) = membership.encode();
+    ChangeMembershipResponse { configs, learners }
+}
+
+#[tonic::async_trait]
+impl Admin for Raft {
+    async fn add_node(&self, request: Request<AddNodeRequest>) -> Result<Response<ChangeMembershipResponse>, Status> {
+        let req = request.into_inner();
+        if req.group != self.group {
+            return Err(not_member(self.id, req.group));
+        }
+        if req.addr.is_empty() {
+            return Err(Status::invalid_argument("the address of the node is required"));
+        }
+        if req.learner && req.witness {
+            return Err(Status::invalid_argument("a learner cannot be a witness"));
+        }
+        let id = req.id as u64;
+        let membership = self
+            .change_membership(move |current| {
+                if current.voters().contains(&id) {
+                    return Err(ChangeError::AlreadyVoter(id));
+                }
+                if req.learner {
+                    if current.learners.contains(&id) {
+                        return Err(ChangeError::AlreadyLearner(id));
+                    }
+                    return Ok(current.with_learner(id, req.addr));
+                }
+                if req.witness {
+                    return Ok(current.with_witness(id, req.addr));
+                }
+                Ok(current.with_voter(id, req.addr))
+            })
+            .await?;
+        Ok(Response::new(membership_response(membership)))
+    }
+
+    async fn remove_node(
+        &self,
+        request: Request<RemoveNodeRequest>,
+    ) -> Result<Response<ChangeMembershipResponse>, Status> {
+        let req = request.into_inner();
+        if req.group != self.group {
+            return Err(not_member(self.id, req.group));
+        }
+        let id = req.id as u64;
+        let membership = self
+            .change_membership(move |current| {
+                if !current.members().contains(&id) {
+                    return Err(ChangeError::NotMember(id));
+                }
+                let next = current.without(id);
+                // witnesses alone cannot elect a leader
+                if !next.voters().into_iter().any(|id| next.can_lead(id)) {
+                    return Err(ChangeError::LastVoter);
+                }
+                Ok(next)
+            })
+            .await?;
+        Ok(Response::new(membership_response(membership)))
+    }
+
+    async fn transfer_leader(
+        &self,
+        request: Request<TransferLeaderRequest>,
+    ) -> Result<Response<TransferLeaderResponse>, Status> {
+        let req = request.into_inner();
+        if req.group != self.group {
+            return Err(not_member(self.id, req.group));
+        }
+        let target = req.id as u64;
+        let metrics = self.metrics();
+        if metrics.role != Role::Leader {
+            return Err(self.not_leader());
+        }
+        if target == self.id as u64 {
+            return Ok(Response::new(TransferLeaderResponse { term: metrics.term }));
+        }
+        if !metrics.membership.voters().contains(&target) {
+            return Err(Status::failed_precondition(format!("raft {} is not a voter", target)));
+        }
+        if metrics.membership.witnesses.contains(&target) {
+            return Err(Status::failed_precondition(format!("raft {} is a witness", target)));
+        }
+        if !self.transfer_leader(target).await {
+            return Err(Status::deadline_exceeded(format!("raft {} did not take over the leadership in time", target)));
+        }
+        Ok(Response::new(TransferLeaderResponse {
+            term: self.metrics().term,
+        }))
+    }
+
+    async fn cluster_status(
+        &self,
+        request: Request<ClusterStatusRequest>,
+    ) -> Result<Response<ClusterStatusResponse>, Status> {
+        let group = request.into_inner().group;
+        if group != self.group {
+            return Err(not_member(self.id, group));
+        }
+        Ok(Response::new(self.status().await?))
+    }
+
+    async fn split_range(&self, _: Request<SplitRangeRequest>) -> Result<Response<SplitRangeResponse>, Status> {
+        Err(Status::unimplemented(format!("raft {} serves a single range, ranges are split by a MultiRaft", self.id)))
+    }
+
+    async fn merge_range(&self, _: Request<MergeRangeRequest>) -> Result<Response<MergeRangeResponse>, Status> {
+        Err(Status::unimplemented(format!("raft {} serves a single range, ranges are merged by a MultiRaft", self.id)))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::node::Store;
+    use crate::raft::admin_client::AdminClient;
+    use crate::state_machine::{KvCommand, KvStateMachine};
+    use crate::test_util::{start, start_cluster, wait_for_leader};
+
+    #[tokio::test]
+    async fn test_add_and_remove_nodes() {
+        let peers = "127.0.0.1:19141,127.0.0.1:19142,127.0.0.1:19143";
+        let mut nodes: Vec<Raft> = (0..3).map(|id| Raft::new(id, peers.to_string())).collect();
+        let joining = Raft::join(3, "127.0.0.1:19144".to_string(), Store::new(3), Box::<KvStateMachine>::default());
+        nodes.push(joining.clone());
+        start(&nodes);
+        let leader = wait_for_leader(&nodes).await;
+        let cmd = KvCommand::Put {
+            key: "k".to_string(),
+            value: "v".to_string(),
+        };
+        leader.propose(cmd.encode()).await.unwrap();
+
+        let addr = leader.leader_addr().unwrap();
+        let mut client = AdminClient::connect(format!("http://{}", addr)).await.unwrap();
+        let mut add = AddNodeRequest {
+            id: 3,
+            addr: "127.0.0.1:19144".to_string(),
+            learner: true,
+            ..Default::default()
+        };
+        let resp = client.add_node(add.clone()).await.unwrap().into_inner();
+        assert_eq!(resp.learners, "3=127.0.0.1:19144");
+        assert!(!joining.is_voter());
+
+        // promoting the learner goes through the joint membership
+        add.learner = false;
+        let resp = client.add_node(add).await.unwrap().into_inner();
+        assert_eq!(resp.configs.len(), 1);
+        assert!(resp.configs[0].contains("3=127.0.0.1:19144"));
+        tokio::time::sleep(time::Duration::from_millis(500)).await;
+        assert!(joining.is_voter());
+        assert_eq!(joining.query("k").await.unwrap(), "\"v\"");
+
+        // the removed leader steps down and the remaining voters elect a new one
+        let removed = leader.id;
+        let resp = client
+            .remove_node(RemoveNodeRequest {
+                id: removed,
+                ..Default::default()
+            })
+            .await
+            .unwrap()
+            .into_inner();
+        assert!(!resp.configs[0].contains(&addr));
+        tokio::time::sleep(time::Duration::from_millis(100)).await;
+        assert_ne!(leader.metrics().role, Role::Leader);
+        nodes.retain(|node| node.id != removed);
+        let leader = wait_for_leader(&nodes).await;
+        let cmd = KvCommand::Put {
+            key: "k".to_string(),
+            value: "v2".to_string(),
+        };
+        let (_, rx) = leader.propose(cmd.encode()).await.unwrap();
+        rx.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_transfer_leader() {
+        let nodes = start_cluster("127.0.0.1:19171,127.0.0.1:19172,127.0.0.1:19173");
+        let leader = wait_for_leader(&nodes).await;
+        for i in 0..10 {
+            let cmd = KvCommand::Put {
+                key: format!("key-{}", i),
+                value: format!("value-{}", i),
+            };
+            leader.propose(cmd.encode()).await.unwrap();
+        }
+        let term = leader.metrics().term;
+
+        let target = nodes.iter().find(|node| node.id != leader.id).unwrap();
+        let addr = leader.leader_addr().unwrap();
+        let mut client = AdminClient::connect(format!("http://{}", addr)).await.unwrap();
+        let request = TransferLeaderRequest {
+            id: 7,
+            ..Default::default()
+        };
+        let status = client.transfer_leader(request).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::FailedPrecondition);
+
+        let request = TransferLeaderRequest {
+            id: target.id,
+            ..Default::default()
+        };
+        let resp = client.transfer_leader(request).await.unwrap().into_inner();
+        assert!(resp.term > term);
+        assert_eq!(target.metrics().role, Role::Leader);
+        assert_ne!(leader.metrics().role, Role::Leader);
+        assert_eq!(leader.metrics().leader_id, Some(target.id));
+        // the target is caught up before it campaigns
+        assert_eq!(target.metrics().last_log_id, leader.metrics().last_log_id);
+    }
+
+    #[tokio::test]
+    async fn test_cluster_status() {
+        let nodes = start_cluster("127.0.0.1:19201,127.0.0.1:19202,127.0.0.1:19203");
+        let leader = wait_for_leader(&nodes).await;
+        let cmd = KvCommand::Put {
+            key: "k".to_string(),
+            value: "v".to_string(),
+        };
+        let (log_id, rx) = leader.propose(cmd.encode()).await.unwrap();
+        rx.await.unwrap();
+        tokio::time::sleep(time::Duration::from_millis(200)).await;
+
+        let addr = leader.leader_addr().unwrap();
+        let mut client = AdminClient::connect(format!("http://{}", addr)).await.unwrap();
+        let status = client
+            .cluster_status(ClusterStatusRequest::default())
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(status.id, leader.id);
+        assert_eq!(status.role, "Leader");
+        assert_eq!(status.leader_id, Some(leader.id));
+        assert_eq!(status.voted_for, Some(leader.id));
+        assert_eq!(status.last_log_id, Some(log_id.clone()));
+        assert_eq!((status.commit, status.applied), (log_id.index, log_id.index));
+        assert_eq!(status.configs, vec!["0=127.0.0.1:19201,1=127.0.0.1:19202,2=127.0.0.1:19203"]);
+        assert_eq!(status.peers.len(), 2);
+        for peer in status.peers {
+            assert_ne!(peer.id, leader.id);
+            assert_eq!(peer.matched, Some(log_id.clone()));
+            assert_eq!(peer.lag, 0);
+        }
+        // the leader has reached every follower
+        assert_eq!(status.connections.len(), 2);
+        assert!(status
+            .connections
+            .iter()
+            .all(|peer| peer.state == "Connected" && peer.failures == 0));
+
+        // a follower answers with its own view, without the replication progress
+        let follower = nodes.iter().find(|node| node.id != leader.id).unwrap();
+        let status = follower.status().await.unwrap();
+        assert_eq!(status.role, "Follower");
+        assert_eq!(status.leader_id, Some(leader.id));
+        assert!(status.peers.is_empty());
+    }
+}
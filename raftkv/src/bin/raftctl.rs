@@ -0,0 +1,404 @@
+//! Inspect a raftkv cluster through the admin service of every node.
+//!
+//! ```text
+//! cargo run --bin raftctl -- --peers 127.0.0.1:9001,127.0.0.1:9002,127.0.0.1:9003 status
+//! cargo run --bin raftctl -- members
+//! cargo run --bin raftctl -- --group 1 split --new-group 3
+//! cargo run --bin raftctl -- --group 1 merge 3
+//! ```
+use std::time::Duration;
+
+use clap::{Parser, Subcommand};
+use tokio::task::JoinSet;
+
+use raftkv::membership::Membership;
+use raftkv::raft::{
+    admin_client::AdminClient, kv_client::KvClient, ClusterStatusRequest, ClusterStatusResponse, LogId,
+    MergeRangeRequest, Route, RoutesRequest, RoutesResponse, SplitRangeRequest, TransferLeaderRequest,
+};
+
+#[derive(Debug, Parser)]
+#[command(name = "raftctl", about = "Inspect a raftkv cluster")]
+struct Args {
+    /// the addresses of the nodes to ask, the other members they know of are asked as well
+    #[arg(
+        long,
+        value_delimiter = ',',
+        default_value = "127.0.0.1:9001,127.0.0.1:9002,127.0.0.1:9003"
+    )]
+    peers: Vec<String>,
+    /// the deadline of a request to a node, in milliseconds
+    #[arg(long, default_value_t = 1000)]
+    timeout: u64,
+    /// the raft group to inspect, on nodes hosting several
+    #[arg(long, default_value_t = 0)]
+    group: u64,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Print the state of every node, and the replication progress of the leader
+    Status,
+    /// Print the membership in effect on the leader
+    Members,
+    /// Print the key range served by every group, as the node with the latest routes knows it
+    Routes,
+    /// Split the range of the group, the upper part goes to a new group on the same nodes
+    Split {
+        /// the first key of the new group, the middle key of the range by default
+        #[arg(long)]
+        key: Option<String>,
+        /// the id of the new group
+        #[arg(long)]
+        new_group: u64,
+    },
+    /// Merge the adjacent range of `source` into the range of the group
+    Merge {
+        /// the group merged away
+        source: u64,
+    },
+}
+
+/// Ask the node at `addr` for its status in `group`.
+async fn cluster_status(addr: String, group: u64, timeout: Duration) -> Result<ClusterStatusResponse, String> {
+    let request = async {
+        let mut client = AdminClient::connect(format!("http://{}", addr))
+            .await
+            .map_err(|e| e.to_string())?;
+        let resp = client
+            .cluster_status(ClusterStatusRequest { group })
+            .await
+            .map_err(|e| e.message().to_string())?;
+        Ok(resp.into_inner())
+    };
+    tokio::time::timeout(timeout, request)
+        .await
+        .map_err(|_| "timed out".to_string())?
+}
+
+/// Ask every node of `peers`, then the members they know of that are not in `peers`.
+/// The statuses are sorted by address.
+async fn collect(
+    peers: Vec<String>,
+    group: u64,
+    timeout: Duration,
+) -> Vec<(String, Result<ClusterStatusResponse, String>)> {
+    let mut statuses: Vec<(String, Result<ClusterStatusResponse, String>)> = Vec::new();
+    let mut pending = peers;
+    while !pending.is_empty() {
+        let mut requests = JoinSet::new();
+        for addr in pending.drain(..) {
+            requests.spawn(async move { (addr.clone(), cluster_status(addr, group, timeout).await) });
+        }
+        while let Some(joined) = requests.join_next().await {
+            statuses.push(joined.expect("status request panicked"));
+        }
+        for (_, status) in statuses.iter() {
+            let Some(membership) = status.as_ref().ok().and_then(membership) else {
+                continue;
+            };
+            for addr in membership.nodes.into_values() {
+                if !pending.contains(&addr) && statuses.iter().all(|(known, _)| *known != addr) {
+                    pending.push(addr);
+                }
+            }
+        }
+    }
+    statuses.sort_by(|a, b| a.0.cmp(&b.0));
+    statuses
+}
+
+/// Ask every node of `peers` for its routes, the latest ones are returned.
+async fn collect_routes(peers: Vec<String>, timeout: Duration) -> Result<RoutesResponse, String> {
+    let mut requests = JoinSet::new();
+    for addr in peers {
+        requests.spawn(async move {
+            let request = async {
+                let mut client = KvClient::connect(format!("http://{}", addr))
+                    .await
+                    .map_err(|e| e.to_string())?;
+                let resp = client
+                    .routes(RoutesRequest {})
+                    .await
+                    .map_err(|e| e.message().to_string())?;
+                Ok::<_, String>(resp.into_inner())
+            };
+            tokio::time::timeout(timeout, request)
+                .await
+                .map_err(|_| "timed out".to_string())?
+        });
+    }
+    let mut latest: Result<RoutesResponse, String> = Err("no node is reachable".to_string());
+    while let Some(joined) = requests.join_next().await {
+        if let Ok(routes) = joined.expect("routes request panicked") {
+            if latest.as_ref().map_or(true, |latest| routes.version > latest.version) {
+                latest = Ok(routes);
+            }
+        }
+    }
+    latest
+}
+
+/// The address and id of the leader of the highest term.
+fn leader(statuses: &[(String, Result<ClusterStatusResponse, String>)]) -> Result<(String, u32), String> {
+    statuses
+        .iter()
+        .filter_map(|(addr, status)| Some((addr, status.as_ref().ok()?)))
+        .filter(|(_, status)| status.role == "Leader")
+        .max_by_key(|(_, status)| status.term)
+        .map(|(addr, status)| (addr.clone(), status.id))
+        .ok_or_else(|| "no leader".to_string())
+}
+
+async fn admin(addr: &str) -> Result<AdminClient<tonic::transport::Channel>, String> {
+    AdminClient::connect(format!("http://{}", addr))
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// Split `group` on its leader.
+async fn split(args: &Args, key: Option<String>, new_group: u64) -> Result<(), String> {
+    let statuses = collect(args.peers.clone(), args.group, Duration::from_millis(args.timeout)).await;
+    let (addr, _) = leader(&statuses)?;
+    let request = SplitRangeRequest {
+        group: args.group,
+        key: key.unwrap_or_default(),
+        new_group,
+    };
+    let resp = admin(&addr)
+        .await?
+        .split_range(request)
+        .await
+        .map_err(|e| e.message().to_string())?;
+    let resp = resp.into_inner();
+    print_routes(resp.left.into_iter().chain(resp.right).collect());
+    Ok(())
+}
+
+/// Move the leadership of `source` to the leader of the group, which then merges them.
+async fn merge(args: &Args, source: u64) -> Result<(), String> {
+    let timeout = Duration::from_millis(args.timeout);
+    let (addr, id) = leader(&collect(args.peers.clone(), args.group, timeout).await)?;
+    let (source_addr, source_leader) = leader(&collect(args.peers.clone(), source, timeout).await)?;
+    if source_leader != id {
+        println!("transferring the leadership of group {} from raft {} to raft {}", source, source_leader, id);
+        let request = TransferLeaderRequest { id, group: source };
+        admin(&source_addr)
+            .await?
+            .transfer_leader(request)
+            .await
+            .map_err(|e| e.message().to_string())?;
+    }
+    let request = MergeRangeRequest {
+        target: args.group,
+        source,
+    };
+    let resp = admin(&addr)
+        .await?
+        .merge_range(request)
+        .await
+        .map_err(|e| e.message().to_string())?;
+    print_routes(resp.into_inner().merged.into_iter().collect());
+    Ok(())
+}
+
+fn membership(status: &ClusterStatusResponse) -> Option<Membership> {
+    Membership::decode(&status.configs, &status.learners)
+}
+
+/// The status of the leader of the highest term, or of the node of the highest term if there is no leader.
+fn authority(statuses: &[(String, Result<ClusterStatusResponse, String>)]) -> Option<&ClusterStatusResponse> {
+    statuses
+        .iter()
+        .filter_map(|(_, status)| status.as_ref().ok())
+        .max_by_key(|status| (status.term, status.role == "Leader"))
+}
+
+fn log_id(id: &Option<LogId>) -> String {
+    let id = id.clone().unwrap_or_default();
+    format!("{}-{}", id.term, id.index)
+}
+
+fn optional(id: Option<u32>) -> String {
+    id.map(|id| id.to_string()).unwrap_or("-".to_string())
+}
+
+/// Print `rows` in columns as wide as their widest cell.
+fn print_table(header: &[&str], rows: Vec<Vec<String>>) {
+    let mut widths: Vec<usize> = header.iter().map(|cell| cell.len()).collect();
+    for row in rows.iter() {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.len());
+        }
+    }
+    let header: Vec<String> = header.iter().map(|cell| cell.to_string()).collect();
+    for row in std::iter::once(&header).chain(rows.iter()) {
+        let cells: Vec<String> = row
+            .iter()
+            .zip(widths.iter())
+            .map(|(cell, width)| format!("{:<width$}", cell, width = width))
+            .collect();
+        println!("{}", cells.join("  ").trim_end());
+    }
+}
+
+fn print_status(statuses: &[(String, Result<ClusterStatusResponse, String>)]) {
+    let header = [
+        "ADDR",
+        "ID",
+        "ROLE",
+        "TERM",
+        "LEADER",
+        "VOTED FOR",
+        "LAST LOG",
+        "COMMIT",
+        "APPLIED",
+    ];
+    let rows = statuses
+        .iter()
+        .map(|(addr, status)| match status {
+            Ok(status) => vec![
+                addr.clone(),
+                status.id.to_string(),
+                status.role.clone(),
+                status.term.to_string(),
+                optional(status.leader_id),
+                optional(status.voted_for),
+                log_id(&status.last_log_id),
+                status.commit.to_string(),
+                status.applied.to_string(),
+            ],
+            Err(e) => vec![addr.clone(), "-".to_string(), format!("unreachable: {}", e)],
+        })
+        .collect();
+    print_table(&header, rows);
+
+    let connections: Vec<Vec<String>> = statuses
+        .iter()
+        .filter_map(|(_, status)| status.as_ref().ok())
+        .flat_map(|status| {
+            status.connections.iter().map(|peer| {
+                vec![
+                    status.id.to_string(),
+                    peer.id.to_string(),
+                    peer.addr.clone(),
+                    peer.state.clone(),
+                    peer.failures.to_string(),
+                    format!("{}ms", peer.retry_in_ms),
+                    peer.last_error.clone(),
+                ]
+            })
+        })
+        .collect();
+    if !connections.is_empty() {
+        println!("\nconnections between the nodes:");
+        let header = ["FROM", "PEER", "ADDR", "STATE", "FAILURES", "RETRY IN", "LAST ERROR"];
+        print_table(&header, connections);
+    }
+
+    let Some(leader) = authority(statuses).filter(|status| status.role == "Leader") else {
+        println!("\nno leader");
+        return;
+    };
+    println!("\nreplication of group {} from leader {} of term {}:", leader.group, leader.id, leader.term);
+    let header = ["PEER", "ADDR", "MATCHED", "LAG", "INFLIGHT", "LAST ACK"];
+    let rows = leader
+        .peers
+        .iter()
+        .map(|peer| {
+            vec![
+                peer.id.to_string(),
+                peer.addr.clone(),
+                log_id(&peer.matched),
+                peer.lag.to_string(),
+                peer.inflight.to_string(),
+                format!("{}ms", peer.last_ack_ms),
+            ]
+        })
+        .collect();
+    print_table(&header, rows);
+}
+
+fn print_members(statuses: &[(String, Result<ClusterStatusResponse, String>)]) {
+    let Some(status) = authority(statuses) else {
+        println!("no node is reachable");
+        return;
+    };
+    let Some(membership) = membership(status) else {
+        println!("raft {} has no membership", status.id);
+        return;
+    };
+    println!("membership in effect on raft {} of term {}:", status.id, status.term);
+    if membership.is_joint() {
+        println!("joint consensus in progress: {:?} -> {:?}", membership.configs[0], membership.configs[1]);
+    }
+    let voters = membership.voters();
+    let header = ["ID", "ADDR", "SUFFRAGE", "ROLE"];
+    let rows = membership
+        .nodes
+        .iter()
+        .map(|(id, addr)| {
+            let suffrage = match (voters.contains(id), membership.witnesses.contains(id)) {
+                (true, true) => "witness",
+                (true, false) => "voter",
+                _ => "learner",
+            };
+            let role = match statuses.iter().find(|(known, _)| known == addr) {
+                Some((_, Ok(status))) => status.role.clone(),
+                _ => "unreachable".to_string(),
+            };
+            vec![id.to_string(), addr.clone(), suffrage.to_string(), role]
+        })
+        .collect();
+    print_table(&header, rows);
+}
+
+fn print_routes(routes: Vec<Route>) {
+    let header = ["GROUP", "START", "END", "VERSION", "LEADER"];
+    let rows = routes
+        .into_iter()
+        .map(|route| {
+            let leader = if route.leader.is_empty() {
+                "-".to_string()
+            } else {
+                route.leader
+            };
+            vec![
+                route.group.to_string(),
+                format!("{:?}", route.start),
+                format!("{:?}", route.end),
+                route.version.to_string(),
+                leader,
+            ]
+        })
+        .collect();
+    print_table(&header, rows);
+}
+
+#[tokio::main]
+async fn main() {
+    let args = Args::parse();
+    let timeout = Duration::from_millis(args.timeout);
+    let result = match &args.command {
+        Command::Status | Command::Members => {
+            let statuses = collect(args.peers.clone(), args.group, timeout).await;
+            match args.command {
+                Command::Status => print_status(&statuses),
+                _ => print_members(&statuses),
+            }
+            Ok(())
+        }
+        Command::Routes => collect_routes(args.peers.clone(), timeout).await.map(|routes| {
+            println!("routes of version {}:", routes.version);
+            print_routes(routes.routes);
+        }),
+        Command::Split { key, new_group } => split(&args, key.clone(), *new_group).await,
+        Command::Merge { source } => merge(&args, *source).await,
+    };
+    if let Err(e) = result {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+}
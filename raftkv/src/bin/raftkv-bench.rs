@@ -0,0 +1,134 @@
+//! Measure the write throughput and commit latency of a local 3-node cluster.
+//!
+//! ```text
+//! cargo run --release --bin raftkv-bench -- --ops 20000 --clients 64 --max-inflight 8
+//! ```
+use std::time::Duration;
+
+use clap::Parser;
+use tokio::{task::JoinSet, time::Instant};
+
+use raftkv::node::{Raft, ReplicationPolicy, Role};
+use raftkv::state_machine::KvCommand;
+
+#[derive(Debug, Parser)]
+#[command(
+    name = "raftkv-bench",
+    about = "Benchmark the writes of a local 3-node raftkv cluster"
+)]
+struct Args {
+    /// the number of writes in total
+    #[arg(long, default_value_t = 20000, value_parser = positive)]
+    ops: usize,
+    /// the number of clients writing concurrently, every client waits for its write to be applied
+    #[arg(long, default_value_t = 64, value_parser = positive)]
+    clients: usize,
+    /// the size of a value, in bytes
+    #[arg(long, default_value_t = 100)]
+    value_size: usize,
+    /// the port of the first node, the others listen on the following ones
+    #[arg(long, default_value_t = 19301)]
+    port: u16,
+    /// the max number of append log rpcs in flight to a follower
+    #[arg(long, default_value_t = ReplicationPolicy::default().max_inflight)]
+    max_inflight: usize,
+    /// the max number of log entries carried by one append log rpc
+    #[arg(long, default_value_t = ReplicationPolicy::default().max_entries)]
+    max_entries: usize,
+    /// the max encoded size of the entries carried by one append log rpc, in bytes
+    #[arg(long, default_value_t = ReplicationPolicy::default().max_bytes)]
+    max_bytes: u64,
+    /// the max encoded size of the entries in flight to a follower, in bytes
+    #[arg(long, default_value_t = ReplicationPolicy::default().max_inflight_bytes)]
+    max_inflight_bytes: u64,
+    /// the max number of queued requests the leader handles before replicating
+    #[arg(long, default_value_t = ReplicationPolicy::default().max_batch)]
+    max_batch: usize,
+}
+
+impl Args {
+    fn replication(&self) -> ReplicationPolicy {
+        ReplicationPolicy {
+            max_inflight: self.max_inflight,
+            max_entries: self.max_entries,
+            max_bytes: self.max_bytes,
+            max_inflight_bytes: self.max_inflight_bytes,
+            max_batch: self.max_batch,
+        }
+    }
+}
+
+/// Parse a count that must not be 0.
+fn positive(arg: &str) -> Result<usize, String> {
+    match arg.parse() {
+        Ok(0) => Err("must be greater than 0".to_string()),
+        Ok(n) => Ok(n),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+/// The latency at `p` percent of the sorted `latencies`.
+fn percentile(latencies: &[Duration], p: usize) -> Duration {
+    let index = (latencies.len() * p / 100).min(latencies.len() - 1);
+    latencies[index]
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args = Args::parse();
+    println!("{:?}", args);
+
+    let peers: Vec<String> = (0..3).map(|i| format!("127.0.0.1:{}", args.port + i)).collect();
+    let nodes: Vec<Raft> = (0..3)
+        .map(|id| Raft::new(id, peers.join(",")).with_replication_policy(args.replication()))
+        .collect();
+    for node in nodes.iter() {
+        tokio::spawn(Raft::run(node.clone()));
+        let sch = node.clone();
+        tokio::spawn(async move { sch.scheduler().await });
+    }
+    let leader = loop {
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        if let Some(leader) = nodes.iter().find(|node| node.metrics().role == Role::Leader) {
+            break leader.clone();
+        }
+    };
+
+    let value = "x".repeat(args.value_size);
+    let start = Instant::now();
+    let mut clients = JoinSet::new();
+    for client in 0..args.clients {
+        let (leader, value) = (leader.clone(), value.clone());
+        let ops = args.ops / args.clients + usize::from(client < args.ops % args.clients);
+        clients.spawn(async move {
+            let mut latencies = Vec::with_capacity(ops);
+            for i in 0..ops {
+                let cmd = KvCommand::Put {
+                    key: format!("key-{}-{}", client, i),
+                    value: value.clone(),
+                };
+                let proposed = Instant::now();
+                let (_, rx) = leader.propose(cmd.encode()).await.ok_or("the leader has changed")?;
+                rx.await.map_err(|_| "the write is overwritten")?;
+                latencies.push(proposed.elapsed());
+            }
+            Ok::<_, &str>(latencies)
+        });
+    }
+    let mut latencies = Vec::with_capacity(args.ops);
+    while let Some(joined) = clients.join_next().await {
+        latencies.extend(joined??);
+    }
+    let elapsed = start.elapsed();
+    latencies.sort();
+
+    println!("{} writes in {:.2?}", latencies.len(), elapsed);
+    println!("throughput: {:.0} ops/sec", latencies.len() as f64 / elapsed.as_secs_f64());
+    println!(
+        "commit latency: p50 {:.2?}, p99 {:.2?}, max {:.2?}",
+        percentile(&latencies, 50),
+        percentile(&latencies, 99),
+        latencies.last().copied().unwrap_or_default(),
+    );
+    Ok(())
+}
@@ -0,0 +1,110 @@
+//! Run a node of a raftkv cluster until it receives SIGINT or SIGTERM.
+//!
+//! ```text
+//! cargo run --bin raftkv-server -- --id 0 --peers 127.0.0.1:9001,127.0.0.1:9002,127.0.0.1:9003 --data-dir data/raft-0
+//! cargo run --bin raftkv-server -- --config raftkv.toml
+//! ```
+use std::{fs, io, sync::Arc};
+
+use clap::Parser;
+use tokio::{
+    signal::unix::{signal, SignalKind},
+    sync::watch,
+};
+use tracing::info;
+
+use raftkv::config::{ServerArgs, ServerConfig};
+use raftkv::multi::{GroupFactory, KeyRange, MultiRaft};
+use raftkv::node::{Raft, Store};
+use raftkv::state_machine::{StateMachine, WitnessStateMachine};
+use raftkv::storage::FileStorage;
+use raftkv::trace::init_subscriber;
+
+/// Keeps every group in its directory under the data directory, and the ranges next to them.
+#[derive(Debug)]
+struct FileGroups(ServerConfig);
+
+impl GroupFactory for FileGroups {
+    fn open(&self, id: u32, group: u64) -> io::Result<Store> {
+        let backend = FileStorage::open(self.0.group_dir(group))?;
+        Store::open(id, Box::new(backend))
+    }
+
+    fn configure(&self, raft: Raft) -> Raft {
+        raft.with_timeouts(self.0.timeouts())
+    }
+
+    fn remove(&self, _id: u32, group: u64) -> io::Result<()> {
+        // group 0 shares the data directory with the others, its files are left behind
+        match group {
+            0 => Ok(()),
+            group => fs::remove_dir_all(self.0.group_dir(group)),
+        }
+    }
+
+    fn save_ranges(&self, ranges: &[KeyRange]) -> io::Result<()> {
+        self.0.save_ranges(ranges)
+    }
+}
+
+/// Complete once SIGINT or SIGTERM is received.
+async fn shutdown_signal() {
+    let mut terminate = signal(SignalKind::terminate()).expect("failed to listen for SIGTERM");
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => info!("received SIGINT"),
+        _ = terminate.recv() => info!("received SIGTERM"),
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let config = ServerConfig::load(ServerArgs::parse())?;
+    init_subscriber(&config.log_level, config.log_format)?;
+    info!("{:?}", config);
+
+    // recover the persisted state of every group, if any, including the groups split off since the start
+    let factory = FileGroups(config.clone());
+    let multi = MultiRaft::with_connection_policy(config.id, config.connection_policy())
+        .with_factory(Arc::new(FileGroups(config.clone())));
+    let witnesses: Vec<u64> = config.witnesses.iter().map(|id| *id as u64).collect();
+    let mut cores = Vec::new();
+    for range in config.load_ranges()? {
+        let sto = factory.open(config.id, range.group)?;
+        // a witness keeps the log of every group but none of the keys
+        let sm: Box<dyn StateMachine> = if config.is_witness() {
+            Box::<WitnessStateMachine>::default()
+        } else {
+            multi.state_machine(range.clone())
+        };
+        let raft = Raft::with_store(config.id, config.peers.join(","), sto, sm).with_witnesses(&witnesses);
+        let raft = factory.configure(raft);
+        let raft = multi.add_group(range, raft)?;
+        cores.push(tokio::spawn(async move { raft.scheduler().await }));
+    }
+
+    // the metrics server stops along with the grpc server
+    let (stop, mut stopped) = watch::channel(false);
+    let metrics = config.metrics_listen.map(|addr| {
+        let signal = async move {
+            let _ = stopped.wait_for(|stopped| *stopped).await;
+        };
+        tokio::spawn(MultiRaft::serve_metrics(multi.clone(), addr, signal))
+    });
+    let signal = async move {
+        shutdown_signal().await;
+        stop.send_replace(true);
+    };
+    // the cores are stopped as well if the server fails, e.g. to bind its address
+    let served = MultiRaft::serve(multi.clone(), config.listen_addr(), signal).await;
+    if let Some(metrics) = metrics {
+        metrics.await??;
+    }
+    // the server has stopped accepting rpcs, stop the cores after the events already queued
+    multi.shutdown().await?;
+    for core in cores {
+        core.await?;
+    }
+    served?;
+    info!("raft {} is stopped", config.id);
+    Ok(())
+}
@@ -0,0 +1,53 @@
+use std::fmt::Debug;
+use std::sync::OnceLock;
+use std::time::Instant;
+
+/// Where a raft instance reads the time from, for its election timer, leader lease and the
+/// deadlines of a leadership transfer.
+pub trait Clock: Debug + Send + Sync {
+    /// the milliseconds elapsed since a fixed moment, it never goes backward
+    fn now(&self) -> u128;
+}
+
+/// The monotonic clock of the system, the time is the milliseconds since the process first read it.
+/// Unlike the wall clock it is not stepped backward by an NTP adjustment, which would stretch a
+/// leader lease past the election timeout of the followers.
+#[derive(Debug, Clone, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> u128 {
+        static START: OnceLock<Instant> = OnceLock::new();
+        START.get_or_init(Instant::now).elapsed().as_millis()
+    }
+}
+
+/// A clock following the timer of the tokio runtime, the time is the milliseconds since it is created.
+///
+/// Once the time of the runtime is paused, e.g. in a `#[tokio::test(start_paused = true)]`, it
+/// only moves forward when every task is waiting for a timer, and jumps to the earliest one.
+/// A run then takes no real time and depends on nothing but the timers, so it can be reproduced.
+#[derive(Debug, Clone)]
+pub struct VirtualClock {
+    start: tokio::time::Instant,
+}
+
+impl VirtualClock {
+    pub fn new() -> Self {
+        VirtualClock {
+            start: tokio::time::Instant::now(),
+        }
+    }
+}
+
+impl Default for VirtualClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for VirtualClock {
+    fn now(&self) -> u128 {
+        self.start.elapsed().as_millis()
+    }
+}
@@ -0,0 +1,380 @@
+use std::{
+    fs, io,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+};
+
+use clap::Parser;
+use derive_more::Display;
+use serde::Deserialize;
+
+use crate::multi::{KeyRange, Router};
+use crate::node::{Timeouts, ELECTION_TIMEOUT, HEARTBEAT_INTERVAL};
+use crate::peers::ConnectionPolicy;
+use crate::trace::LogFormat;
+
+/// The command line of `raftkv-server`. A flag overrides the same setting of the config file.
+#[derive(Debug, Default, Parser)]
+#[command(name = "raftkv-server", about = "Run a node of a raftkv cluster")]
+pub struct ServerArgs {
+    /// a TOML file holding the settings below, named after the flags with `_` instead of `-`
+    #[arg(short, long)]
+    pub config: Option<PathBuf>,
+    /// the raft instance id, the index of this node in the peer list
+    #[arg(long)]
+    pub id: Option<u32>,
+    /// the address to listen on, the address of this node in the peer list by default
+    #[arg(long)]
+    pub listen: Option<SocketAddr>,
+    /// the addresses of every node of the initial membership, separated by commas
+    #[arg(long, value_delimiter = ',')]
+    pub peers: Option<Vec<String>>,
+    /// the directory the log, the hard state and the snapshot are kept in
+    #[arg(long)]
+    pub data_dir: Option<PathBuf>,
+    /// the minimum election timeout, in milliseconds
+    #[arg(long)]
+    pub election_timeout_min: Option<u64>,
+    /// the maximum election timeout, in milliseconds
+    #[arg(long)]
+    pub election_timeout_max: Option<u64>,
+    /// the interval the leader sends heartbeats at, in milliseconds
+    #[arg(long)]
+    pub heartbeat_interval: Option<u64>,
+    /// the log filter, a level like `debug` or directives like `raftkv=debug,tonic=info`
+    #[arg(long)]
+    pub log_level: Option<String>,
+    /// how the log lines are written
+    #[arg(long, value_enum)]
+    pub log_format: Option<LogFormat>,
+    /// the address to serve the Prometheus metrics on at `/metrics`, they are not served by default
+    #[arg(long)]
+    pub metrics_listen: Option<SocketAddr>,
+    /// the deadline of an rpc to a peer, in milliseconds
+    #[arg(long)]
+    pub rpc_timeout: Option<u64>,
+    /// the longest a peer that failed to answer is not retried for, in milliseconds, half the minimum
+    /// election timeout by default
+    #[arg(long)]
+    pub reconnect_backoff_max: Option<u64>,
+    /// the ids of the initial members that are witnesses, separated by commas. A witness votes and
+    /// keeps the log but no data, and never becomes the leader
+    #[arg(long, value_delimiter = ',')]
+    pub witnesses: Option<Vec<u32>>,
+}
+
+/// The settings of a `raftkv-server` node, read from the config file then overridden by the flags.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub id: u32,
+    pub listen: Option<SocketAddr>,
+    pub peers: Vec<String>,
+    pub data_dir: PathBuf,
+    pub election_timeout_min: u64,
+    pub election_timeout_max: u64,
+    pub heartbeat_interval: u64,
+    pub log_level: String,
+    pub log_format: LogFormat,
+    pub metrics_listen: Option<SocketAddr>,
+    pub rpc_timeout: u64,
+    pub reconnect_backoff_max: Option<u64>,
+    pub witnesses: Vec<u32>,
+    /// the raft groups hosted by this node and the keys they serve, only set by `[[groups]]` tables of the
+    /// config file. Every node of `peers` is a member of every group
+    pub groups: Vec<KeyRange>,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            id: 0,
+            listen: None,
+            peers: Vec::new(),
+            data_dir: PathBuf::from("data"),
+            election_timeout_min: ELECTION_TIMEOUT.0,
+            election_timeout_max: ELECTION_TIMEOUT.1,
+            heartbeat_interval: HEARTBEAT_INTERVAL,
+            log_level: "info".to_string(),
+            log_format: LogFormat::default(),
+            metrics_listen: None,
+            rpc_timeout: ConnectionPolicy::default().rpc_timeout,
+            reconnect_backoff_max: None,
+            witnesses: Vec::new(),
+            groups: vec![KeyRange::full(0)],
+        }
+    }
+}
+
+#[derive(Debug, Display)]
+pub enum ConfigError {
+    #[display(fmt = "failed to read {}: {}", "_0.display()", _1)]
+    Read(PathBuf, io::Error),
+    #[display(fmt = "failed to parse {}: {}", "_0.display()", _1)]
+    Parse(PathBuf, toml::de::Error),
+    #[display(fmt = "invalid config: {}", _0)]
+    Invalid(String),
+}
+
+impl std::error::Error for ConfigError {}
+
+impl ServerConfig {
+    /// Read the config file named by `args`, if any, apply the flags on top and validate the result.
+    pub fn load(args: ServerArgs) -> Result<Self, ConfigError> {
+        let config = match args.config.as_deref() {
+            Some(path) => Self::from_file(path)?,
+            None => ServerConfig::default(),
+        };
+        let config = config.merge(args);
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn from_file(path: &Path) -> Result<Self, ConfigError> {
+        let text = fs::read_to_string(path).map_err(|e| ConfigError::Read(path.to_path_buf(), e))?;
+        toml::from_str(&text).map_err(|e| ConfigError::Parse(path.to_path_buf(), e))
+    }
+
+    fn merge(self, args: ServerArgs) -> Self {
+        ServerConfig {
+            id: args.id.unwrap_or(self.id),
+            listen: args.listen.or(self.listen),
+            peers: args.peers.unwrap_or(self.peers),
+            data_dir: args.data_dir.unwrap_or(self.data_dir),
+            election_timeout_min: args.election_timeout_min.unwrap_or(self.election_timeout_min),
+            election_timeout_max: args.election_timeout_max.unwrap_or(self.election_timeout_max),
+            heartbeat_interval: args.heartbeat_interval.unwrap_or(self.heartbeat_interval),
+            log_level: args.log_level.unwrap_or(self.log_level),
+            log_format: args.log_format.unwrap_or(self.log_format),
+            metrics_listen: args.metrics_listen.or(self.metrics_listen),
+            rpc_timeout: args.rpc_timeout.unwrap_or(self.rpc_timeout),
+            reconnect_backoff_max: args.reconnect_backoff_max.or(self.reconnect_backoff_max),
+            witnesses: args.witnesses.unwrap_or(self.witnesses),
+            groups: self.groups,
+        }
+    }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+        let invalid = |msg: String| Err(ConfigError::Invalid(msg));
+        if self.id as usize >= self.peers.len() {
+            return invalid(format!("id {} is not in the peer list {:?}", self.id, self.peers));
+        }
+        if self.listen.is_none() && self.peers[self.id as usize].parse::<SocketAddr>().is_err() {
+            return invalid(format!("cannot listen on {}, set the listen address", self.peers[self.id as usize]));
+        }
+        if self.election_timeout_min >= self.election_timeout_max {
+            return invalid("the minimum election timeout must be less than the maximum".to_string());
+        }
+        if self.heartbeat_interval.saturating_mul(2) > self.election_timeout_min {
+            return invalid("the heartbeat interval must be at most half the minimum election timeout".to_string());
+        }
+        if self.rpc_timeout == 0 {
+            return invalid("the rpc timeout must be positive".to_string());
+        }
+        if self
+            .reconnect_backoff_max
+            .is_some_and(|backoff| backoff >= self.election_timeout_min)
+        {
+            return invalid("the reconnect backoff must be less than the minimum election timeout".to_string());
+        }
+        if let Some(id) = self.witnesses.iter().find(|id| **id as usize >= self.peers.len()) {
+            return invalid(format!("witness {} is not in the peer list {:?}", id, self.peers));
+        }
+        if (0..self.peers.len() as u32).all(|id| self.witnesses.contains(&id)) {
+            return invalid("at least one peer must not be a witness".to_string());
+        }
+        if self.groups.is_empty() {
+            return invalid("no group is hosted".to_string());
+        }
+        let mut router = Router::default();
+        for range in self.groups.iter() {
+            router.insert(range.clone()).or_else(|e| invalid(e.to_string()))?;
+        }
+        Ok(())
+    }
+
+    /// The directory the data of `group` is kept in, `data_dir` itself for group 0.
+    pub fn group_dir(&self, group: u64) -> PathBuf {
+        match group {
+            0 => self.data_dir.clone(),
+            group => self.data_dir.join(format!("group-{}", group)),
+        }
+    }
+
+    /// The file the ranges of the groups are saved in once a split or a merge has changed them.
+    pub fn ranges_file(&self) -> PathBuf {
+        self.data_dir.join("ranges.json")
+    }
+
+    /// The ranges of the groups to host, the saved ones if any, otherwise `groups`.
+    pub fn load_ranges(&self) -> io::Result<Vec<KeyRange>> {
+        match fs::read(self.ranges_file()) {
+            Ok(data) => serde_json::from_slice(&data).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(self.groups.clone()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Save `ranges` to replace `groups` from the next start on.
+    pub fn save_ranges(&self, ranges: &[KeyRange]) -> io::Result<()> {
+        let path = self.ranges_file();
+        let tmp = path.with_extension("json.tmp");
+        fs::create_dir_all(&self.data_dir)?;
+        fs::write(&tmp, serde_json::to_vec(ranges)?)?;
+        fs::rename(&tmp, path)
+    }
+
+    /// The address to listen on.
+    pub fn listen_addr(&self) -> SocketAddr {
+        self.listen
+            .unwrap_or_else(|| self.peers[self.id as usize].parse().unwrap())
+    }
+
+    /// Whether this node is a witness, keeping no data.
+    pub fn is_witness(&self) -> bool {
+        self.witnesses.contains(&self.id)
+    }
+
+    /// How the connections to the peers are made and retried.
+    pub fn connection_policy(&self) -> ConnectionPolicy {
+        ConnectionPolicy {
+            rpc_timeout: self.rpc_timeout,
+            connect_timeout: self.rpc_timeout,
+            backoff_max: self.reconnect_backoff_max.unwrap_or(self.election_timeout_min / 2),
+            ..Default::default()
+        }
+    }
+
+    pub fn timeouts(&self) -> Timeouts {
+        Timeouts {
+            election: (self.election_timeout_min, self.election_timeout_max),
+            heartbeat: self.heartbeat_interval,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_flags_override_config_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("raftkv.toml");
+        let text = r#"
+            id = 1
+            peers = ["127.0.0.1:9001", "127.0.0.1:9002", "127.0.0.1:9003"]
+            data_dir = "/var/lib/raftkv"
+            election_timeout_min = 300
+            election_timeout_max = 600
+            log_level = "debug"
+            log_format = "json"
+            metrics_listen = "127.0.0.1:9101"
+            rpc_timeout = 300
+
+            [[groups]]
+            group = 1
+            end = "m"
+
+            [[groups]]
+            group = 2
+            start = "m"
+        "#;
+        fs::write(&path, text).unwrap();
+
+        let args = ServerArgs::parse_from([
+            "raftkv-server",
+            "--config",
+            path.to_str().unwrap(),
+            "--id",
+            "2",
+            "--heartbeat-interval",
+            "50",
+            "--log-format",
+            "pretty",
+        ]);
+        let config = ServerConfig::load(args).unwrap();
+        assert_eq!(config.id, 2);
+        assert_eq!(config.listen_addr(), "127.0.0.1:9003".parse().unwrap());
+        assert_eq!(config.data_dir, PathBuf::from("/var/lib/raftkv"));
+        assert_eq!(
+            config.timeouts(),
+            Timeouts {
+                election: (300, 600),
+                heartbeat: 50
+            }
+        );
+        assert_eq!(config.log_level, "debug");
+        assert_eq!(config.log_format, LogFormat::Pretty);
+        assert_eq!(config.metrics_listen, Some("127.0.0.1:9101".parse().unwrap()));
+        assert_eq!(config.connection_policy().rpc_timeout, 300);
+        let groups: Vec<(u64, &str, &str)> = config
+            .groups
+            .iter()
+            .map(|range| (range.group, range.start.as_str(), range.end.as_str()))
+            .collect();
+        assert_eq!(groups, vec![(1, "", "m"), (2, "m", "")]);
+        assert_eq!(config.group_dir(2), PathBuf::from("/var/lib/raftkv/group-2"));
+
+        // flags alone are enough
+        let args = ServerArgs::parse_from(["raftkv-server", "--peers", "a:1,b:2", "--listen", "0.0.0.0:9001"]);
+        let config = ServerConfig::load(args).unwrap();
+        assert_eq!(config.peers, vec!["a:1", "b:2"]);
+        assert_eq!(config.listen_addr(), "0.0.0.0:9001".parse().unwrap());
+        assert_eq!(config.timeouts(), Timeouts::default());
+        assert_eq!(config.groups, vec![KeyRange::full(0)]);
+        assert_eq!(config.group_dir(0), PathBuf::from("data"));
+    }
+
+    #[test]
+    fn test_saved_ranges_replace_groups() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = ServerConfig {
+            data_dir: dir.path().join("raft-0"),
+            ..Default::default()
+        };
+        assert_eq!(config.load_ranges().unwrap(), vec![KeyRange::full(0)]);
+
+        let ranges = vec![
+            KeyRange {
+                end: "m".to_string(),
+                version: 1,
+                ..KeyRange::full(0)
+            },
+            KeyRange {
+                start: "m".to_string(),
+                version: 1,
+                ..KeyRange::full(3)
+            },
+        ];
+        config.save_ranges(&ranges).unwrap();
+        assert_eq!(config.load_ranges().unwrap(), ranges);
+    }
+
+    #[test]
+    fn test_invalid_config() {
+        let load = |args: &[&str]| ServerConfig::load(ServerArgs::parse_from(args));
+        let peers = ["raftkv-server", "--peers", "127.0.0.1:9001,127.0.0.1:9002"];
+        assert!(load(&peers).is_ok());
+        assert!(load(&[&peers[..], &["--id", "2"]].concat()).is_err());
+        assert!(load(&[&peers[..], &["--election-timeout-min", "2000"]].concat()).is_err());
+        assert!(load(&[&peers[..], &["--heartbeat-interval", "600"]].concat()).is_err());
+        assert!(load(&[&peers[..], &["--heartbeat-interval", "18446744073709551615"]].concat()).is_err());
+        assert!(load(&[&peers[..], &["--reconnect-backoff-max", "1000"]].concat()).is_err());
+        assert!(load(&["raftkv-server", "--peers", "node-0:9001"]).is_err());
+        assert_eq!(load(&[&peers[..], &["--witnesses", "1"]].concat()).unwrap().witnesses, [1]);
+        assert!(load(&[&peers[..], &["--witnesses", "2"]].concat()).is_err());
+        assert!(load(&[&peers[..], &["--witnesses", "0,1"]].concat()).is_err());
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("raftkv.toml");
+        fs::write(&path, "peer = [\"127.0.0.1:9001\"]").unwrap();
+        let err = load(&["raftkv-server", "--config", path.to_str().unwrap()]).unwrap_err();
+        assert!(matches!(err, ConfigError::Parse(..)));
+
+        let groups = "peers = [\"127.0.0.1:9001\"]\n[[groups]]\ngroup = 1\n[[groups]]\ngroup = 2\nstart = \"m\"";
+        fs::write(&path, groups).unwrap();
+        let err = load(&["raftkv-server", "--config", path.to_str().unwrap()]).unwrap_err();
+        assert!(matches!(err, ConfigError::Invalid(..)));
+    }
+}
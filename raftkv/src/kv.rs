@@ -0,0 +1,503 @@
+use core::time;
+use std::result::Result;
+
+use tonic::{metadata::MetadataValue, Request, Response, Status};
+
+use crate::node::{Raft, Role};
+use crate::raft::{kv_server::Kv, *};
+use crate::state_machine::{KvCommand, KvResult};
+
+/// how long a write waits for its log entry to be committed and applied, in milliseconds
+pub const PROPOSE_TIMEOUT: u64 = 5000;
+/// the metadata key carrying the leader address when a follower rejects a request
+pub const LEADER_HINT_KEY: &str = "leader";
+
+/// Read the leader address a follower returned along with the rejection.
+pub fn leader_hint(status: &Status) -> Option<String> {
+    let hint = status.metadata().get(LEADER_HINT_KEY)?;
+    hint.to_str().ok().map(|hint| hint.to_string())
+}
+
+impl Raft {
+    /// Reject a request on a non-leader, hinting the client at the known leader.
+    pub(crate) fn not_leader(&self) -> Status {
+        let mut status = Status::unavailable(format!("raft {} is not the leader", self.id));
+        if let Some(addr) = self.leader_addr() {
+            if let Ok(addr) = MetadataValue::try_from(addr) {
+                status.metadata_mut().insert(LEADER_HINT_KEY, addr);
+            }
+        }
+        status
+    }
+
+    /// Reject a request on a key this group no longer serves after a split or a merge, the client
+    /// looks up the group of the key again with the `Routes` rpc.
+    pub(crate) fn moved(&self) -> Status {
+        Status::out_of_range(format!("the key moved out of group {}", self.group))
+    }
+
+    /// Propose a command on the leader and wait until it is applied to the state machine, returning
+    /// the output of the state machine.
+    pub(crate) async fn execute(&self, cmd: KvCommand) -> Result<String, Status> {
+        let Some((_, rx)) = self.propose(cmd.encode()).await else {
+            return Err(self.not_leader());
+        };
+        tokio::time::timeout(time::Duration::from_millis(PROPOSE_TIMEOUT), rx)
+            .await
+            .map_err(|_| Status::deadline_exceeded("timeout waiting for the log to be applied"))?
+            .map_err(|_| Status::aborted("the log is overwritten by another leader"))
+    }
+
+    /// Propose a key command and wait for its result.
+    async fn write(&self, cmd: KvCommand) -> Result<KvResult, Status> {
+        let output = self.execute(cmd).await?;
+        let result: KvResult = serde_json::from_str(&output).map_err(|e| Status::internal(e.to_string()))?;
+        if result.moved {
+            return Err(self.moved());
+        }
+        Ok(result)
+    }
+
+    /// Wait until the state machine reflects the writes committed before a read at the given
+    /// consistency level arrived.
+    async fn read_barrier(&self, consistency: Consistency) -> Result<(), Status> {
+        let index = match consistency {
+            Consistency::Stale => return Ok(()),
+            // a lease read falls back to a read index once the lease expires
+            Consistency::Lease => match self.lease_read_index().await {
+                Some(index) => Some(index),
+                None => self.read_index().await,
+            },
+            Consistency::Linearizable => self.read_index().await,
+        };
+        let index = match index {
+            Some(index) => index,
+            None if self.metrics().role != Role::Leader => return Err(self.not_leader()),
+            None => return Err(Status::unavailable(format!("raft {} cannot confirm its leadership", self.id))),
+        };
+        let mut metrics = self.subscribe();
+        let applied = metrics.wait_for(|metrics| metrics.applied.index >= index);
+        tokio::time::timeout(time::Duration::from_millis(PROPOSE_TIMEOUT), applied)
+            .await
+            .map_err(|_| Status::deadline_exceeded("timeout waiting for the read index to be applied"))?
+            .map_err(|e| Status::internal(e.to_string()))?;
+        Ok(())
+    }
+}
+
+#[tonic::async_trait]
+impl Kv for Raft {
+    async fn put(&self, request: Request<PutRequest>) -> Result<Response<PutResponse>, Status> {
+        let req = request.into_inner();
+        let result = self
+            .write(KvCommand::Put {
+                key: req.key,
+                value: req.value,
+            })
+            .await?;
+        Ok(Response::new(PutResponse { prev: result.prev }))
+    }
+
+    async fn get(&self, request: Request<GetRequest>) -> Result<Response<GetResponse>, Status> {
+        let req = request.into_inner();
+        if self.is_witness() {
+            return Err(Status::failed_precondition(format!("raft {} is a witness without data", self.id)));
+        }
+        self.read_barrier(req.consistency()).await?;
+        let output = self.query(&req.key).await?;
+        if output.is_empty() {
+            return Err(self.moved());
+        }
+        let value = serde_json::from_str(&output).map_err(|e| Status::internal(e.to_string()))?;
+        Ok(Response::new(GetResponse { value }))
+    }
+
+    async fn delete(&self, request: Request<DeleteRequest>) -> Result<Response<DeleteResponse>, Status> {
+        let req = request.into_inner();
+        let result = self.write(KvCommand::Delete { key: req.key }).await?;
+        Ok(Response::new(DeleteResponse { prev: result.prev }))
+    }
+
+    async fn compare_and_swap(
+        &self,
+        request: Request<CompareAndSwapRequest>,
+    ) -> Result<Response<CompareAndSwapResponse>, Status> {
+        let req = request.into_inner();
+        let result = self
+            .write(KvCommand::CompareAndSwap {
+                key: req.key,
+                expected: req.expected,
+                value: req.value,
+            })
+            .await?;
+        Ok(Response::new(CompareAndSwapResponse {
+            succeeded: result.ok,
+            prev: result.prev,
+        }))
+    }
+
+    /// A single instance serves every key.
+    async fn routes(&self, _: Request<RoutesRequest>) -> Result<Response<RoutesResponse>, Status> {
+        let route = Route {
+            group: self.group,
+            leader: self.leader_addr().unwrap_or_default(),
+            ..Default::default()
+        };
+        Ok(Response::new(RoutesResponse {
+            version: 0,
+            routes: vec![route],
+        }))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use linearizability::{check, KvInput, KvModel, KvOutput, Recorder};
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+    use tokio::task::JoinSet;
+
+    use super::*;
+    use crate::clock::VirtualClock;
+    use crate::node::{Store, ELECTION_TIMEOUT, TICK_INTERVAL};
+    use crate::raft::{admin_server::Admin, kv_client::KvClient};
+    use crate::sim::{SimConfig, SimNetwork};
+    use crate::state_machine::{KvStateMachine, StateMachine, WitnessStateMachine};
+    use crate::test_util::{find_leader, spawn_scheduler, start_cluster, wait_for_leader};
+
+    fn get(key: &str, consistency: Consistency) -> GetRequest {
+        GetRequest {
+            key: key.to_string(),
+            consistency: consistency as i32,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_kv_service_follows_leader_hint() {
+        let nodes = start_cluster("127.0.0.1:19121,127.0.0.1:19122,127.0.0.1:19123");
+        wait_for_leader(&nodes).await;
+
+        let mut addr = "127.0.0.1:19121".to_string();
+        let put = PutRequest {
+            key: "k".to_string(),
+            value: "v1".to_string(),
+        };
+        let resp = loop {
+            let mut client = KvClient::connect(format!("http://{}", addr)).await.unwrap();
+            match client.put(put.clone()).await {
+                Ok(resp) => break resp.into_inner(),
+                Err(status) => {
+                    addr = leader_hint(&status).unwrap_or(addr);
+                    tokio::time::sleep(time::Duration::from_millis(100)).await;
+                }
+            }
+        };
+        assert_eq!(resp.prev, None);
+
+        let mut client = KvClient::connect(format!("http://{}", addr)).await.unwrap();
+        let cas = CompareAndSwapRequest {
+            key: "k".to_string(),
+            expected: Some("v0".to_string()),
+            value: "v2".to_string(),
+        };
+        let resp = client.compare_and_swap(cas.clone()).await.unwrap().into_inner();
+        assert_eq!(
+            resp,
+            CompareAndSwapResponse {
+                succeeded: false,
+                prev: Some("v1".to_string())
+            }
+        );
+        let cas = CompareAndSwapRequest {
+            expected: Some("v1".to_string()),
+            ..cas
+        };
+        assert!(client.compare_and_swap(cas).await.unwrap().into_inner().succeeded);
+
+        let resp = client
+            .get(get("k", Consistency::Linearizable))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(resp.value, Some("v2".to_string()));
+        let resp = client
+            .delete(DeleteRequest { key: "k".to_string() })
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(resp.prev, Some("v2".to_string()));
+        let resp = client
+            .get(get("k", Consistency::Linearizable))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(resp.value, None);
+    }
+
+    #[tokio::test]
+    async fn test_read_consistency_levels() {
+        let nodes = start_cluster("127.0.0.1:19151,127.0.0.1:19152,127.0.0.1:19153");
+        let leader = wait_for_leader(&nodes).await;
+        let follower = nodes.iter().find(|node| node.id != leader.id).unwrap();
+
+        // a new leader commits an entry of its own term before it serves a read index
+        let value = leader
+            .get(Request::new(get("k", Consistency::Linearizable)))
+            .await
+            .unwrap();
+        assert_eq!(value.into_inner().value, None);
+        let index = leader.read_index().await.unwrap();
+        assert!(index >= 1);
+        assert_eq!(leader.lease_read_index().await, Some(index));
+
+        let put = PutRequest {
+            key: "k".to_string(),
+            value: "v".to_string(),
+        };
+        leader.put(Request::new(put)).await.unwrap();
+        for consistency in [Consistency::Linearizable, Consistency::Lease] {
+            let value = leader.get(Request::new(get("k", consistency))).await.unwrap();
+            assert_eq!(value.into_inner().value, Some("v".to_string()));
+            // only the leader serves consistent reads
+            let status = follower.get(Request::new(get("k", consistency))).await.unwrap_err();
+            assert_eq!(leader_hint(&status), leader.leader_addr());
+        }
+
+        // a follower serves stale reads once the write reaches it
+        tokio::time::sleep(time::Duration::from_millis(300)).await;
+        let value = follower.get(Request::new(get("k", Consistency::Stale))).await.unwrap();
+        assert_eq!(value.into_inner().value, Some("v".to_string()));
+    }
+
+    /// Run random operations on two keys against `nodes` for `millis`, following the leader hints,
+    /// and record them in `history`.
+    async fn run_client(process: usize, nodes: Vec<Raft>, history: Recorder<KvInput, KvOutput>, millis: u64) {
+        let deadline = tokio::time::Instant::now() + time::Duration::from_millis(millis);
+        let mut rng = StdRng::seed_from_u64(process as u64);
+        let mut target = rng.gen_range(0..nodes.len());
+        let mut seen = None;
+        for i in 0.. {
+            if tokio::time::Instant::now() >= deadline {
+                break;
+            }
+            tokio::time::sleep(time::Duration::from_millis(rng.gen_range(0..100))).await;
+            let key = ["x", "y"][rng.gen_range(0..2)].to_string();
+            let value = format!("{}-{}", process, i);
+            let node = &nodes[target];
+            let (op, write, result) = match rng.gen_range(0..5) {
+                0 | 1 => {
+                    let op = history.invoke(process, KvInput::Get { key: key.clone() });
+                    let consistency = [Consistency::Linearizable, Consistency::Lease][rng.gen_range(0..2)];
+                    let resp = node.get(Request::new(get(&key, consistency))).await;
+                    let resp = resp.map(|resp| resp.into_inner().value);
+                    if let Ok(value) = resp.as_ref() {
+                        seen = value.clone();
+                    }
+                    (op, false, resp.map(KvOutput::Value))
+                }
+                2 => {
+                    let op = history.invoke(
+                        process,
+                        KvInput::Put {
+                            key: key.clone(),
+                            value: value.clone(),
+                        },
+                    );
+                    let resp = node.put(Request::new(PutRequest { key, value })).await;
+                    (op, true, resp.map(|resp| KvOutput::Prev(resp.into_inner().prev)))
+                }
+                3 => {
+                    let op = history.invoke(process, KvInput::Delete { key: key.clone() });
+                    let resp = node.delete(Request::new(DeleteRequest { key })).await;
+                    (op, true, resp.map(|resp| KvOutput::Prev(resp.into_inner().prev)))
+                }
+                _ => {
+                    let expected = seen.clone();
+                    let input = KvInput::CompareAndSwap {
+                        key: key.clone(),
+                        expected: expected.clone(),
+                        value: value.clone(),
+                    };
+                    let op = history.invoke(process, input);
+                    let cas = CompareAndSwapRequest { key, expected, value };
+                    let resp = node
+                        .compare_and_swap(Request::new(cas))
+                        .await
+                        .map(|resp| resp.into_inner());
+                    (
+                        op,
+                        true,
+                        resp.map(|resp| KvOutput::Swapped {
+                            ok: resp.succeeded,
+                            prev: resp.prev,
+                        }),
+                    )
+                }
+            };
+            match result {
+                Ok(output) => history.ok(op, output),
+                Err(status) => {
+                    // a rejected request is never proposed, and a failed read changes nothing,
+                    // but a write that timed out may still be committed
+                    if !write || status.code() == tonic::Code::Unavailable {
+                        history.fail(op);
+                    } else {
+                        history.info(op);
+                    }
+                    let hint = leader_hint(&status).and_then(|addr| addr.strip_prefix("sim-")?.parse().ok());
+                    target = hint.unwrap_or_else(|| rng.gen_range(0..nodes.len()));
+                    tokio::time::sleep(time::Duration::from_millis(TICK_INTERVAL)).await;
+                }
+            }
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_linearizable_under_faults() {
+        let config = SimConfig {
+            min_delay: 1,
+            max_delay: 20,
+            drop_rate: 0.05,
+            duplicate_rate: 0.05,
+        };
+        let net = SimNetwork::new(2024, config);
+        let nodes = net.start_cluster(5);
+        let history = Recorder::new();
+        let mut clients = JoinSet::new();
+        for process in 0..4 {
+            clients.spawn(run_client(process, nodes.clone(), history.clone(), 30000));
+        }
+
+        // the nemesis keeps cutting the network into random partitions
+        let mut rng = StdRng::seed_from_u64(7);
+        let addrs: Vec<String> = (0..nodes.len()).map(|id| format!("sim-{}", id)).collect();
+        for _ in 0..15 {
+            tokio::time::sleep(time::Duration::from_millis(rng.gen_range(1000..3000))).await;
+            match rng.gen_range(0..3) {
+                0 => {
+                    let leader = find_leader(&nodes);
+                    let id = leader.map_or_else(|| rng.gen_range(0..nodes.len()), |leader| leader.id as usize);
+                    net.isolate(&addrs[id]);
+                }
+                1 => {
+                    let (left, right): (Vec<&str>, Vec<&str>) =
+                        addrs.iter().map(|addr| addr.as_str()).partition(|_| rng.gen());
+                    net.partition(&[&left, &right]);
+                }
+                _ => net.heal(),
+            }
+        }
+        net.heal();
+        while clients.join_next().await.is_some() {}
+
+        let history = history.history();
+        let completed = history
+            .iter()
+            .filter(|e| matches!(e.kind, linearizability::EventKind::Ok(_)))
+            .count();
+        assert!(completed > 100, "only {} operations completed", completed);
+        if let Err(violation) = check(&KvModel, &history) {
+            panic!("{}", violation);
+        }
+    }
+
+    fn put(key: &str, value: &str) -> Request<PutRequest> {
+        Request::new(PutRequest {
+            key: key.to_string(),
+            value: value.to_string(),
+        })
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_witness_votes_but_keeps_no_data() {
+        let net = SimNetwork::new(5, SimConfig::default());
+        let clock = Arc::new(VirtualClock::new());
+        let addrs: Vec<String> = (0..3).map(|id| format!("sim-{}", id)).collect();
+        let nodes: Vec<Raft> = (0..3)
+            .map(|id| {
+                let sm: Box<dyn StateMachine> = match id {
+                    2 => Box::<WitnessStateMachine>::default(),
+                    _ => Box::<KvStateMachine>::default(),
+                };
+                let raft = Raft::with_state_machine(id, addrs.join(","), sm)
+                    .with_witnesses(&[2])
+                    .with_transport(net.transport(&addrs[id as usize]))
+                    .with_clock(clock.clone())
+                    .with_seed(id as u64);
+                net.register(addrs[id as usize].clone(), raft.clone());
+                spawn_scheduler(&raft);
+                raft
+            })
+            .collect();
+        let witness = &nodes[2];
+        assert!(witness.is_witness());
+
+        let leader = wait_for_leader(&nodes).await;
+        assert_ne!(leader.id, witness.id);
+        leader.put(put("k", "v1")).await.unwrap();
+        assert!(!leader.transfer_leader(2).await);
+
+        // the other data node takes over, the witness votes and acks the writes but never leads
+        net.isolate(&addrs[leader.id as usize]);
+        tokio::time::sleep(time::Duration::from_millis(ELECTION_TIMEOUT.1 * 3)).await;
+        let survivors: Vec<Raft> = nodes.iter().filter(|node| node.id != leader.id).cloned().collect();
+        let new = wait_for_leader(&survivors).await;
+        assert_ne!(new.id, witness.id);
+        new.put(put("k", "v2")).await.unwrap();
+        let commit = new.metrics().commit;
+        tokio::time::sleep(time::Duration::from_millis(ELECTION_TIMEOUT.0)).await;
+        assert!(witness.metrics().applied.index >= commit);
+
+        let status = witness
+            .get(Request::new(get("k", Consistency::Stale)))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::FailedPrecondition);
+        assert_eq!(witness.query("k").await.unwrap(), "");
+        let value = new
+            .get(Request::new(get("k", Consistency::Linearizable)))
+            .await
+            .unwrap();
+        assert_eq!(value.into_inner().value, Some("v2".to_string()));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_learner_serves_linearizable_reads() {
+        let net = SimNetwork::new(6, SimConfig::default());
+        let nodes = net.start_cluster(3);
+        let learner = Raft::join(3, "sim-3".to_string(), Store::new(3), Box::<KvStateMachine>::default())
+            .with_transport(net.transport("sim-3"))
+            .with_clock(Arc::new(VirtualClock::new()));
+        net.register("sim-3".to_string(), learner.clone());
+        spawn_scheduler(&learner);
+
+        let leader = wait_for_leader(&nodes).await;
+        let add = AddNodeRequest {
+            id: 3,
+            addr: "sim-3".to_string(),
+            learner: true,
+            ..Default::default()
+        };
+        leader.add_node(Request::new(add)).await.unwrap();
+        leader.put(put("k", "v")).await.unwrap();
+
+        // the learner asks the leader for the read index and waits until it has applied up to there
+        let value = learner
+            .get(Request::new(get("k", Consistency::Linearizable)))
+            .await
+            .unwrap();
+        assert_eq!(value.into_inner().value, Some("v".to_string()));
+        let metrics = learner.metrics();
+        assert!(!metrics.membership.voters().contains(&3));
+        assert_eq!(metrics.leader_id, Some(leader.id));
+
+        // a follower still redirects to the leader
+        let follower = nodes.iter().find(|node| node.id != leader.id).unwrap();
+        let status = follower
+            .get(Request::new(get("k", Consistency::Linearizable)))
+            .await
+            .unwrap_err();
+        assert_eq!(leader_hint(&status), leader.leader_addr());
+    }
+}
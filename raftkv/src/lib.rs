@@ -0,0 +1,18 @@
+mod actor;
+pub mod admin;
+pub mod clock;
+pub mod config;
+pub mod kv;
+pub mod membership;
+pub mod multi;
+pub mod node;
+pub mod peers;
+pub mod raft;
+pub mod sim;
+pub mod state_machine;
+pub mod storage;
+pub mod telemetry;
+#[cfg(test)]
+mod test_util;
+pub mod trace;
+pub mod transport;
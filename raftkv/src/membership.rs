@@ -0,0 +1,313 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use derive_more::Display;
+
+/// the suffix of a witness in an encoded config, e.g. `2=127.0.0.1:9003#witness`
+const WITNESS_SUFFIX: &str = "#witness";
+
+/// The members of a raft cluster, as carried by `Log::configs` and `Log::learners`.
+///
+/// A change of the voters goes through joint consensus: the leader first appends a joint
+/// membership holding both the old and the new voters, and once it is committed, a membership
+/// holding only the new ones. While joint, elections and commits need a majority of both.
+///
+/// A witness is a voter keeping the log but no state machine, e.g. a cheap node in a third site
+/// breaking the ties between two others. It votes and counts towards a commit, but never leads.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Membership {
+    /// the voter sets, two of them during a joint consensus transition
+    pub configs: Vec<BTreeSet<u64>>,
+    /// members which receive logs but never vote or count towards a commit
+    pub learners: BTreeSet<u64>,
+    /// the voters which are witnesses
+    pub witnesses: BTreeSet<u64>,
+    /// the addresses of every member
+    pub nodes: BTreeMap<u64, String>,
+}
+
+/// Why a membership change is rejected.
+#[derive(Debug, Clone, PartialEq, Eq, Display)]
+pub enum ChangeError {
+    #[display(fmt = "not the leader")]
+    NotLeader,
+    #[display(fmt = "another membership change is in progress")]
+    InProgress,
+    #[display(fmt = "the leadership is being transferred")]
+    Transferring,
+    #[display(fmt = "raft {} is already a voter", _0)]
+    AlreadyVoter(u64),
+    #[display(fmt = "raft {} is already a learner", _0)]
+    AlreadyLearner(u64),
+    #[display(fmt = "raft {} is not a member", _0)]
+    NotMember(u64),
+    #[display(fmt = "cannot remove the last voter that may lead")]
+    LastVoter,
+}
+
+impl Membership {
+    /// A membership of `addrs` as voters, the id of a node is its position in `addrs`.
+    pub fn bootstrap(addrs: &[String]) -> Self {
+        let nodes: BTreeMap<u64, String> = addrs
+            .iter()
+            .cloned()
+            .enumerate()
+            .map(|(id, addr)| (id as u64, addr))
+            .collect();
+        Membership {
+            configs: vec![nodes.keys().copied().collect()],
+            learners: BTreeSet::new(),
+            witnesses: BTreeSet::new(),
+            nodes,
+        }
+    }
+
+    pub fn is_joint(&self) -> bool {
+        self.configs.len() > 1
+    }
+
+    /// All the voters, of both configs while joint.
+    pub fn voters(&self) -> BTreeSet<u64> {
+        self.configs.iter().flatten().copied().collect()
+    }
+
+    /// All the voters and learners.
+    pub fn members(&self) -> BTreeSet<u64> {
+        let mut members = self.voters();
+        members.extend(self.learners.iter().copied());
+        members
+    }
+
+    /// Whether `id` is a voter that may campaign, i.e. not a witness.
+    pub fn can_lead(&self, id: u64) -> bool {
+        self.voters().contains(&id) && !self.witnesses.contains(&id)
+    }
+
+    /// Whether `granted` holds a majority of every config.
+    pub fn is_quorum(&self, granted: &BTreeSet<u64>) -> bool {
+        !self.configs.is_empty()
+            && self
+                .configs
+                .iter()
+                .all(|config| config.intersection(granted).count() > config.len() / 2)
+    }
+
+    /// The highest value reached by a majority of every config, given the value each voter has
+    /// reached in `acked`, e.g. the index replicated up to or the time of the latest acknowledgement.
+    pub fn quorum_acked(&self, acked: &BTreeMap<u64, u64>) -> u64 {
+        self.configs
+            .iter()
+            .map(|config| {
+                let mut indexes: Vec<u64> = config.iter().map(|id| acked.get(id).copied().unwrap_or(0)).collect();
+                indexes.sort_unstable_by(|a, b| b.cmp(a));
+                indexes.get(indexes.len() / 2).copied().unwrap_or(0)
+            })
+            .min()
+            .unwrap_or(0)
+    }
+
+    /// The membership with `id` as a voter, promoting it if it is a learner.
+    pub fn with_voter(&self, id: u64, addr: String) -> Self {
+        let mut next = self.clone();
+        next.learners.remove(&id);
+        next.witnesses.remove(&id);
+        next.configs.last_mut().unwrap().insert(id);
+        next.nodes.insert(id, addr);
+        next
+    }
+
+    /// The membership with `id` as a witness.
+    pub fn with_witness(&self, id: u64, addr: String) -> Self {
+        let mut next = self.with_voter(id, addr);
+        next.witnesses.insert(id);
+        next
+    }
+
+    /// The membership with `id` as a learner.
+    pub fn with_learner(&self, id: u64, addr: String) -> Self {
+        let mut next = self.clone();
+        next.learners.insert(id);
+        next.nodes.insert(id, addr);
+        next
+    }
+
+    /// The membership without `id`, as either a voter or a learner.
+    pub fn without(&self, id: u64) -> Self {
+        let mut next = self.clone();
+        next.learners.remove(&id);
+        next.witnesses.remove(&id);
+        next.configs.last_mut().unwrap().remove(&id);
+        next.nodes.remove(&id);
+        next
+    }
+
+    /// The joint membership moving the voters of `self` to those of `next`.
+    pub fn joint(&self, next: &Membership) -> Self {
+        let mut nodes = self.nodes.clone();
+        nodes.extend(next.nodes.clone());
+        Membership {
+            configs: vec![
+                self.configs.last().unwrap().clone(),
+                next.configs.last().unwrap().clone(),
+            ],
+            learners: next.learners.clone(),
+            witnesses: self.witnesses.union(&next.witnesses).copied().collect(),
+            nodes,
+        }
+    }
+
+    /// The membership to move to once this joint membership is committed.
+    pub fn leave_joint(&self) -> Self {
+        let mut next = Membership {
+            configs: vec![self.configs.last().cloned().unwrap_or_default()],
+            learners: self.learners.clone(),
+            witnesses: BTreeSet::new(),
+            nodes: BTreeMap::new(),
+        };
+        let (members, voters) = (next.members(), next.voters());
+        next.witnesses = self.witnesses.intersection(&voters).copied().collect();
+        next.nodes = self.nodes.clone();
+        next.nodes.retain(|id, _| members.contains(id));
+        next
+    }
+
+    /// Encode into the `configs` and `learners` fields of a `Log` or a `Snapshot`.
+    pub fn encode(&self) -> (Vec<String>, String) {
+        let encode = |ids: &BTreeSet<u64>| {
+            let nodes: Vec<String> = ids
+                .iter()
+                .map(|id| {
+                    let addr = self.nodes.get(id).cloned().unwrap_or_default();
+                    let suffix = if self.witnesses.contains(id) {
+                        WITNESS_SUFFIX
+                    } else {
+                        ""
+                    };
+                    format!("{}={}{}", id, addr, suffix)
+                })
+                .collect();
+            nodes.join(",")
+        };
+        (self.configs.iter().map(encode).collect(), encode(&self.learners))
+    }
+
+    /// Decode from the `configs` and `learners` fields of a `Log` or a `Snapshot`.
+    /// It is `None` if the fields are empty, e.g. the log is not a membership change.
+    pub fn decode(configs: &[String], learners: &str) -> Option<Self> {
+        if configs.is_empty() {
+            return None;
+        }
+        let mut nodes = BTreeMap::new();
+        let mut witnesses = BTreeSet::new();
+        let mut decode = |encoded: &str| -> BTreeSet<u64> {
+            let mut ids = BTreeSet::new();
+            for node in encoded.split(',').filter(|node| !node.is_empty()) {
+                let (id, addr) = node.split_once('=').unwrap_or((node, ""));
+                let Ok(id) = id.parse() else {
+                    continue;
+                };
+                let addr = match addr.strip_suffix(WITNESS_SUFFIX) {
+                    Some(addr) => {
+                        witnesses.insert(id);
+                        addr
+                    }
+                    None => addr,
+                };
+                nodes.insert(id, addr.to_string());
+                ids.insert(id);
+            }
+            ids
+        };
+        let configs = configs.iter().map(|config| decode(config)).collect();
+        let learners = decode(learners);
+        Some(Membership {
+            configs,
+            learners,
+            witnesses,
+            nodes,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn ids(ids: &[u64]) -> BTreeSet<u64> {
+        ids.iter().copied().collect()
+    }
+
+    #[test]
+    fn test_joint_quorum() {
+        let addrs: Vec<String> = (0..3).map(|id| format!("127.0.0.1:{}", 9001 + id)).collect();
+        let old = Membership::bootstrap(&addrs);
+        assert!(old.is_quorum(&ids(&[0, 1])));
+        assert!(!old.is_quorum(&ids(&[2, 3])));
+
+        // move from {0, 1, 2} to {1, 2, 3, 4}
+        let new = old.with_voter(3, "127.0.0.1:9004".to_string());
+        let new = new.with_voter(4, "127.0.0.1:9005".to_string()).without(0);
+        let joint = old.joint(&new);
+        assert!(joint.is_joint());
+        assert_eq!(joint.voters(), ids(&[0, 1, 2, 3, 4]));
+        assert!(!joint.is_quorum(&ids(&[0, 1])));
+        assert!(!joint.is_quorum(&ids(&[2, 3, 4])));
+        assert!(joint.is_quorum(&ids(&[1, 2, 3])));
+
+        let acked = BTreeMap::from([(0, 9), (1, 9), (2, 5), (3, 4), (4, 3)]);
+        assert_eq!(old.quorum_acked(&acked), 9);
+        assert_eq!(joint.quorum_acked(&acked), 4);
+
+        let left = joint.leave_joint();
+        assert_eq!(left.configs, vec![ids(&[1, 2, 3, 4])]);
+        assert!(!left.nodes.contains_key(&0));
+    }
+
+    #[test]
+    fn test_learners_do_not_vote() {
+        let addrs: Vec<String> = (0..3).map(|id| format!("127.0.0.1:{}", 9001 + id)).collect();
+        let membership = Membership::bootstrap(&addrs).with_learner(3, "127.0.0.1:9004".to_string());
+        assert_eq!(membership.members(), ids(&[0, 1, 2, 3]));
+        assert!(!membership.is_quorum(&ids(&[0, 3])));
+        let acked = BTreeMap::from([(0, 1), (1, 1), (2, 1), (3, 9)]);
+        assert_eq!(membership.quorum_acked(&acked), 1);
+
+        let promoted = membership.with_voter(3, "127.0.0.1:9004".to_string());
+        assert!(promoted.learners.is_empty());
+        assert_eq!(promoted.voters(), ids(&[0, 1, 2, 3]));
+    }
+
+    #[test]
+    fn test_encode_decode() {
+        let addrs: Vec<String> = (0..3).map(|id| format!("127.0.0.1:{}", 9001 + id)).collect();
+        let old = Membership::bootstrap(&addrs);
+        let new = old.without(2).with_learner(5, "127.0.0.1:9006".to_string());
+        let joint = old.joint(&new);
+        let (configs, learners) = joint.encode();
+        assert_eq!(configs[1], "0=127.0.0.1:9001,1=127.0.0.1:9002");
+        assert_eq!(learners, "5=127.0.0.1:9006");
+        assert_eq!(Membership::decode(&configs, &learners), Some(joint));
+        assert_eq!(Membership::decode(&[], ""), None);
+    }
+
+    #[test]
+    fn test_witnesses_vote_but_never_lead() {
+        let addrs: Vec<String> = (0..2).map(|id| format!("127.0.0.1:{}", 9001 + id)).collect();
+        let old = Membership::bootstrap(&addrs);
+        let new = old.with_witness(2, "127.0.0.1:9003".to_string());
+        assert!(new.is_quorum(&ids(&[0, 2])));
+        assert!(new.can_lead(0));
+        assert!(!new.can_lead(2));
+        let acked = BTreeMap::from([(0, 9), (1, 1), (2, 9)]);
+        assert_eq!(new.quorum_acked(&acked), 9);
+
+        let joint = old.joint(&new);
+        assert!(joint.witnesses.contains(&2));
+        let (configs, learners) = joint.encode();
+        assert_eq!(configs[1], "0=127.0.0.1:9001,1=127.0.0.1:9002,2=127.0.0.1:9003#witness");
+        let decoded = Membership::decode(&configs, &learners).unwrap();
+        assert_eq!(decoded, joint);
+        assert_eq!(decoded.nodes[&2], "127.0.0.1:9003");
+        assert_eq!(joint.leave_joint(), new);
+        assert!(new.without(2).witnesses.is_empty());
+    }
+}
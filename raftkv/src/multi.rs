@@ -0,0 +1,1068 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Debug,
+    future::Future,
+    io,
+    net::SocketAddr,
+    ops::Bound,
+    result::Result,
+    sync::{Arc, RwLock, Weak},
+};
+
+use derive_more::Display;
+use serde::{Deserialize, Serialize};
+use tonic::{transport::Server, Request, Response, Status, Streaming};
+use tracing::{error, info};
+
+use crate::membership::Membership;
+use crate::node::{not_member, read_chunks, Raft, Role, Store, FILE_DESCRIPTOR_SET};
+use crate::peers::ConnectionPolicy;
+use crate::raft::{
+    admin_server::{Admin, AdminServer},
+    kv_server::{Kv, KvServer},
+    raft_server::{Raft as RaftTrait, RaftServer},
+    *,
+};
+use crate::state_machine::{KvCommand, KvSnapshot, KvStateMachine, RangeChange, StateMachine};
+use crate::telemetry::{self, Telemetry};
+use crate::trace::TraceContext;
+use crate::transport::{CoalescingTransport, GrpcTransport, Transport};
+
+/// how long a heartbeat waits for the heartbeats of other groups to the same peer, in milliseconds
+pub const HEARTBEAT_COALESCE_WINDOW: u64 = 5;
+/// the max encoded size of the keys a merge carries, in bytes. They go through the log of the target
+/// in a single entry, which has to fit in a grpc message of at most 4 MB
+pub const MAX_MERGE_BYTES: usize = 1024 * 1024;
+
+/// A range of keys `[start, end)` served by a raft group, an empty `end` is unbounded.
+#[derive(Debug, Clone, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct KeyRange {
+    pub group: u64,
+    pub start: String,
+    pub end: String,
+    /// bumped by every split or merge of the range, so that the routes never go back to an older one
+    pub version: u64,
+}
+
+impl KeyRange {
+    /// The range of every key, served by `group`.
+    pub fn full(group: u64) -> Self {
+        KeyRange {
+            group,
+            ..Default::default()
+        }
+    }
+
+    pub fn contains(&self, key: &str) -> bool {
+        key >= self.start.as_str() && (self.end.is_empty() || key < self.end.as_str())
+    }
+
+    /// Whether every key of `other` falls in this range.
+    pub fn covers(&self, other: &KeyRange) -> bool {
+        self.contains(&other.start) && (self.end.is_empty() || (!other.end.is_empty() && other.end <= self.end))
+    }
+
+    pub fn overlaps(&self, other: &KeyRange) -> bool {
+        (self.end.is_empty() || other.start < self.end) && (other.end.is_empty() || self.start < other.end)
+    }
+}
+
+/// Why a range cannot be added to a router.
+#[derive(Debug, Clone, PartialEq, Eq, Display)]
+pub enum RouteError {
+    #[display(fmt = "the range {:?}..{:?} is empty", "_0.start", "_0.end")]
+    EmptyRange(KeyRange),
+    #[display(fmt = "the range of group {} overlaps the range of group {}", _0, _1)]
+    Overlap(u64, u64),
+    #[display(fmt = "group {} already serves a range", _0)]
+    DuplicateGroup(u64),
+}
+
+impl std::error::Error for RouteError {}
+
+/// Maps the keys to the raft groups serving them, the ranges of the groups never overlap.
+#[derive(Debug, Clone, Default)]
+pub struct Router {
+    /// the ranges keyed by their start
+    ranges: BTreeMap<String, KeyRange>,
+    /// bumped by every change of the routes
+    version: u64,
+}
+
+impl Router {
+    /// Route the keys of `range` to its group.
+    pub fn insert(&mut self, range: KeyRange) -> Result<(), RouteError> {
+        if !range.end.is_empty() && range.end <= range.start {
+            return Err(RouteError::EmptyRange(range));
+        }
+        if self.ranges.values().any(|known| known.group == range.group) {
+            return Err(RouteError::DuplicateGroup(range.group));
+        }
+        let before = self.ranges.range(..=range.start.clone()).next_back();
+        let after = self
+            .ranges
+            .range((Bound::Excluded(range.start.clone()), Bound::Unbounded))
+            .next();
+        let overlapped = before
+            .filter(|(_, known)| known.contains(&range.start))
+            .or(after.filter(|(start, _)| range.end.is_empty() || **start < range.end));
+        if let Some((_, known)) = overlapped {
+            return Err(RouteError::Overlap(range.group, known.group));
+        }
+        self.ranges.insert(range.start.clone(), range);
+        self.version += 1;
+        Ok(())
+    }
+
+    /// Stop routing to `group`, returning the range it served.
+    pub fn remove(&mut self, group: u64) -> Option<KeyRange> {
+        let start = self.get(group)?.start.clone();
+        self.version += 1;
+        self.ranges.remove(&start)
+    }
+
+    /// Follow a split, a merge or a restored snapshot applied by a group. It returns false if the routes
+    /// are already newer than the change, e.g. when a group replays its log after a restart.
+    pub fn apply(&mut self, change: &RangeChange) -> Result<bool, RouteError> {
+        let (updated, removed, ranges) = match change {
+            RangeChange::Split { left, right, .. } => (left, vec![right.group], vec![left.clone(), right.clone()]),
+            RangeChange::Merge { range, source } => (range, vec![source.group], vec![range.clone()]),
+            RangeChange::Restore { range, .. } => {
+                // a group not routed yet is being opened, its range is the one it is added with
+                if self.get(range.group).is_none() {
+                    return Ok(false);
+                }
+                // the groups whose ranges the restored range covers were merged into it
+                let removed = self
+                    .ranges()
+                    .filter(|known| known.group != range.group && known.overlaps(range))
+                    .map(|known| known.group)
+                    .collect();
+                (range, removed, vec![range.clone()])
+            }
+        };
+        if self
+            .get(updated.group)
+            .is_some_and(|known| known.version >= updated.version)
+        {
+            return Ok(false);
+        }
+        let mut next = self.clone();
+        next.remove(updated.group);
+        for group in removed {
+            next.remove(group);
+        }
+        for range in ranges {
+            next.insert(range)?;
+        }
+        // the ranges split off meanwhile are routed unless their groups are known already, or they
+        // overlap the stale route of a group that took them over since and has yet to catch up
+        if let RangeChange::Restore { split_off, .. } = change {
+            for split_off in split_off {
+                let _ = next.insert(split_off.range.clone());
+            }
+        }
+        next.version = self.version + 1;
+        *self = next;
+        Ok(true)
+    }
+
+    /// The range served by `group`.
+    pub fn get(&self, group: u64) -> Option<&KeyRange> {
+        self.ranges.values().find(|range| range.group == group)
+    }
+
+    /// The range `key` falls in, if any group serves it.
+    pub fn route(&self, key: &str) -> Option<&KeyRange> {
+        let (_, range) = self
+            .ranges
+            .range::<str, _>((Bound::Unbounded, Bound::Included(key)))
+            .next_back()?;
+        range.contains(key).then_some(range)
+    }
+
+    /// The ranges in key order.
+    pub fn ranges(&self) -> impl Iterator<Item = &KeyRange> {
+        self.ranges.values()
+    }
+
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+}
+
+/// Where a `MultiRaft` keeps the groups it creates on a split, and drops the ones merged away.
+pub trait GroupFactory: Debug + Send + Sync {
+    /// Open the store of the instance `id` of `group`, it is not empty if the group ran here before.
+    fn open(&self, id: u32, group: u64) -> io::Result<Store>;
+
+    /// Configure a new instance before its core starts, e.g. with the timeouts of the other groups.
+    fn configure(&self, raft: Raft) -> Raft {
+        raft
+    }
+
+    /// Drop the state of the instance `id` of a group merged away, once it is shut down.
+    fn remove(&self, _id: u32, _group: u64) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Persist the ranges after a split or a merge, so that the groups created are hosted again after
+    /// a restart. It is called before the change is reported as applied.
+    fn save_ranges(&self, _ranges: &[KeyRange]) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Keeps the groups created on splits in memory.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MemGroups;
+
+impl GroupFactory for MemGroups {
+    fn open(&self, id: u32, _group: u64) -> io::Result<Store> {
+        Ok(Store::new(id))
+    }
+}
+
+/// A process hosting many raft groups, each an independent `Raft` replicating its own range of keys.
+/// The groups share the transport, so their rpcs to a peer go over the same connection and their
+/// heartbeats to it go out together. The services route an rpc to the group it carries, and a kv
+/// request to the group serving its key. A clone shares the groups.
+///
+/// A range is split by a command replicated through the log of its group. Every replica applying it
+/// hands the upper part of the range to a new group with the same members, which starts on every
+/// replica from the same snapshot. A replica catching up with a snapshot of the log past the split
+/// creates the new group with no keys instead, and the leader of the new group sends them.
+#[derive(Debug, Clone)]
+pub struct MultiRaft {
+    /// the raft instance id of this process in every group
+    pub id: u32,
+    groups: Arc<RwLock<BTreeMap<u64, Raft>>>,
+    router: Arc<RwLock<Router>>,
+    transport: Arc<dyn Transport>,
+    factory: Arc<dyn GroupFactory>,
+}
+
+/// A `MultiRaft` held by the state machines of its groups, which must not keep the groups alive.
+#[derive(Debug, Clone)]
+struct WeakMultiRaft {
+    id: u32,
+    groups: Weak<RwLock<BTreeMap<u64, Raft>>>,
+    router: Weak<RwLock<Router>>,
+    transport: Arc<dyn Transport>,
+    factory: Arc<dyn GroupFactory>,
+}
+
+impl WeakMultiRaft {
+    fn upgrade(&self) -> Option<MultiRaft> {
+        Some(MultiRaft {
+            id: self.id,
+            groups: self.groups.upgrade()?,
+            router: self.router.upgrade()?,
+            transport: self.transport.clone(),
+            factory: self.factory.clone(),
+        })
+    }
+}
+
+/// the log id of the snapshot a group created by a split starts from, the same on every replica
+const SPLIT_LOG_ID: LogId = LogId { term: 0, index: 1 };
+
+impl MultiRaft {
+    pub fn new(id: u32) -> Self {
+        Self::with_connection_policy(id, ConnectionPolicy::default())
+    }
+
+    /// Reach the peers through gRPC with coalesced heartbeats, connecting and retrying as `policy` says.
+    pub fn with_connection_policy(id: u32, policy: ConnectionPolicy) -> Self {
+        let transport = CoalescingTransport::new(Arc::new(GrpcTransport::new(policy)), HEARTBEAT_COALESCE_WINDOW);
+        Self::with_transport(id, Arc::new(transport))
+    }
+
+    /// Reach the peers through `transport` instead of gRPC with coalesced heartbeats.
+    pub fn with_transport(id: u32, transport: Arc<dyn Transport>) -> Self {
+        MultiRaft {
+            id,
+            groups: Arc::new(RwLock::new(BTreeMap::new())),
+            router: Arc::new(RwLock::new(Router::default())),
+            transport,
+            factory: Arc::new(MemGroups),
+        }
+    }
+
+    /// Keep the groups created on splits with `factory` instead of in memory.
+    pub fn with_factory(mut self, factory: Arc<dyn GroupFactory>) -> Self {
+        self.factory = factory;
+        self
+    }
+
+    /// Host `raft` as the group serving `range`. The instance joins the group of the range and uses
+    /// the shared transport, its core is started by the caller with `scheduler`. The state machine of
+    /// the instance should come from `state_machine` for the range to be split and merged.
+    pub fn add_group(&self, range: KeyRange, raft: Raft) -> Result<Raft, RouteError> {
+        let group = range.group;
+        self.router.write().unwrap().insert(range)?;
+        Ok(self.host(group, raft))
+    }
+
+    fn host(&self, group: u64, raft: Raft) -> Raft {
+        let raft = raft.with_group(group).with_transport(self.transport.clone());
+        self.groups.write().unwrap().insert(group, raft.clone());
+        raft
+    }
+
+    /// The key-value state machine of the group serving `range`, the routes follow the splits and
+    /// merges it applies.
+    pub fn state_machine(&self, range: KeyRange) -> Box<dyn StateMachine> {
+        let weak = WeakMultiRaft {
+            id: self.id,
+            groups: Arc::downgrade(&self.groups),
+            router: Arc::downgrade(&self.router),
+            transport: self.transport.clone(),
+            factory: self.factory.clone(),
+        };
+        let listener = move |change| {
+            if let Some(multi) = weak.upgrade() {
+                multi.follow(change);
+            }
+        };
+        Box::new(KvStateMachine::new(range).with_listener(Arc::new(listener)))
+    }
+
+    /// Follow a range change applied by a group on this process.
+    fn follow(&self, change: RangeChange) {
+        let (before, after) = {
+            let mut router = self.router.write().unwrap();
+            let before = router.clone();
+            match router.apply(&change) {
+                Ok(true) => {}
+                Ok(false) => return,
+                Err(e) => {
+                    error!("raft {} cannot follow {:?}: {}", self.id, change, e);
+                    return;
+                }
+            }
+            (before, router.clone())
+        };
+        let ranges: Vec<KeyRange> = after.ranges().cloned().collect();
+        if let Err(e) = self.factory.save_ranges(&ranges) {
+            error!("raft {} failed to save the ranges: {}", self.id, e);
+        }
+        match change {
+            RangeChange::Split {
+                left,
+                right,
+                snapshot,
+                configs,
+                learners,
+            } => {
+                info!("raft {} splits group {} at {:?} into group {}", self.id, left.group, right.start, right.group);
+                let snapshot = Snapshot {
+                    last_log_id: Some(SPLIT_LOG_ID),
+                    data: snapshot,
+                    configs,
+                    learners,
+                };
+                let group = right.group;
+                if let Err(e) = self.create_group(right, snapshot) {
+                    error!("raft {} failed to create group {}: {}", self.id, group, e);
+                }
+            }
+            RangeChange::Merge { range, source } => {
+                info!("raft {} merges group {} into group {}", self.id, source.group, range.group);
+                self.remove_group(source.group);
+            }
+            RangeChange::Restore { range, split_off } => {
+                info!("raft {} restores group {} at version {} from a snapshot", self.id, range.group, range.version);
+                for merged in before.ranges().filter(|known| after.get(known.group).is_none()) {
+                    info!("raft {} merges group {} into group {}", self.id, merged.group, range.group);
+                    self.remove_group(merged.group);
+                }
+                for split_off in split_off {
+                    let group = split_off.range.group;
+                    if before.get(group).is_some() || after.get(group) != Some(&split_off.range) {
+                        continue;
+                    }
+                    let start = &split_off.range.start;
+                    info!("raft {} splits group {} at {:?} into group {}", self.id, range.group, start, group);
+                    // the keys split off are gone from the snapshot, the leader of the new group sends them
+                    let snapshot = Snapshot {
+                        configs: split_off.configs,
+                        learners: split_off.learners,
+                        ..Default::default()
+                    };
+                    if let Err(e) = self.create_group(split_off.range, snapshot) {
+                        error!("raft {} failed to create group {}: {}", self.id, group, e);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Start the instance of a group split off on this process. It starts from `snapshot`, or only
+    /// knows the members in it if the snapshot has no log id, until the leader sends it a snapshot.
+    fn create_group(&self, range: KeyRange, snapshot: Snapshot) -> io::Result<()> {
+        if self.group(range.group).is_some() {
+            return Ok(());
+        }
+        let mut sto = self.factory.open(self.id, range.group)?;
+        // a group that ran here before goes on from its own state
+        if sto.get_last().index == 0 {
+            match snapshot.last_log_id {
+                Some(_) => sto.install_snapshot(snapshot),
+                None => {
+                    if let Some(membership) = Membership::decode(&snapshot.configs, &snapshot.learners) {
+                        sto.configs.insert(0, membership);
+                    }
+                }
+            }
+        }
+        let raft = Raft::with_peers(self.id, BTreeMap::new(), sto, self.state_machine(range.clone()));
+        let raft = self.host(range.group, self.factory.configure(raft));
+        tokio::spawn(async move { raft.scheduler().await });
+        Ok(())
+    }
+
+    /// Stop the instance of a group merged away, and drop its state.
+    fn remove_group(&self, group: u64) {
+        let Some(raft) = self.groups.write().unwrap().remove(&group) else {
+            return;
+        };
+        let (id, factory) = (self.id, self.factory.clone());
+        tokio::spawn(async move {
+            if let Err(e) = raft.shutdown().await.and_then(|_| factory.remove(id, group)) {
+                error!("raft {} failed to remove group {}: {}", id, group, e);
+            }
+        });
+    }
+
+    pub fn group(&self, group: u64) -> Option<Raft> {
+        self.groups.read().unwrap().get(&group).cloned()
+    }
+
+    pub fn groups(&self) -> Vec<Raft> {
+        self.groups.read().unwrap().values().cloned().collect()
+    }
+
+    /// A copy of the current routes.
+    pub fn router(&self) -> Router {
+        self.router.read().unwrap().clone()
+    }
+
+    /// The group serving `key`.
+    pub fn route(&self, key: &str) -> Option<Raft> {
+        let group = self.router.read().unwrap().route(key)?.group;
+        self.group(group)
+    }
+
+    /// The route of `range`, with the leader of its group known by this process.
+    fn to_route(&self, range: &KeyRange) -> Route {
+        Route {
+            group: range.group,
+            start: range.start.clone(),
+            end: range.end.clone(),
+            leader: self
+                .group(range.group)
+                .and_then(|raft| raft.leader_addr())
+                .unwrap_or_default(),
+            version: range.version,
+        }
+    }
+
+    /// The current route of `group`.
+    fn route_of(&self, group: u64) -> Option<Route> {
+        let range = self.router.read().unwrap().get(group).cloned()?;
+        Some(self.to_route(&range))
+    }
+
+    /// Serve the raft, kv and admin services of every group on `addr` until `signal` completes,
+    /// the requests in progress are finished first.
+    pub async fn serve(
+        instance: MultiRaft,
+        addr: SocketAddr,
+        signal: impl Future<Output = ()>,
+    ) -> Result<(), tonic::transport::Error> {
+        let reflection_service = tonic_reflection::server::Builder::configure()
+            .register_encoded_file_descriptor_set(FILE_DESCRIPTOR_SET)
+            .build()
+            .unwrap();
+        info!("raft {} listening on {} for {} groups", instance.id, addr, instance.groups().len());
+        Server::builder()
+            .add_service(AdminServer::new(instance.clone()))
+            .add_service(KvServer::new(instance.clone()))
+            .add_service(RaftServer::new(instance))
+            .add_service(reflection_service)
+            .serve_with_shutdown(addr, signal)
+            .await
+    }
+
+    /// Serve the Prometheus metrics of every group at `/metrics` on `addr` until `signal` completes.
+    pub async fn serve_metrics(
+        instance: MultiRaft,
+        addr: SocketAddr,
+        signal: impl Future<Output = ()>,
+    ) -> Result<(), hyper::Error> {
+        info!("raft {} serving metrics on {}", instance.id, addr);
+        let gather = move || {
+            let telemetries: Vec<Telemetry> = instance.groups().iter().map(|raft| raft.telemetry().clone()).collect();
+            Telemetry::gather_all(&telemetries)
+        };
+        telemetry::serve(addr, signal, gather).await
+    }
+
+    /// Shut every group down, see `Raft::shutdown`.
+    pub async fn shutdown(&self) -> io::Result<()> {
+        for raft in self.groups() {
+            raft.shutdown().await?;
+        }
+        Ok(())
+    }
+}
+
+/// Reject a kv request on a key no group of this process serves.
+fn not_routed(key: &str) -> Status {
+    Status::not_found(format!("no group serves the key {:?}", key))
+}
+
+#[tonic::async_trait]
+impl RaftTrait for MultiRaft {
+    async fn elect(&self, request: Request<ElectRequest>) -> Result<Response<ElectResponse>, Status> {
+        let group = request.get_ref().group;
+        let raft = self.group(group).ok_or_else(|| not_member(self.id, group))?;
+        RaftTrait::elect(&raft, request).await
+    }
+
+    async fn pre_vote(&self, request: Request<ElectRequest>) -> Result<Response<ElectResponse>, Status> {
+        let group = request.get_ref().group;
+        let raft = self.group(group).ok_or_else(|| not_member(self.id, group))?;
+        RaftTrait::pre_vote(&raft, request).await
+    }
+
+    async fn append_log(&self, request: Request<AppendLogRequest>) -> Result<Response<AppendLogResponse>, Status> {
+        let group = request.get_ref().group;
+        let raft = self.group(group).ok_or_else(|| not_member(self.id, group))?;
+        RaftTrait::append_log(&raft, request).await
+    }
+
+    async fn install_snapshot(
+        &self,
+        request: Request<Streaming<InstallSnapshotRequest>>,
+    ) -> Result<Response<InstallSnapshotResponse>, Status> {
+        let ctx = TraceContext::extract(&request);
+        let chunks = read_chunks(request.into_inner()).await?;
+        let group = chunks.first().map(|chunk| chunk.group).unwrap_or_default();
+        let raft = self.group(group).ok_or_else(|| not_member(self.id, group))?;
+        raft.receive_snapshot(chunks, ctx).await.map(Response::new)
+    }
+
+    async fn timeout_now(&self, request: Request<TimeoutNowRequest>) -> Result<Response<TimeoutNowResponse>, Status> {
+        let group = request.get_ref().group;
+        let raft = self.group(group).ok_or_else(|| not_member(self.id, group))?;
+        RaftTrait::timeout_now(&raft, request).await
+    }
+
+    async fn read_index(&self, request: Request<ReadIndexRequest>) -> Result<Response<ReadIndexResponse>, Status> {
+        let group = request.get_ref().group;
+        let raft = self.group(group).ok_or_else(|| not_member(self.id, group))?;
+        RaftTrait::read_index(&raft, request).await
+    }
+
+    /// Hand every heartbeat to its group at once, the groups handle them concurrently.
+    async fn heartbeat(&self, request: Request<HeartbeatRequest>) -> Result<Response<HeartbeatResponse>, Status> {
+        let handles: Vec<_> = request
+            .into_inner()
+            .heartbeats
+            .into_iter()
+            .map(|req| {
+                let raft = self.group(req.group).ok_or_else(|| not_member(self.id, req.group));
+                tokio::spawn(async move { RaftTrait::append_log(&raft?, Request::new(req)).await })
+            })
+            .collect();
+        let mut results = Vec::with_capacity(handles.len());
+        for handle in handles {
+            let result = match handle.await {
+                Ok(resp) => resp.into(),
+                Err(e) => HeartbeatResult {
+                    response: None,
+                    error: e.to_string(),
+                },
+            };
+            results.push(result);
+        }
+        Ok(Response::new(HeartbeatResponse { results }))
+    }
+}
+
+#[tonic::async_trait]
+impl Kv for MultiRaft {
+    async fn put(&self, request: Request<PutRequest>) -> Result<Response<PutResponse>, Status> {
+        let key = &request.get_ref().key;
+        let raft = self.route(key).ok_or_else(|| not_routed(key))?;
+        Kv::put(&raft, request).await
+    }
+
+    async fn get(&self, request: Request<GetRequest>) -> Result<Response<GetResponse>, Status> {
+        let key = &request.get_ref().key;
+        let raft = self.route(key).ok_or_else(|| not_routed(key))?;
+        Kv::get(&raft, request).await
+    }
+
+    async fn delete(&self, request: Request<DeleteRequest>) -> Result<Response<DeleteResponse>, Status> {
+        let key = &request.get_ref().key;
+        let raft = self.route(key).ok_or_else(|| not_routed(key))?;
+        Kv::delete(&raft, request).await
+    }
+
+    async fn compare_and_swap(
+        &self,
+        request: Request<CompareAndSwapRequest>,
+    ) -> Result<Response<CompareAndSwapResponse>, Status> {
+        let key = &request.get_ref().key;
+        let raft = self.route(key).ok_or_else(|| not_routed(key))?;
+        Kv::compare_and_swap(&raft, request).await
+    }
+
+    async fn routes(&self, _: Request<RoutesRequest>) -> Result<Response<RoutesResponse>, Status> {
+        let router = self.router();
+        Ok(Response::new(RoutesResponse {
+            version: router.version(),
+            routes: router.ranges().map(|range| self.to_route(range)).collect(),
+        }))
+    }
+}
+
+#[tonic::async_trait]
+impl Admin for MultiRaft {
+    async fn add_node(&self, request: Request<AddNodeRequest>) -> Result<Response<ChangeMembershipResponse>, Status> {
+        let group = request.get_ref().group;
+        let raft = self.group(group).ok_or_else(|| not_member(self.id, group))?;
+        Admin::add_node(&raft, request).await
+    }
+
+    async fn remove_node(
+        &self,
+        request: Request<RemoveNodeRequest>,
+    ) -> Result<Response<ChangeMembershipResponse>, Status> {
+        let group = request.get_ref().group;
+        let raft = self.group(group).ok_or_else(|| not_member(self.id, group))?;
+        Admin::remove_node(&raft, request).await
+    }
+
+    async fn transfer_leader(
+        &self,
+        request: Request<TransferLeaderRequest>,
+    ) -> Result<Response<TransferLeaderResponse>, Status> {
+        let group = request.get_ref().group;
+        let raft = self.group(group).ok_or_else(|| not_member(self.id, group))?;
+        Admin::transfer_leader(&raft, request).await
+    }
+
+    async fn cluster_status(
+        &self,
+        request: Request<ClusterStatusRequest>,
+    ) -> Result<Response<ClusterStatusResponse>, Status> {
+        let group = request.get_ref().group;
+        let raft = self.group(group).ok_or_else(|| not_member(self.id, group))?;
+        Admin::cluster_status(&raft, request).await
+    }
+
+    /// Propose the split on the leader of the group, the new group has the members in effect.
+    async fn split_range(&self, request: Request<SplitRangeRequest>) -> Result<Response<SplitRangeResponse>, Status> {
+        let req = request.into_inner();
+        let raft = self.group(req.group).ok_or_else(|| not_member(self.id, req.group))?;
+        if self.group(req.new_group).is_some() || self.router().get(req.new_group).is_some() {
+            return Err(Status::already_exists(format!("group {} already exists", req.new_group)));
+        }
+        let metrics = raft.metrics();
+        if metrics.membership.is_joint() || metrics.membership_log_index > metrics.commit {
+            return Err(Status::failed_precondition("a membership change is in progress"));
+        }
+        let (configs, learners) = metrics.membership.encode();
+        let split = KvCommand::Split {
+            key: (!req.key.is_empty()).then_some(req.key),
+            group: req.new_group,
+            configs,
+            learners,
+        };
+        let output = raft.execute(split).await?;
+        let right: Option<KeyRange> = serde_json::from_str(&output).map_err(|e| Status::internal(e.to_string()))?;
+        if right.is_none() {
+            return Err(Status::failed_precondition(format!("group {} cannot be split there", req.group)));
+        }
+        // the leader has followed the split once it is applied
+        Ok(Response::new(SplitRangeResponse {
+            left: self.route_of(req.group),
+            right: self.route_of(req.new_group),
+        }))
+    }
+
+    /// Freeze the source so that its keys cannot change any more, then propose the merge with them
+    /// on the target. The source takes writes again if the target refuses the merge or surely has not
+    /// committed it, but stays frozen if the merge may have been committed, a retry finishes it then.
+    async fn merge_range(&self, request: Request<MergeRangeRequest>) -> Result<Response<MergeRangeResponse>, Status> {
+        let req = request.into_inner();
+        let target = self.group(req.target).ok_or_else(|| not_member(self.id, req.target))?;
+        let source = self.group(req.source).ok_or_else(|| not_member(self.id, req.source))?;
+        if target.metrics().role != Role::Leader || source.metrics().role != Role::Leader {
+            let msg = format!("raft {} must lead both group {} and group {}", self.id, req.target, req.source);
+            return Err(Status::failed_precondition(msg));
+        }
+        let router = self.router();
+        let adjacent = match (router.get(req.target), router.get(req.source)) {
+            (Some(target), Some(source)) => target.end == source.start || source.end == target.start,
+            _ => false,
+        };
+        if !adjacent {
+            let msg = format!("the ranges of group {} and group {} are not adjacent", req.target, req.source);
+            return Err(Status::failed_precondition(msg));
+        }
+
+        let output = source.execute(KvCommand::PrepareMerge { target: req.target }).await?;
+        let prepared: Option<KvSnapshot> =
+            serde_json::from_str(&output).map_err(|e| Status::internal(e.to_string()))?;
+        let Some(prepared) = prepared else {
+            return Err(Status::failed_precondition(format!(
+                "group {} is being merged into another group",
+                req.source
+            )));
+        };
+        let commit = KvCommand::CommitMerge {
+            source: prepared.range,
+            data: prepared.data,
+        };
+        let size = commit.encode().len();
+        if size > MAX_MERGE_BYTES {
+            source.execute(KvCommand::CancelMerge).await?;
+            let msg = format!("group {} holds {} bytes, more than a merge carries", req.source, size);
+            return Err(Status::failed_precondition(msg));
+        }
+        let merged = match target.execute(commit).await {
+            Ok(output) => serde_json::from_str(&output).map_err(|e| Status::internal(e.to_string()))?,
+            // the merge is not in the log of the target if it is not the leader any more, or if another
+            // leader overwrote it
+            Err(status) if matches!(status.code(), tonic::Code::Unavailable | tonic::Code::Aborted) => {
+                source.execute(KvCommand::CancelMerge).await?;
+                return Err(status);
+            }
+            // it may still be applied, the source takes no write until a retry finishes the merge
+            Err(status) => {
+                let msg = format!("{}, group {} stays frozen until the merge is retried", status.message(), req.source);
+                return Err(Status::new(status.code(), msg));
+            }
+        };
+        let Some(merged): Option<KeyRange> = merged else {
+            source.execute(KvCommand::CancelMerge).await?;
+            return Err(Status::failed_precondition(format!("group {} refuses the merge", req.target)));
+        };
+        Ok(Response::new(MergeRangeResponse {
+            merged: Some(self.to_route(&merged)),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use core::time;
+
+    use super::*;
+    use crate::node::{SnapshotPolicy, TICK_INTERVAL};
+    use crate::raft::kv_client::KvClient;
+    use crate::state_machine::SplitOff;
+    use crate::test_util::{build_multi_cluster, start_multi_cluster, wait_for, wait_for_group_leader, GROUP_TIMEOUTS};
+
+    fn range(group: u64, start: &str, end: &str) -> KeyRange {
+        KeyRange {
+            group,
+            start: start.to_string(),
+            end: end.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_route_keys() {
+        let mut router = Router::default();
+        router.insert(range(1, "", "g")).unwrap();
+        router.insert(range(2, "m", "")).unwrap();
+        assert_eq!(router.route("").map(|r| r.group), Some(1));
+        assert_eq!(router.route("apple").map(|r| r.group), Some(1));
+        assert_eq!(router.route("g"), None);
+        assert_eq!(router.route("m").map(|r| r.group), Some(2));
+        assert_eq!(router.route("zebra").map(|r| r.group), Some(2));
+
+        assert!(matches!(router.insert(range(3, "f", "h")), Err(RouteError::Overlap(..))));
+        assert!(matches!(router.insert(range(3, "h", "n")), Err(RouteError::Overlap(..))));
+        assert!(matches!(router.insert(range(3, "h", "")), Err(RouteError::Overlap(..))));
+        assert_eq!(router.insert(range(3, "k", "h")), Err(RouteError::EmptyRange(range(3, "k", "h"))));
+        assert_eq!(router.insert(range(2, "g", "h")), Err(RouteError::DuplicateGroup(2)));
+        router.insert(range(3, "g", "m")).unwrap();
+        assert_eq!(router.route("g").map(|r| r.group), Some(3));
+
+        assert_eq!(router.remove(3), Some(range(3, "g", "m")));
+        assert_eq!(router.route("h"), None);
+        let groups: Vec<u64> = router.ranges().map(|r| r.group).collect();
+        assert_eq!(groups, vec![1, 2]);
+
+        // a change already followed is skipped
+        let split = RangeChange::Split {
+            left: KeyRange {
+                version: 1,
+                ..range(2, "m", "t")
+            },
+            right: KeyRange {
+                version: 1,
+                ..range(4, "t", "")
+            },
+            snapshot: Vec::new(),
+            configs: Vec::new(),
+            learners: String::new(),
+        };
+        let version = router.version();
+        assert_eq!(router.apply(&split), Ok(true));
+        assert_eq!(router.route("zebra").map(|r| r.group), Some(4));
+        assert_eq!(router.version(), version + 1);
+        assert_eq!(router.apply(&split), Ok(false));
+        assert_eq!(router.version(), version + 1);
+
+        // a snapshot taken past a merge drops the group merged away
+        let restore = RangeChange::Restore {
+            range: KeyRange {
+                version: 3,
+                ..range(2, "m", "")
+            },
+            split_off: Vec::new(),
+        };
+        assert_eq!(router.apply(&restore), Ok(true));
+        assert_eq!(router.route("zebra").map(|r| r.group), Some(2));
+        assert_eq!(router.get(4), None);
+        // and one taken past a split routes the range split off
+        let split_off = KeyRange {
+            version: 4,
+            ..range(5, "t", "")
+        };
+        let restore = RangeChange::Restore {
+            range: KeyRange {
+                version: 4,
+                ..range(2, "m", "t")
+            },
+            split_off: vec![SplitOff {
+                range: split_off.clone(),
+                ..Default::default()
+            }],
+        };
+        assert_eq!(router.apply(&restore), Ok(true));
+        assert_eq!(router.get(5), Some(&split_off));
+        assert_eq!(router.apply(&restore), Ok(false));
+        // a group not routed yet keeps the range it is added with
+        let restore = RangeChange::Restore {
+            range: range(9, "a", "b"),
+            split_off: Vec::new(),
+        };
+        assert_eq!(router.apply(&restore), Ok(false));
+    }
+
+    #[tokio::test]
+    async fn test_groups_share_a_process() {
+        let addrs = ["127.0.0.1:19261", "127.0.0.1:19262", "127.0.0.1:19263"];
+        let nodes = start_multi_cluster(&addrs, &[range(1, "", "m"), range(2, "m", "")]);
+
+        // every group elects a leader of its own
+        for (key, group) in [("apple", 1), ("zebra", 2)] {
+            let addr = addrs[wait_for_group_leader(&nodes, group).await];
+            let mut client = KvClient::connect(format!("http://{}", addr)).await.unwrap();
+            let put = PutRequest {
+                key: key.to_string(),
+                value: "v".to_string(),
+            };
+            client.put(put).await.unwrap();
+            tokio::time::sleep(time::Duration::from_millis(300)).await;
+            // the key is replicated in its group only
+            for node in nodes.iter() {
+                for raft in node.groups() {
+                    let expected = if raft.group == group { "\"v\"" } else { "" };
+                    assert_eq!(raft.query(key).await.unwrap(), expected);
+                }
+            }
+        }
+
+        // the coalesced heartbeats keep the followers of both groups from campaigning
+        let terms = |nodes: &[MultiRaft]| -> Vec<u64> {
+            let groups = nodes.iter().flat_map(|node| node.groups());
+            groups.map(|raft| raft.metrics().term).collect()
+        };
+        let before = terms(&nodes);
+        tokio::time::sleep(time::Duration::from_millis(GROUP_TIMEOUTS.election.1 * 2)).await;
+        assert_eq!(terms(&nodes), before);
+
+        // an rpc of a group the process does not host is rejected
+        let request = Request::new(ElectRequest {
+            group: 3,
+            ..Default::default()
+        });
+        let status = RaftTrait::elect(&nodes[0], request).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::NotFound);
+    }
+
+    #[tokio::test]
+    async fn test_split_and_merge_ranges() {
+        let addrs = ["127.0.0.1:19291", "127.0.0.1:19292", "127.0.0.1:19293"];
+        let nodes = start_multi_cluster(&addrs, &[KeyRange::full(1)]);
+
+        let leader1 = wait_for_group_leader(&nodes, 1).await;
+        let mut client = KvClient::connect(format!("http://{}", addrs[leader1])).await.unwrap();
+        for key in ["a", "b", "c", "d", "e", "f"] {
+            let put = PutRequest {
+                key: key.to_string(),
+                value: "v".to_string(),
+            };
+            client.put(put).await.unwrap();
+        }
+
+        // the upper half of the keys moves to the new group on every node
+        let request = Request::new(SplitRangeRequest {
+            group: 1,
+            new_group: 2,
+            ..Default::default()
+        });
+        let resp = Admin::split_range(&nodes[leader1], request).await.unwrap().into_inner();
+        assert_eq!((resp.left.unwrap().end, resp.right.unwrap().start), ("d".to_string(), "d".to_string()));
+        wait_for(&nodes, |nodes| nodes.iter().all(|node| node.group(2).is_some()).then_some(())).await;
+        for node in nodes.iter() {
+            assert_eq!(node.route("e").unwrap().group, 2);
+            assert_eq!(node.group(2).unwrap().query("e").await.unwrap(), "\"v\"");
+            assert_eq!(node.group(1).unwrap().query("e").await.unwrap(), "");
+        }
+        // a write reaching the old group is told to look up the routes again
+        let put = PutRequest {
+            key: "e".to_string(),
+            value: "w".to_string(),
+        };
+        let status = Kv::put(&nodes[leader1].group(1).unwrap(), Request::new(put.clone()))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::OutOfRange);
+        let routes = Kv::routes(&nodes[leader1], Request::new(RoutesRequest {}))
+            .await
+            .unwrap()
+            .into_inner();
+        let groups: Vec<(u64, u64)> = routes.routes.iter().map(|route| (route.group, route.version)).collect();
+        assert_eq!(groups, vec![(1, 1), (2, 1)]);
+        let leader2 = wait_for_group_leader(&nodes, 2).await;
+        let mut client = KvClient::connect(format!("http://{}", addrs[leader2])).await.unwrap();
+        client.put(put).await.unwrap();
+
+        // the source is merged back on the node leading both groups
+        if leader2 != leader1 {
+            assert!(nodes[leader2].group(2).unwrap().transfer_leader(leader1 as u64).await);
+        }
+        // a merge that timed out leaves the source frozen, and a retry finishes it
+        let source = nodes[leader1].group(2).unwrap();
+        source.execute(KvCommand::PrepareMerge { target: 1 }).await.unwrap();
+        let put = PutRequest {
+            key: "f".to_string(),
+            value: "w".to_string(),
+        };
+        let status = Kv::put(&source, Request::new(put)).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::OutOfRange);
+        let request = Request::new(MergeRangeRequest { target: 1, source: 2 });
+        let resp = Admin::merge_range(&nodes[leader1], request).await.unwrap().into_inner();
+        let merged = resp.merged.unwrap();
+        assert_eq!((merged.start.as_str(), merged.end.as_str(), merged.version), ("", "", 2));
+        wait_for(&nodes, |nodes| nodes.iter().all(|node| node.group(2).is_none()).then_some(())).await;
+        tokio::time::sleep(time::Duration::from_millis(100)).await;
+        for node in nodes.iter() {
+            assert_eq!(node.route("e").unwrap().group, 1);
+            assert_eq!(node.group(1).unwrap().query("e").await.unwrap(), "\"w\"");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_replica_restored_past_a_split() {
+        let addrs = ["127.0.0.1:19341", "127.0.0.1:19342", "127.0.0.1:19343"];
+        let policy = SnapshotPolicy {
+            max_entries: 3,
+            ..Default::default()
+        };
+        let nodes = build_multi_cluster(&addrs, &[KeyRange::full(1)], |raft| raft.with_snapshot_policy(policy));
+        let mut stops = Vec::new();
+        for (node, addr) in nodes.iter().zip(addrs) {
+            let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+            let signal = async move {
+                let _ = stopped.await;
+            };
+            tokio::spawn(MultiRaft::serve(node.clone(), addr.parse().unwrap(), signal));
+            stops.push(Some(stop));
+        }
+        let leader1 = wait_for_group_leader(&nodes, 1).await;
+        let mut client = KvClient::connect(format!("http://{}", addrs[leader1])).await.unwrap();
+        let put = |key: &str| PutRequest {
+            key: key.to_string(),
+            value: "v".to_string(),
+        };
+        for key in ["a", "e"] {
+            client.put(put(key)).await.unwrap();
+        }
+
+        // a follower misses the split, and the log of the split is compacted meanwhile
+        let lagging = (leader1 + 1) % addrs.len();
+        stops[lagging].take().unwrap().send(()).unwrap();
+        tokio::time::sleep(time::Duration::from_millis(100)).await;
+        let request = Request::new(SplitRangeRequest {
+            group: 1,
+            new_group: 2,
+            key: "d".to_string(),
+        });
+        Admin::split_range(&nodes[leader1], request).await.unwrap();
+        for key in ["a", "b", "c", "a", "b", "c"] {
+            client.put(put(key)).await.unwrap();
+        }
+        assert!(nodes[leader1].group(1).unwrap().metrics().snapshot_last.index > 0);
+        assert!(nodes[lagging].group(2).is_none());
+
+        // it follows the split once it restores a snapshot of the group, and the new group sends it the keys
+        let addr = addrs[lagging].parse().unwrap();
+        tokio::spawn(MultiRaft::serve(nodes[lagging].clone(), addr, std::future::pending()));
+        let raft = wait_for(&nodes, |nodes| nodes[lagging].group(2)).await;
+        assert_eq!(nodes[lagging].route("e").unwrap().group, 2);
+        assert_eq!(nodes[lagging].router().get(1).unwrap().end, "d");
+        let caught_up = async {
+            while raft.query("e").await.unwrap() != "\"v\"" {
+                tokio::time::sleep(time::Duration::from_millis(TICK_INTERVAL)).await;
+            }
+        };
+        tokio::time::timeout(time::Duration::from_millis(GROUP_TIMEOUTS.election.1 * 5), caught_up)
+            .await
+            .expect("the keys of the new group do not reach the lagging replica in time");
+    }
+
+    #[tokio::test]
+    async fn test_refuse_oversized_merge() {
+        let nodes = start_multi_cluster(&["127.0.0.1:19351"], &[range(1, "", "m"), range(2, "m", "")]);
+        let source = wait_for(&nodes, |nodes| {
+            let source = nodes[0].group(2)?;
+            (source.metrics().role == Role::Leader).then_some(source)
+        })
+        .await;
+        wait_for_group_leader(&nodes, 1).await;
+        let value = "v".repeat(MAX_MERGE_BYTES / 4);
+        for key in ["n", "o", "p", "q", "r"] {
+            let put = PutRequest {
+                key: key.to_string(),
+                value: value.clone(),
+            };
+            Kv::put(&source, Request::new(put)).await.unwrap();
+        }
+
+        // the keys would not fit in a log entry of the target, the source takes writes again
+        let request = Request::new(MergeRangeRequest { target: 1, source: 2 });
+        let status = Admin::merge_range(&nodes[0], request).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::FailedPrecondition);
+        assert!(status.message().contains("bytes"), "{}", status.message());
+        assert_eq!(nodes[0].route("n").unwrap().group, 2);
+        let put = PutRequest {
+            key: "n".to_string(),
+            value: "w".to_string(),
+        };
+        Kv::put(&source, Request::new(put)).await.unwrap();
+    }
+}
@@ -1,176 +1,1157 @@
-use core::time;
 use std::{
+    cmp::Ordering,
     collections::{BTreeMap, BTreeSet},
+    future::{self, Future},
+    io,
+    net::SocketAddr,
     result::Result,
-    sync::{Arc, RwLock},
+    sync::{Arc, Mutex},
 };
 
 use derivative::Derivative;
 use derive_new::new as New;
-use tonic::{transport::Server, Request, Response, Status};
+use prost::Message;
+use rand::{rngs::StdRng, SeedableRng};
+use tokio::sync::{mpsc, oneshot, watch};
+use tonic::{transport::Server, Request, Response, Status, Streaming};
+use tracing::{debug_span, error, field::Empty, info, info_span, Instrument, Span};
 
-use self::{raft_server::Raft as RaftTrait, raft_server::RaftServer};
+use self::{admin_server::AdminServer, kv_server::KvServer, raft_server::Raft as RaftTrait, raft_server::RaftServer};
+use crate::actor::{Core, Envelope, Event};
+use crate::clock::Clock;
+use crate::membership::{ChangeError, Membership};
 use crate::raft::*;
+use crate::state_machine::{KvStateMachine, StateMachine};
+use crate::storage::{MemStorage, Storage};
+use crate::telemetry::Telemetry;
+use crate::trace::TraceContext;
+use crate::transport::Transport;
 
 pub const FILE_DESCRIPTOR_SET: &[u8] = tonic::include_file_descriptor_set!("raft_descriptor");
 
+/// the interval of the scheduler loop, in milliseconds
+pub const TICK_INTERVAL: u64 = 50;
+/// the interval the leader sends heartbeats at, in milliseconds
+pub const HEARTBEAT_INTERVAL: u64 = 100;
+/// the max number of log entries carried by one append log rpc by default
+pub const MAX_ENTRIES_PER_RPC: usize = 64;
+/// the election timeout is picked randomly in `[min, max)` milliseconds for every round,
+/// so that followers rarely time out at the same moment and split the votes
+pub const ELECTION_TIMEOUT: (u64, u64) = (1000, 2000);
+/// the deadline of a single rpc to a peer, in milliseconds
+pub const RPC_TIMEOUT: u64 = 500;
+/// the size of a chunk of an install snapshot stream, in bytes
+pub const SNAPSHOT_CHUNK_SIZE: usize = 64 * 1024;
+
+/// When to take a snapshot of the state machine and compact the log entries it covers.
+/// A snapshot is taken once either of the limits is reached.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SnapshotPolicy {
+    /// the number of entries applied since the last snapshot
+    pub max_entries: u64,
+    /// the encoded size of the entries applied since the last snapshot, in bytes
+    pub max_bytes: u64,
+}
+
+impl Default for SnapshotPolicy {
+    fn default() -> Self {
+        SnapshotPolicy {
+            max_entries: 10000,
+            max_bytes: 64 * 1024 * 1024,
+        }
+    }
+}
+
+/// How the leader appends proposals and replicates the log to the followers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReplicationPolicy {
+    /// the max number of append log rpcs in flight to a follower. With 1, the next rpc is only sent
+    /// once the previous one is responded, otherwise the leader sends the following entries right away
+    pub max_inflight: usize,
+    /// the max number of log entries carried by one append log rpc
+    pub max_entries: usize,
+    /// the max encoded size of the entries carried by one append log rpc, in bytes.
+    /// An rpc carries at least one entry whatever its size.
+    pub max_bytes: u64,
+    /// the max encoded size of the entries in flight to a follower, in bytes
+    pub max_inflight_bytes: u64,
+    /// the max number of queued requests the leader handles before replicating, the proposals among
+    /// them are appended to the log together and go out in the same rpcs
+    pub max_batch: usize,
+}
+
+impl Default for ReplicationPolicy {
+    fn default() -> Self {
+        ReplicationPolicy {
+            max_inflight: 1,
+            max_entries: MAX_ENTRIES_PER_RPC,
+            max_bytes: 1024 * 1024,
+            max_inflight_bytes: 8 * 1024 * 1024,
+            max_batch: 256,
+        }
+    }
+}
+
+/// The timers of a raft instance, in milliseconds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Timeouts {
+    /// the election timeout is picked randomly in `[min, max)` for every round
+    pub election: (u64, u64),
+    /// the interval the leader sends heartbeats at, it must be well below the minimum election timeout
+    pub heartbeat: u64,
+}
+
+impl Timeouts {
+    /// How long a leader serves lease reads after a majority acknowledged it, in milliseconds.
+    /// It is shorter than the minimum election timeout to tolerate a bounded clock drift between nodes,
+    /// as followers hearing from the leader do not vote for anyone else within the election timeout.
+    pub fn lease(&self) -> u64 {
+        self.election.0 * 9 / 10
+    }
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Timeouts {
+            election: ELECTION_TIMEOUT,
+            heartbeat: HEARTBEAT_INTERVAL,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Role {
+    #[default]
+    Follower,
+    /// asking for pre-votes, the term is only increased once a majority would grant the vote
+    PreCandidate,
+    Candidate,
+    Leader,
+}
+
 #[derive(Debug, New)]
 pub struct Leading {
-    granted_by: BTreeSet<u64>,
-    progresses: BTreeMap<u64, Progress>,
-    log_index_range: (u64, u64),
+    /// the voters that granted the votes of this term, the learners never count
+    pub(crate) granted_by: BTreeSet<u64>,
+    pub(crate) progresses: BTreeMap<u64, Progress>,
+    /// the log indexes `[start, end)` appended by this leader in its own term
+    pub(crate) log_index_range: (u64, u64),
 }
 
-#[derive(Debug, Clone, Derivative, PartialEq, New)]
-#[derivative(Default)]
+#[derive(Debug, Clone, Default, PartialEq, New)]
 pub struct Progress {
-    acked: LogId,
-    len: u64,
-    /// It is a token to indicate if it can send an RPC, e.g., there is no inflight RPC sending.
-    /// It is set to `None` when an RPC is sent, and set to `Some(())` when the RPC is finished.
-    #[derivative(Default(value = "Some(())"))]
-    ready: Option<()>,
+    /// the last log id the follower has confirmed to be identical to the leader's
+    pub(crate) acked: LogId,
+    /// the log length the leader assumes the follower has once the rpcs in flight succeed,
+    /// the next rpc sends entries from `len + 1`
+    pub(crate) len: u64,
+    /// when the latest rpc the follower responded to in the leader's term was sent, in milliseconds
+    pub(crate) acked_at: u128,
+    /// the rpcs in flight keyed by their sequence number. It is cleared when the leader starts over
+    /// from what is acked, the responses of the rpcs sent before then only tell what the follower has.
+    #[new(default)]
+    pub(crate) inflight: BTreeMap<u64, Inflight>,
+    /// the sequence number of the next rpc
+    #[new(default)]
+    pub(crate) next_seq: u64,
 }
 
-#[derive(Debug, Default)]
+/// An rpc sent to a follower and not responded yet.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Inflight {
+    /// the encoded size of the entries it carries, in bytes
+    pub(crate) bytes: u64,
+    /// nothing else is sent along with a snapshot
+    pub(crate) snapshot: bool,
+}
+
+impl Progress {
+    /// Whether the window of rpcs in flight is full.
+    pub(crate) fn is_paused(&self, policy: &ReplicationPolicy) -> bool {
+        let bytes: u64 = self.inflight.values().map(|inflight| inflight.bytes).sum();
+        self.inflight.len() >= policy.max_inflight
+            || bytes >= policy.max_inflight_bytes
+            || self.inflight.values().any(|inflight| inflight.snapshot)
+    }
+
+    /// Record an rpc sent, returns its sequence number.
+    pub(crate) fn send(&mut self, inflight: Inflight) -> u64 {
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        self.inflight.insert(seq, inflight);
+        seq
+    }
+}
+
+#[derive(Debug)]
 pub struct Store {
     /// the raft instance id
     id: u32,
     /// the candidate term id
-    term: u64,
-    /// the server voted leader_id for in current term
-    voted_for: u32,
-    /// log entries configs, just for membership
-    configs: BTreeMap<u64, Vec<BTreeSet<u64>>>,
-    /// log entries
-    logs: Vec<Log>,
+    pub(crate) term: u64,
+    /// the server voted leader_id for in current term, `None` if it has not voted yet
+    pub(crate) voted_for: Option<u32>,
+    /// the membership changes in the log keyed by log index, the last one is in effect
+    /// even before it is committed
+    pub(crate) configs: BTreeMap<u64, Membership>,
+    /// the latest snapshot, the log entries it covers are compacted
+    pub(crate) snapshot: Snapshot,
+    /// log entries after the snapshot
+    pub(crate) logs: Vec<Log>,
+    /// the durable backend every change of the state above is written through
+    backend: Box<dyn Storage>,
 }
 
 impl Store {
+    /// Create a store which keeps everything in memory only.
     pub fn new(id: u32) -> Self {
-        Store {
+        Self::open(id, Box::new(MemStorage)).unwrap()
+    }
+
+    /// Open a store on `backend`, recovering the state persisted in it.
+    pub fn open(id: u32, mut backend: Box<dyn Storage>) -> io::Result<Self> {
+        let (hs, snapshot, mut logs) = backend.load()?;
+        let snapshot = snapshot.unwrap_or_default();
+        let snapshot_last = snapshot.last_log_id.clone().unwrap_or_default();
+        // drop the entries covered by the snapshot, or the whole log if it disagrees with the
+        // snapshot, which happens if a crash interrupted installing a snapshot from the leader
+        match logs.iter().position(|log| log.id.as_ref() == Some(&snapshot_last)) {
+            Some(pos) => {
+                logs.drain(..=pos);
+            }
+            None if logs
+                .first()
+                .is_some_and(|log| log.id.as_ref().unwrap().index <= snapshot_last.index) =>
+            {
+                logs.clear();
+                backend.truncate(0)?;
+            }
+            None => {}
+        }
+        let mut configs = BTreeMap::new();
+        if let Some(membership) = Membership::decode(&snapshot.configs, &snapshot.learners) {
+            configs.insert(snapshot_last.index, membership);
+        }
+        for log in logs.iter() {
+            if let Some(membership) = Membership::decode(&log.configs, &log.learners) {
+                configs.insert(log.id.as_ref().unwrap().index, membership);
+            }
+        }
+        Ok(Store {
             id,
-            term: 0,
-            voted_for: 0,
-            configs: BTreeMap::new(),
-            logs: Vec::new(),
+            term: hs.term,
+            voted_for: hs.voted_for,
+            configs,
+            snapshot,
+            logs,
+            backend,
+        })
+    }
+
+    /// The membership in effect and the index of the log entry it comes from.
+    pub fn membership(&self) -> (u64, Membership) {
+        match self.configs.last_key_value() {
+            Some((index, membership)) => (*index, membership.clone()),
+            None => (0, Membership::default()),
+        }
+    }
+
+    /// Record a membership change carried by the log entry at `index`, if any.
+    fn add_config(&mut self, index: u64, log: &Log) {
+        if let Some(membership) = Membership::decode(&log.configs, &log.learners) {
+            self.configs.insert(index, membership);
         }
     }
 
+    pub fn term(&self) -> u64 {
+        self.term
+    }
+
+    /// The last log id covered by the snapshot.
+    pub fn snapshot_last(&self) -> LogId {
+        self.snapshot.last_log_id.clone().unwrap_or_default()
+    }
+
     pub fn get_last(&self) -> LogId {
         let last = self.logs.last();
         match last {
             Some(log) => log.id.clone().unwrap(),
-            None => LogId::default(),
+            None => self.snapshot_last(),
+        }
+    }
+
+    /// Get the log id at `index`, index 0 is the empty head of the log.
+    /// It is `None` for entries not in the log or compacted, except the last one of the snapshot.
+    pub fn get_log_id(&self, index: u64) -> Option<LogId> {
+        let snapshot_last = self.snapshot_last();
+        if index <= snapshot_last.index {
+            return (index == snapshot_last.index).then_some(snapshot_last);
+        }
+        let offset = index - snapshot_last.index - 1;
+        self.logs.get(offset as usize).map(|log| log.id.clone().unwrap())
+    }
+
+    /// Get at most `max` log entries starting from `index`, compacted entries are skipped.
+    pub fn get_logs(&self, index: u64, max: usize) -> Vec<Log> {
+        self.get_logs_within(index, max, u64::MAX)
+    }
+
+    /// Get at most `max` log entries starting from `index` whose encoded size is within `max_bytes`,
+    /// but at least one entry if there is any.
+    pub fn get_logs_within(&self, index: u64, max: usize, max_bytes: u64) -> Vec<Log> {
+        let first = self.snapshot_last().index + 1;
+        let start = (index.max(first) - first) as usize;
+        let mut bytes = 0;
+        let mut logs = Vec::new();
+        for log in self.logs.iter().skip(start).take(max) {
+            bytes += log.encoded_len() as u64;
+            if bytes > max_bytes && !logs.is_empty() {
+                break;
+            }
+            logs.push(log.clone());
         }
+        logs
+    }
+
+    /// The encoded size of the entries up to `index`, in bytes.
+    pub fn logs_size(&self, index: u64) -> u64 {
+        let count = index.saturating_sub(self.snapshot_last().index) as usize;
+        self.logs.iter().take(count).map(|log| log.encoded_len() as u64).sum()
+    }
+
+    /// Append `logs` right after `prev_log_id`, following the log matching rule of raft.
+    ///
+    /// Entries already present are skipped, and a conflicting suffix is truncated before the new
+    /// entries are appended. It returns the index of the last entry covered by the request, or
+    /// the log id the leader should retry from when `prev_log_id` is not in the local log.
+    pub fn append_logs(&mut self, prev_log_id: &LogId, mut logs: Vec<Log>) -> Result<u64, LogId> {
+        let last = self.get_last();
+        if prev_log_id.index > last.index {
+            return Err(last);
+        }
+        // entries covered by the snapshot are committed, so they must match the leader's
+        let snapshot_last = self.snapshot_last();
+        let mut prev_log_id = prev_log_id.clone();
+        if prev_log_id.index < snapshot_last.index {
+            let covered = (snapshot_last.index - prev_log_id.index) as usize;
+            if logs.len() <= covered {
+                return Ok(prev_log_id.index + logs.len() as u64);
+            }
+            logs.drain(..covered);
+            prev_log_id = snapshot_last.clone();
+        }
+
+        let local = self.get_log_id(prev_log_id.index).unwrap();
+        if local.term != prev_log_id.term {
+            // skip the whole conflicting term at once instead of one entry per round trip
+            let first = self
+                .logs
+                .iter()
+                .map(|log| log.id.as_ref().unwrap())
+                .find(|id| id.term == local.term)
+                .map_or(prev_log_id.index, |id| id.index);
+            return Err(self.get_log_id(first - 1).unwrap_or(snapshot_last));
+        }
+
+        let mut index = prev_log_id.index;
+        let mut new_logs = Vec::new();
+        for log in logs {
+            index += 1;
+            let term = log.id.as_ref().unwrap().term;
+            match self.get_log_id(index) {
+                Some(id) if id.term == term && new_logs.is_empty() => continue,
+                Some(_) if new_logs.is_empty() => {
+                    self.logs.truncate((index - snapshot_last.index - 1) as usize);
+                    persist(self.id, self.backend.truncate(index));
+                    // the membership falls back to the last one left in the log
+                    self.configs.retain(|config_index, _| *config_index < index);
+                }
+                _ => {}
+            }
+            self.add_config(index, &log);
+            new_logs.push(log);
+        }
+        persist(self.id, self.backend.append(&new_logs));
+        self.logs.append(&mut new_logs);
+        Ok(index)
+    }
+
+    /// Append a single entry to the end of the log.
+    pub fn append_log(&mut self, log: Log) {
+        self.append_batch(vec![log]);
+    }
+
+    /// Append `logs` to the end of the log with a single write to the backend.
+    pub fn append_batch(&mut self, mut logs: Vec<Log>) {
+        for log in logs.iter() {
+            self.add_config(log.id.as_ref().unwrap().index, log);
+        }
+        persist(self.id, self.backend.append(&logs));
+        self.logs.append(&mut logs);
+    }
+
+    /// Replace the snapshot with a newer one taken locally, and compact the log entries it covers.
+    /// The snapshot keeps the membership in effect at its last entry.
+    pub fn compact(&mut self, mut snapshot: Snapshot) {
+        let last = snapshot.last_log_id.clone().unwrap_or_default();
+        let count = last.index.saturating_sub(self.snapshot_last().index) as usize;
+        if let Some((index, membership)) = self.configs.range(..=last.index).next_back() {
+            (snapshot.configs, snapshot.learners) = membership.encode();
+            let index = *index;
+            self.configs.retain(|config_index, _| *config_index >= index);
+        }
+        persist(self.id, self.backend.save_snapshot(&snapshot));
+        persist(self.id, self.backend.compact(last.index));
+        self.logs.drain(..count.min(self.logs.len()));
+        self.snapshot = snapshot;
+    }
+
+    /// Replace the snapshot with one sent by the leader. The log entries after the snapshot are
+    /// kept if the log agrees with it, otherwise the whole log is discarded.
+    pub fn install_snapshot(&mut self, snapshot: Snapshot) {
+        let last = snapshot.last_log_id.clone().unwrap_or_default();
+        if self.get_log_id(last.index) == Some(last.clone()) {
+            self.compact(snapshot);
+            return;
+        }
+        persist(self.id, self.backend.save_snapshot(&snapshot));
+        persist(self.id, self.backend.truncate(0));
+        self.logs.clear();
+        self.configs.clear();
+        if let Some(membership) = Membership::decode(&snapshot.configs, &snapshot.learners) {
+            self.configs.insert(last.index, membership);
+        }
+        self.snapshot = snapshot;
+    }
+
+    /// Flush the backend, the store is left as it is and may still be written.
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.backend.sync()
+    }
+
+    /// Record the vote of the current term.
+    pub fn vote_for(&mut self, id: u32) {
+        self.voted_for = Some(id);
+        self.save_hard_state();
+    }
+
+    fn save_hard_state(&mut self) {
+        let hs = HardState {
+            term: self.term,
+            voted_for: self.voted_for,
+        };
+        persist(self.id, self.backend.save_hard_state(&hs));
+    }
+
+    /// Move to a newer `term` and forget the vote cast in the previous one.
+    /// Returns `true` if the local term was behind.
+    pub fn update_term(&mut self, term: u64) -> bool {
+        if term <= self.term {
+            return false;
+        }
+        self.term = term;
+        self.voted_for = None;
+        self.save_hard_state();
+        true
     }
 }
 
-#[derive(Debug, Clone)]
+/// A store that cannot persist its state must not go on, otherwise it may forget
+/// a vote or an acknowledged entry.
+fn persist<T>(id: u32, res: io::Result<T>) -> T {
+    res.unwrap_or_else(|e| panic!("raft {} failed to persist its state: {}", id, e))
+}
+
+impl PartialOrd for LogId {
+    /// Log ids are ordered by term first and then by index, which is how raft decides
+    /// whose log is more up-to-date.
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some((self.term, self.index).cmp(&(other.term, other.index)))
+    }
+}
+
+/// A view of the state of a raft instance, published by its core after every event.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RaftMetrics {
+    pub id: u32,
+    pub role: Role,
+    pub term: u64,
+    pub voted_for: Option<u32>,
+    pub leader_id: Option<u32>,
+    pub last_log_id: LogId,
+    /// the last log id covered by the snapshot
+    pub snapshot_last: LogId,
+    pub commit: u64,
+    /// the last log id applied to the state machine
+    pub applied: LogId,
+    /// the membership in effect and the index of the log entry it comes from
+    pub membership: Membership,
+    pub membership_log_index: u64,
+    /// the addresses of the peers keyed by raft instance id
+    pub peers: BTreeMap<u64, String>,
+    /// the last log id each follower has confirmed, only on the leader
+    pub matched: BTreeMap<u64, LogId>,
+}
+
+/// a core waiting for the scheduler, with the receiver of its events
+type Unstarted = (Core, mpsc::UnboundedReceiver<Envelope>);
+
+/// A handle of a raft instance. The state lives in a single core task started by `scheduler`,
+/// the handle and the rpc handlers only send it events and wait for the replies.
+#[derive(Derivative, Clone)]
+#[derivative(Debug)]
 pub struct Raft {
     /// raft instance id
     pub id: u32,
-    /// raft peers process info, for leader
-    pub leading: Arc<RwLock<Option<Leading>>>,
-    /// raft instance committed log index
-    pub commit: Arc<RwLock<u64>>,
-    /// raft peers client
-    pub peers: Arc<RwLock<Vec<String>>>,
-    /// raft instance backend storage impl
-    pub sto: Arc<RwLock<Store>>,
-    /// last time heart beat timestamp
-    pub last_hb: Arc<RwLock<u128>>,
+    /// the raft group of this instance, 0 unless it is hosted by a `MultiRaft`
+    pub group: u64,
+    tx: mpsc::UnboundedSender<Envelope>,
+    metrics: watch::Receiver<RaftMetrics>,
+    telemetry: Telemetry,
+    /// the core until the scheduler takes it over
+    #[derivative(Debug = "ignore")]
+    core: Arc<Mutex<Option<Unstarted>>>,
 }
 
 impl Raft {
     /// id is the raft instance id
     /// peers is a string of peer addresses, separated by commas, like '127.0.0.1:8080,127.0.0.1:8081'
     pub fn new(id: u32, peers: String) -> Self {
-        let peers = peers.split(',').map(|peer| peer.to_string()).collect();
-        let cur_ts = std::time::UNIX_EPOCH.elapsed().unwrap().as_millis();
+        Self::with_state_machine(id, peers, Box::<KvStateMachine>::default())
+    }
+
+    /// Create a raft instance applying committed logs to `sm` instead of the default key-value state machine.
+    pub fn with_state_machine(id: u32, peers: String, sm: Box<dyn StateMachine>) -> Self {
+        Self::with_store(id, peers, Store::new(id), sm)
+    }
 
+    /// Create a raft instance on an opened store, e.g. one recovered from a `FileStorage`.
+    /// `peers` is the initial membership, it is ignored if the store already has one.
+    pub fn with_store(id: u32, peers: String, mut sto: Store, sm: Box<dyn StateMachine>) -> Self {
+        let peers: Vec<String> = peers.split(',').map(|peer| peer.to_string()).collect();
+        if sto.configs.is_empty() {
+            sto.configs.insert(0, Membership::bootstrap(&peers));
+        }
+        let peers = peers
+            .into_iter()
+            .enumerate()
+            .map(|(id, addr)| (id as u64, addr))
+            .collect();
+        Self::with_peers(id, peers, sto, sm)
+    }
+
+    /// Create a raft instance listening on `addr` that is not a member of any cluster yet.
+    /// It stays idle until a leader adds it with the admin service and replicates the membership to it.
+    pub fn join(id: u32, addr: String, mut sto: Store, sm: Box<dyn StateMachine>) -> Self {
+        if sto.configs.is_empty() {
+            sto.configs.insert(0, Membership::default());
+        }
+        Self::with_peers(id, BTreeMap::from([(id as u64, addr)]), sto, sm)
+    }
+
+    /// Create a raft instance knowing the addresses of `peers`, the members of the store are known as well.
+    pub(crate) fn with_peers(id: u32, mut peers: BTreeMap<u64, String>, sto: Store, sm: Box<dyn StateMachine>) -> Self {
+        peers.extend(sto.membership().1.nodes);
+        let (tx, rx) = mpsc::unbounded_channel();
+        let core = Core::new(id, peers, sto, sm, tx.downgrade());
         Raft {
             id,
-            leading: Arc::new(RwLock::new(None)),
-            commit: Arc::new(RwLock::new(0)),
-            peers: Arc::new(RwLock::new(peers)),
-            sto: Arc::new(RwLock::new(Store::new(id))),
-            last_hb: Arc::new(RwLock::new(cur_ts)),
+            group: 0,
+            tx,
+            metrics: core.subscribe(),
+            telemetry: core.telemetry.clone(),
+            core: Arc::new(Mutex::new(Some((core, rx)))),
         }
     }
 
+    /// Change the core before the scheduler starts it.
+    fn configure(self, f: impl FnOnce(&mut Core)) -> Self {
+        if let Some((core, _)) = self.core.lock().unwrap().as_mut() {
+            f(core);
+        }
+        self
+    }
+
+    /// Make this instance a member of `group`, its rpcs carry the group and it only accepts the ones
+    /// of the group.
+    pub fn with_group(mut self, group: u64) -> Self {
+        self.group = group;
+        self.telemetry = Telemetry::new(self.id, group);
+        let telemetry = self.telemetry.clone();
+        self.configure(|core| core.set_group(group, telemetry))
+    }
+
+    pub fn with_snapshot_policy(self, policy: SnapshotPolicy) -> Self {
+        self.configure(|core| core.snapshot_policy = policy)
+    }
+
+    pub fn with_replication_policy(self, policy: ReplicationPolicy) -> Self {
+        self.configure(|core| core.replication = policy)
+    }
+
+    /// Use `timeouts` instead of the default election timeout and heartbeat interval.
+    pub fn with_timeouts(self, timeouts: Timeouts) -> Self {
+        self.configure(|core| {
+            core.timeouts = timeouts;
+            core.reset_election_timer();
+        })
+    }
+
+    /// Start with the nodes `ids` of the initial membership as witnesses, which vote and keep the log but
+    /// never lead. The node of a witness applies the log to a `WitnessStateMachine`.
+    pub fn with_witnesses(self, ids: &[u64]) -> Self {
+        self.configure(|core| core.set_witnesses(ids))
+    }
+
+    pub fn with_check_quorum(self, enabled: bool) -> Self {
+        self.configure(|core| core.check_quorum = enabled)
+    }
+
+    /// Reach the peers through `transport` instead of gRPC, e.g. a `SimNetwork`.
+    pub fn with_transport(self, transport: Arc<dyn Transport>) -> Self {
+        self.configure(|core| core.transport = transport)
+    }
+
+    /// Read the time from `clock` instead of the wall clock, the election timer restarts on it.
+    pub fn with_clock(self, clock: Arc<dyn Clock>) -> Self {
+        self.configure(|core| {
+            core.clock = clock;
+            core.reset_election_timer();
+        })
+    }
+
+    /// Draw the election timeouts from an rng seeded with `seed`, so that elections can be reproduced.
+    pub fn with_seed(self, seed: u64) -> Self {
+        self.configure(|core| {
+            core.rng = StdRng::seed_from_u64(seed);
+            core.reset_election_timer();
+        })
+    }
+
+    /// Take the core out of the handle, it is `None` once the scheduler has started.
+    #[cfg(test)]
+    pub(crate) fn take_core(&self) -> Option<Core> {
+        self.core.lock().unwrap().take().map(|(core, _)| core)
+    }
+
     // run the raft instance
     pub async fn run(instance: Raft) {
-        let peers = instance.peers.read().unwrap().clone();
-        let addr = peers[instance.id as usize].clone();
+        let addr = instance.metrics().peers[&(instance.id as u64)].clone();
+        if let Err(e) = Self::serve(instance, addr.parse().unwrap(), future::pending()).await {
+            error!("failed to serve raft: {}", e);
+        }
+    }
 
+    /// Serve the raft, kv and admin services on `addr` until `signal` completes, the requests in
+    /// progress are finished first.
+    pub async fn serve(
+        instance: Raft,
+        addr: SocketAddr,
+        signal: impl Future<Output = ()>,
+    ) -> Result<(), tonic::transport::Error> {
         let reflection_service = tonic_reflection::server::Builder::configure()
             .register_encoded_file_descriptor_set(FILE_DESCRIPTOR_SET)
             .build()
             .unwrap();
-        println!("Acceptors server listening on: {}", addr);
-        let exec_result = Server::builder()
+        info!("raft {} listening on {}", instance.id, addr);
+        Server::builder()
+            .add_service(AdminServer::new(instance.clone()))
+            .add_service(KvServer::new(instance.clone()))
             .add_service(RaftServer::new(instance))
             .add_service(reflection_service)
-            .serve(addr.parse().unwrap())
-            .await;
-        if let Err(e) = exec_result {
-            eprintln!("Failed to serve acceptor: {}", e);
-        }
+            .serve_with_shutdown(addr, signal)
+            .await
+    }
+
+    /// Serve the Prometheus metrics of this instance at `/metrics` on `addr` until `signal` completes.
+    pub async fn serve_metrics(
+        instance: Raft,
+        addr: SocketAddr,
+        signal: impl Future<Output = ()>,
+    ) -> Result<(), hyper::Error> {
+        info!("raft {} serving metrics on {}", instance.id, addr);
+        instance.telemetry.serve(addr, signal).await
     }
 
+    /// Run the core of this instance until every handle is dropped, the events sent before it starts
+    /// are handled first. It returns right away if the core is already running.
     pub async fn scheduler(&self) {
-        loop {
-            let cur_ts = std::time::UNIX_EPOCH.elapsed().unwrap().as_millis();
-            let last_hb = { *self.last_hb.read().unwrap() };
-
-            if cur_ts - last_hb > 1000 {
-                // request elect rpc
-                let (term, last_log_id) = {
-                    let sto_read = self.sto.read().unwrap();
-                    (sto_read.term, Some(sto_read.get_last()))
-                };
-                let request = ElectRequest {
-                    id: self.id,
-                    term,
-                    last_log_id,
-                };
-                {
-                    let mut sto = self.sto.write().unwrap();
-                    sto.term += 1;
-                }
-                let _ = self.elect(Request::new(request)).await;
-            }
-            {
-                let mut last_hb = self.last_hb.write().unwrap();
-                *last_hb = std::time::UNIX_EPOCH.elapsed().unwrap().as_millis();
-            }
-            tokio::time::sleep(time::Duration::from_millis(100)).await;
-        }
+        let Some((core, rx)) = self.core.lock().unwrap().take() else {
+            return;
+        };
+        core.run(rx).await;
+    }
+
+    /// Stop the core once the events queued before are handled, and flush the store. The rpcs and
+    /// requests sent afterwards fail as unavailable. It does nothing if the core is already stopped.
+    pub async fn shutdown(&self) -> io::Result<()> {
+        self.call(|tx| Event::Shutdown { tx }).await.unwrap_or(Ok(()))
+    }
+
+    /// Send an event built around a reply sender to the core and wait for the reply.
+    async fn call<T>(&self, event: impl FnOnce(oneshot::Sender<T>) -> Event) -> Result<T, Status> {
+        let (tx, rx) = oneshot::channel();
+        let stopped = || Status::unavailable(format!("raft {} is stopped", self.id));
+        self.tx.send((event(tx), Span::current())).map_err(|_| stopped())?;
+        rx.await.map_err(|_| stopped())
+    }
+
+    /// Append `data` to the log if this instance is the leader. It returns the log id of the new entry
+    /// and a receiver of the state machine output once the entry is applied. The receiver fails if
+    /// the entry is overwritten by another leader.
+    pub async fn propose(&self, data: Vec<u8>) -> Option<(LogId, oneshot::Receiver<String>)> {
+        let entry_type = EntryType::Normal;
+        self.call(|tx| Event::Propose { entry_type, data, tx })
+            .await
+            .ok()
+            .flatten()
+    }
+
+    /// Append a barrier entry if this instance is the leader, like `propose`. Once the receiver gets
+    /// the output, every entry before the barrier is applied to the state machine.
+    pub async fn barrier(&self) -> Option<(LogId, oneshot::Receiver<String>)> {
+        let (entry_type, data) = (EntryType::Barrier, Vec::new());
+        self.call(|tx| Event::Propose { entry_type, data, tx })
+            .await
+            .ok()
+            .flatten()
+    }
+
+    /// Propose a membership change if this instance is the leader and no other change is in progress.
+    /// A change of the voters appends the joint membership first, the leader moves on to the new
+    /// membership by itself once the joint one is committed.
+    pub async fn propose_membership<F>(&self, change: F) -> Result<(LogId, oneshot::Receiver<String>), ChangeError>
+    where
+        F: FnOnce(&Membership) -> Result<Membership, ChangeError> + Send + 'static,
+    {
+        let change = Box::new(change);
+        let resp = self.call(|tx| Event::ChangeMembership { change, tx }).await;
+        resp.unwrap_or(Err(ChangeError::NotLeader))
+    }
+
+    /// Get the read index of a linearizable read, the read is served once the state machine has
+    /// applied up to it. It is `None` if this instance is not the leader or fails to confirm its
+    /// leadership with a heartbeat round to a majority.
+    pub async fn read_index(&self) -> Option<u64> {
+        self.call(|tx| Event::ReadIndex { tx }).await.ok().flatten()
+    }
+
+    /// Get the read index of a lease read without a round trip to the followers. It is `None` if
+    /// this instance is not the leader, or its lease has expired.
+    pub async fn lease_read_index(&self) -> Option<u64> {
+        self.call(|tx| Event::LeaseReadIndex { tx }).await.ok().flatten()
+    }
+
+    /// Hand the leadership over to the voter `target`: stop accepting proposals, replicate the whole
+    /// log to it, then make it campaign right away with a TimeoutNow rpc. The transfer is aborted and
+    /// the leader resumes if `target` has not taken over within an election timeout.
+    /// It returns whether `target` became the leader.
+    pub async fn transfer_leader(&self, target: u64) -> bool {
+        self.call(|tx| Event::TransferLeader { target, tx })
+            .await
+            .unwrap_or(false)
+    }
+
+    /// Query the state machine as it is on this instance.
+    pub async fn query(&self, query: &str) -> Result<String, Status> {
+        let query = query.to_string();
+        self.call(|tx| Event::Query { query, tx }).await
+    }
+
+    /// The status of this instance as the core sees it, including the replication progress of every
+    /// follower if it is the leader.
+    pub async fn status(&self) -> Result<ClusterStatusResponse, Status> {
+        self.call(|tx| Event::Status { tx }).await
+    }
+
+    /// The metrics published after the latest event.
+    pub fn metrics(&self) -> RaftMetrics {
+        self.metrics.borrow().clone()
+    }
+
+    /// The Prometheus metrics of this instance.
+    pub fn telemetry(&self) -> &Telemetry {
+        &self.telemetry
+    }
+
+    /// A receiver notified every time the metrics change, e.g. to wait for an entry to be applied.
+    pub fn subscribe(&self) -> watch::Receiver<RaftMetrics> {
+        self.metrics.clone()
+    }
+
+    /// Whether this instance is a voter of the membership in effect.
+    pub fn is_voter(&self) -> bool {
+        self.metrics.borrow().membership.voters().contains(&(self.id as u64))
+    }
+
+    /// Whether this instance is a witness of the membership in effect, keeping no state machine.
+    pub fn is_witness(&self) -> bool {
+        self.metrics.borrow().membership.witnesses.contains(&(self.id as u64))
+    }
+
+    /// The address of the leader known by this instance.
+    pub fn leader_addr(&self) -> Option<String> {
+        let metrics = self.metrics.borrow();
+        metrics.peers.get(&(metrics.leader_id? as u64)).cloned()
     }
 }
 
 #[tonic::async_trait]
 impl RaftTrait for Raft {
     async fn elect(&self, request: Request<ElectRequest>) -> Result<Response<ElectResponse>, Status> {
+        let ctx = TraceContext::extract(&request);
+        let req = request.into_inner();
+        if req.group != self.group {
+            return Err(not_member(self.id, req.group));
+        }
+        let span = info_span!(
+            "elect",
+            node = self.id,
+            group = self.group,
+            candidate = req.id,
+            term = req.term,
+            trace_id = Empty,
+            parent_span = Empty,
+        );
+        let resp = self.call(|tx| Event::Elect { req, tx });
+        resp.instrument(traced(span, ctx)).await.map(Response::new)
+    }
+
+    async fn pre_vote(&self, request: Request<ElectRequest>) -> Result<Response<ElectResponse>, Status> {
+        let ctx = TraceContext::extract(&request);
         let req = request.into_inner();
-        let mut sto = self.sto.write().unwrap();
-        let mut resp = ElectResponse::default();
-        if sto.voted_for == 0 {
-            sto.voted_for = req.id;
-            resp.granted = true;
+        if req.group != self.group {
+            return Err(not_member(self.id, req.group));
         }
-        Ok(Response::new(resp))
+        let span = info_span!(
+            "pre_vote",
+            node = self.id,
+            group = self.group,
+            candidate = req.id,
+            term = req.term,
+            trace_id = Empty,
+            parent_span = Empty,
+        );
+        let resp = self.call(|tx| Event::PreVote { req, tx });
+        resp.instrument(traced(span, ctx)).await.map(Response::new)
+    }
+
+    async fn timeout_now(&self, request: Request<TimeoutNowRequest>) -> Result<Response<TimeoutNowResponse>, Status> {
+        let ctx = TraceContext::extract(&request);
+        let req = request.into_inner();
+        if req.group != self.group {
+            return Err(not_member(self.id, req.group));
+        }
+        let span = info_span!(
+            "timeout_now",
+            node = self.id,
+            group = self.group,
+            leader = req.id,
+            term = req.term,
+            trace_id = Empty,
+            parent_span = Empty,
+        );
+        let resp = self.call(|tx| Event::TimeoutNow { req, tx });
+        resp.instrument(traced(span, ctx)).await.map(Response::new)
     }
 
     async fn append_log(&self, request: Request<AppendLogRequest>) -> Result<Response<AppendLogResponse>, Status> {
-        let mut req = request.into_inner();
-        let mut sto = self.sto.write().unwrap();
-        let mut resp = AppendLogResponse::default();
-        if sto.voted_for == req.id {
-            sto.logs.append(&mut req.log);
-            resp.success = true;
-        }
-        Ok(Response::new(resp))
+        let ctx = TraceContext::extract(&request);
+        let req = request.into_inner();
+        if req.group != self.group {
+            return Err(not_member(self.id, req.group));
+        }
+        let span = debug_span!(
+            "append_log",
+            node = self.id,
+            group = self.group,
+            leader = req.id,
+            term = req.term,
+            prev_index = req.prev_log_id.as_ref().map(|id| id.index),
+            entries = req.log.len(),
+            leader_commit = req.leader_commit,
+            trace_id = Empty,
+            parent_span = Empty,
+        );
+        let resp = self.call(|tx| Event::AppendLog { req, tx });
+        resp.instrument(traced(span, ctx)).await.map(Response::new)
+    }
+
+    async fn install_snapshot(
+        &self,
+        request: Request<Streaming<InstallSnapshotRequest>>,
+    ) -> Result<Response<InstallSnapshotResponse>, Status> {
+        let ctx = TraceContext::extract(&request);
+        let chunks = read_chunks(request.into_inner()).await?;
+        self.receive_snapshot(chunks, ctx).await.map(Response::new)
+    }
+
+    /// Confirm the leadership and hand a learner the commit index it has to apply before a read.
+    async fn read_index(&self, request: Request<ReadIndexRequest>) -> Result<Response<ReadIndexResponse>, Status> {
+        let req = request.into_inner();
+        if req.group != self.group {
+            return Err(not_member(self.id, req.group));
+        }
+        match self.read_index().await {
+            Some(index) => Ok(Response::new(ReadIndexResponse { index })),
+            None => Err(self.not_leader()),
+        }
+    }
+
+    async fn heartbeat(&self, request: Request<HeartbeatRequest>) -> Result<Response<HeartbeatResponse>, Status> {
+        let mut results = Vec::new();
+        for req in request.into_inner().heartbeats {
+            results.push(self.append_log(Request::new(req)).await.into());
+        }
+        Ok(Response::new(HeartbeatResponse { results }))
+    }
+}
+
+impl From<Result<Response<AppendLogResponse>, Status>> for HeartbeatResult {
+    fn from(resp: Result<Response<AppendLogResponse>, Status>) -> Self {
+        match resp {
+            Ok(resp) => HeartbeatResult {
+                response: Some(resp.into_inner()),
+                error: String::new(),
+            },
+            Err(status) => HeartbeatResult {
+                response: None,
+                error: status.message().to_string(),
+            },
+        }
+    }
+}
+
+/// Reject an rpc sent to a group the raft instance `id` is not a member of.
+pub(crate) fn not_member(id: u32, group: u64) -> Status {
+    Status::not_found(format!("raft {} is not a member of group {}", id, group))
+}
+
+/// Read the chunks of an install snapshot stream up to the last one.
+pub(crate) async fn read_chunks(
+    mut stream: Streaming<InstallSnapshotRequest>,
+) -> Result<Vec<InstallSnapshotRequest>, Status> {
+    let mut chunks = Vec::new();
+    while let Some(chunk) = stream.message().await? {
+        let done = chunk.done;
+        chunks.push(chunk);
+        if done {
+            break;
+        }
+    }
+    Ok(chunks)
+}
+
+/// Record the trace context an rpc is received with on the span handling it.
+fn traced(span: Span, ctx: Option<TraceContext>) -> Span {
+    if let Some(ctx) = ctx {
+        span.record("trace_id", ctx.trace_id());
+        span.record("parent_span", ctx.span_id());
+    }
+    span
+}
+
+impl Raft {
+    /// Assemble the snapshot out of the `chunks` of an install snapshot stream and install it,
+    /// `ctx` is the trace context the stream is received with.
+    pub(crate) async fn receive_snapshot(
+        &self,
+        chunks: Vec<InstallSnapshotRequest>,
+        ctx: Option<TraceContext>,
+    ) -> Result<InstallSnapshotResponse, Status> {
+        let span =
+            debug_span!("install_snapshot", node = self.id, group = self.group, trace_id = Empty, parent_span = Empty,);
+        self.install_chunks(chunks).instrument(traced(span, ctx)).await
+    }
+
+    async fn install_chunks(&self, chunks: Vec<InstallSnapshotRequest>) -> Result<InstallSnapshotResponse, Status> {
+        let mut data = Vec::new();
+        for chunk in chunks {
+            if chunk.group != self.group {
+                return Err(not_member(self.id, chunk.group));
+            }
+            if chunk.offset != data.len() as u64 {
+                return Err(Status::invalid_argument("snapshot chunk out of order"));
+            }
+            data.extend_from_slice(&chunk.data);
+            if chunk.done {
+                let snapshot = Snapshot {
+                    last_log_id: chunk.last_log_id,
+                    data,
+                    configs: chunk.configs,
+                    learners: chunk.learners,
+                };
+                let (leader_id, term) = (chunk.id, chunk.term);
+                return self
+                    .call(|tx| Event::InstallSnapshot {
+                        leader_id,
+                        term,
+                        snapshot,
+                        tx,
+                    })
+                    .await;
+            }
+        }
+        Err(Status::aborted("incomplete snapshot stream"))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use core::time;
+
+    use super::*;
+    use crate::state_machine::KvCommand;
+    use crate::storage::FileStorage;
+    use crate::test_util::{spawn_scheduler, start, start_cluster, wait_for_leader};
+
+    fn log(term: u64, index: u64) -> Log {
+        Log {
+            id: Some(LogId { term, index }),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_append_logs_matching_rule() {
+        let mut sto = Store::new(0);
+        // missing prev log, the hint is the local last log id
+        let res = sto.append_logs(&LogId { term: 1, index: 2 }, vec![log(1, 3)]);
+        assert_eq!(res, Err(LogId::default()));
+
+        assert_eq!(sto.append_logs(&LogId::default(), vec![log(1, 1), log(1, 2), log(2, 3)]), Ok(3));
+        // already present entries are skipped
+        assert_eq!(sto.append_logs(&LogId { term: 1, index: 1 }, vec![log(1, 2)]), Ok(2));
+        assert_eq!(sto.get_last(), LogId { term: 2, index: 3 });
+
+        // a conflicting term skips back to the entry before that term
+        let res = sto.append_logs(&LogId { term: 3, index: 3 }, vec![log(3, 4)]);
+        assert_eq!(res, Err(LogId { term: 1, index: 2 }));
+        // the conflicting suffix is truncated
+        assert_eq!(sto.append_logs(&LogId { term: 1, index: 2 }, vec![log(3, 3)]), Ok(3));
+        assert_eq!(sto.logs, vec![log(1, 1), log(1, 2), log(3, 3)]);
+    }
+
+    #[tokio::test]
+    async fn test_campaign_elects_single_leader() {
+        let nodes = start_cluster("127.0.0.1:19101,127.0.0.1:19102,127.0.0.1:19103");
+
+        // every term has at most one leader
+        let mut leaders: BTreeMap<u64, BTreeSet<u32>> = BTreeMap::new();
+        for _ in 0..100 {
+            tokio::time::sleep(time::Duration::from_millis(50)).await;
+            for node in nodes.iter() {
+                let metrics = node.metrics();
+                if metrics.role == Role::Leader {
+                    leaders.entry(metrics.term).or_default().insert(node.id);
+                }
+            }
+        }
+        assert!(!leaders.is_empty());
+        assert!(leaders.values().all(|ids| ids.len() == 1), "{:?}", leaders);
+    }
+
+    #[tokio::test]
+    async fn test_replicate_logs_to_followers() {
+        let nodes = start_cluster("127.0.0.1:19111,127.0.0.1:19112,127.0.0.1:19113");
+        let leader = wait_for_leader(&nodes).await;
+        for i in 0..10 {
+            let cmd = KvCommand::Put {
+                key: format!("key-{}", i),
+                value: format!("value-{}", i),
+            };
+            leader.propose(cmd.encode()).await.unwrap();
+        }
+        tokio::time::sleep(time::Duration::from_millis(500)).await;
+
+        // the no-op entry of the leader comes first
+        let last = leader.metrics().last_log_id;
+        assert_eq!(last.index, 11);
+        for node in nodes.iter() {
+            let metrics = node.metrics();
+            assert_eq!(metrics.last_log_id, last);
+            // followers learn the commit index from the following heartbeat
+            assert_eq!(metrics.commit, 11);
+            assert_eq!(metrics.applied, last);
+            assert_eq!(node.query("key-9").await.unwrap(), "\"value-9\"");
+        }
+        let matched = leader.metrics().matched;
+        assert_eq!(matched.len(), 2);
+        assert!(matched.values().all(|acked| *acked == last));
+    }
+
+    #[tokio::test]
+    async fn test_lagging_follower_catches_up_with_snapshot() {
+        let peers = "127.0.0.1:19131,127.0.0.1:19132,127.0.0.1:19133";
+        let policy = SnapshotPolicy {
+            max_entries: 5,
+            ..Default::default()
+        };
+        let nodes: Vec<Raft> = (0..3)
+            .map(|id| Raft::new(id, peers.to_string()).with_snapshot_policy(policy))
+            .collect();
+        // the third node stays down while the others commit and compact the log
+        start(&nodes[..2]);
+        let leader = wait_for_leader(&nodes).await;
+        for i in 0..12 {
+            let cmd = KvCommand::Put {
+                key: format!("key-{}", i),
+                value: format!("value-{}", i),
+            };
+            leader.propose(cmd.encode()).await.unwrap();
+            tokio::time::sleep(time::Duration::from_millis(20)).await;
+        }
+        tokio::time::sleep(time::Duration::from_millis(500)).await;
+        let last = leader.metrics().last_log_id;
+        assert_eq!(last.index, 13);
+        assert!(leader.metrics().snapshot_last.index >= 5);
+
+        let lagging = nodes[2].clone();
+        start(&nodes[2..]);
+        tokio::time::sleep(time::Duration::from_millis(1000)).await;
+
+        let metrics = lagging.metrics();
+        assert_eq!(metrics.last_log_id, last);
+        assert!(metrics.snapshot_last.index >= 5);
+        assert_eq!(metrics.applied, last);
+        assert_eq!(lagging.query("key-0").await.unwrap(), "\"value-0\"");
+        assert_eq!(leader.metrics().matched[&2], last);
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_flushes_store() {
+        let dir = tempfile::tempdir().unwrap();
+        let open = || Store::open(0, Box::new(FileStorage::open(dir.path()).unwrap())).unwrap();
+        let timeouts = Timeouts {
+            election: (100, 200),
+            heartbeat: 20,
+        };
+        let node = Raft::with_store(0, "127.0.0.1:19181".to_string(), open(), Box::<KvStateMachine>::default())
+            .with_timeouts(timeouts);
+        let core = spawn_scheduler(&node);
+
+        let mut metrics = node.subscribe();
+        let leader = metrics.wait_for(|m| m.role == Role::Leader);
+        tokio::time::timeout(time::Duration::from_millis(timeouts.election.1 * 2), leader)
+            .await
+            .unwrap()
+            .unwrap();
+        let cmd = KvCommand::Put {
+            key: "key".to_string(),
+            value: "value".to_string(),
+        };
+        let (log_id, rx) = node.propose(cmd.encode()).await.unwrap();
+        rx.await.unwrap();
+
+        node.shutdown().await.unwrap();
+        core.await.unwrap();
+        assert!(node.query("key").await.is_err());
+        // a second shutdown finds the core stopped
+        node.shutdown().await.unwrap();
+        drop(node);
+
+        let sto = open();
+        assert_eq!(sto.get_last(), log_id);
+        assert_eq!(sto.term, log_id.term);
     }
 }
@@ -0,0 +1,356 @@
+use core::time;
+use std::{
+    collections::HashMap,
+    error::Error,
+    future::Future,
+    result::Result,
+    sync::{Arc, Mutex},
+};
+
+use derive_more::Display;
+use rand::Rng;
+use tokio::time::{error::Elapsed, Instant};
+use tonic::{
+    transport::{Channel, Endpoint, TimeoutExpired},
+    Request, Response, Status,
+};
+use tracing::{debug, info, warn};
+
+use crate::node::{ELECTION_TIMEOUT, HEARTBEAT_INTERVAL, RPC_TIMEOUT};
+use crate::raft::{raft_client::RaftClient, PeerHealth};
+use crate::trace::TraceContext;
+use crate::transport::RpcError;
+
+/// How the connections to the peers are made and retried, in milliseconds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConnectionPolicy {
+    /// the deadline of a single rpc, it is sent along so that the peer gives up on it as well
+    pub rpc_timeout: u64,
+    /// the deadline of setting up a connection, which counts towards the deadline of the rpc
+    pub connect_timeout: u64,
+    /// how long the rpcs to a peer fail fast after an rpc failed to reach it, doubled on every failure in a row
+    pub backoff_initial: u64,
+    /// the longest backoff, below the election timeout so that a healed peer hears from the leader
+    /// before it campaigns
+    pub backoff_max: u64,
+}
+
+impl ConnectionPolicy {
+    /// The backoff after `failures` failures in a row. The upper half of it is random, so that the
+    /// nodes cut off by the same partition do not retry in lockstep.
+    pub fn backoff(&self, failures: u32, rng: &mut impl Rng) -> u64 {
+        let doublings = failures.saturating_sub(1).min(16);
+        let backoff = self
+            .backoff_initial
+            .saturating_mul(1 << doublings)
+            .min(self.backoff_max);
+        backoff / 2 + rng.gen_range(0..=backoff - backoff / 2)
+    }
+}
+
+impl Default for ConnectionPolicy {
+    fn default() -> Self {
+        ConnectionPolicy {
+            rpc_timeout: RPC_TIMEOUT,
+            connect_timeout: RPC_TIMEOUT,
+            backoff_initial: HEARTBEAT_INTERVAL,
+            backoff_max: ELECTION_TIMEOUT.0 / 2,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionState {
+    /// no rpc has completed on the channel yet
+    Connecting,
+    /// the latest rpc reached the peer
+    Connected,
+    /// the latest rpc failed to reach the peer, the rpcs fail fast until the backoff is over
+    Backoff,
+}
+
+/// An rpc failed fast, as the connection to `addr` backs off after failing to reach the peer.
+#[derive(Debug, Display)]
+#[display(fmt = "{} is unreachable, retrying in {}ms", addr, retry_in)]
+pub struct Unreachable {
+    pub addr: String,
+    pub retry_in: u64,
+}
+
+impl Error for Unreachable {}
+
+#[derive(Debug)]
+struct Connection {
+    channel: Channel,
+    state: ConnectionState,
+    /// the rpcs in a row that failed to reach the peer
+    failures: u32,
+    /// when the backoff is over
+    retry_at: Instant,
+    last_error: String,
+}
+
+/// The long-lived gRPC channels to the peers, keyed by address. Once an rpc fails to reach a peer, the
+/// rpcs to it fail fast with `Unreachable` for a backoff that grows with every failure in a row, instead
+/// of waiting for their deadlines and piling up during a partition. The first rpc after the backoff
+/// reconnects on a fresh channel. A clone shares the connections.
+#[derive(Debug, Clone, Default)]
+pub struct PeerPool {
+    policy: ConnectionPolicy,
+    connections: Arc<Mutex<HashMap<String, Connection>>>,
+}
+
+impl PeerPool {
+    pub fn new(policy: ConnectionPolicy) -> Self {
+        PeerPool {
+            policy,
+            connections: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    pub fn policy(&self) -> ConnectionPolicy {
+        self.policy
+    }
+
+    /// A channel to `addr`, which connects on the first rpc.
+    fn channel(&self, addr: &str) -> Result<Channel, RpcError> {
+        let endpoint = Endpoint::from_shared(format!("http://{}", addr))?
+            .connect_timeout(time::Duration::from_millis(self.policy.connect_timeout))
+            .tcp_nodelay(true);
+        Ok(endpoint.connect_lazy())
+    }
+
+    /// The client of the peer at `addr`, or `Unreachable` while its connection backs off.
+    fn client(&self, addr: &str) -> Result<RaftClient<Channel>, RpcError> {
+        let mut connections = self.connections.lock().unwrap();
+        let now = Instant::now();
+        if let Some(conn) = connections.get_mut(addr) {
+            if conn.state == ConnectionState::Backoff {
+                if now < conn.retry_at {
+                    let retry_in = (conn.retry_at - now).as_millis() as u64;
+                    return Err(Unreachable {
+                        addr: addr.to_string(),
+                        retry_in,
+                    }
+                    .into());
+                }
+                conn.channel = self.channel(addr)?;
+                conn.state = ConnectionState::Connecting;
+            }
+            return Ok(RaftClient::new(conn.channel.clone()));
+        }
+        let channel = self.channel(addr)?;
+        let conn = Connection {
+            channel: channel.clone(),
+            state: ConnectionState::Connecting,
+            failures: 0,
+            retry_at: now,
+            last_error: String::new(),
+        };
+        connections.insert(addr.to_string(), conn);
+        Ok(RaftClient::new(channel))
+    }
+
+    /// Update the connection to `addr` with the result of an rpc sent on it.
+    fn report<T>(&self, addr: &str, result: &Result<T, RpcError>) {
+        let mut connections = self.connections.lock().unwrap();
+        let Some(conn) = connections.get_mut(addr) else {
+            return;
+        };
+        match result {
+            Err(e) if is_unreachable(e) => {
+                // the rpcs sent along with the failed one do not extend the backoff
+                if conn.state == ConnectionState::Backoff {
+                    return;
+                }
+                conn.failures += 1;
+                let backoff = self.policy.backoff(conn.failures, &mut rand::thread_rng());
+                conn.state = ConnectionState::Backoff;
+                conn.retry_at = Instant::now() + time::Duration::from_millis(backoff);
+                conn.last_error = e.to_string();
+                // only the first failure is worth a warning, the retries would flood the log
+                if conn.failures == 1 {
+                    warn!("failed to reach {}: {}, retrying in {}ms", addr, e, backoff);
+                } else {
+                    debug!("failed to reach {} {} times: {}, retrying in {}ms", addr, conn.failures, e, backoff);
+                }
+            }
+            // the peer is reachable but slow, the deadline of the rpc says nothing about the connection
+            Err(e) if is_timeout(e) => {}
+            _ => {
+                if conn.failures > 0 {
+                    info!("reconnected to {} after {} failures", addr, conn.failures);
+                }
+                conn.state = ConnectionState::Connected;
+                conn.failures = 0;
+            }
+        }
+    }
+
+    /// Send `message` to the peer at `addr` with `rpc`, failing once `deadline` milliseconds have passed.
+    /// The request carries the deadline and the trace context of the current task.
+    pub async fn call<M, T, F, Fut>(&self, addr: &str, deadline: u64, message: M, rpc: F) -> Result<T, RpcError>
+    where
+        F: FnOnce(RaftClient<Channel>, Request<M>) -> Fut,
+        Fut: Future<Output = Result<Response<T>, Status>>,
+    {
+        let client = self.client(addr)?;
+        let deadline = time::Duration::from_millis(deadline);
+        let mut request = Request::new(message);
+        request.set_timeout(deadline);
+        TraceContext::inject(&mut request);
+        let result = match tokio::time::timeout(deadline, rpc(client, request)).await {
+            Ok(resp) => resp.map(Response::into_inner).map_err(RpcError::from),
+            Err(elapsed) => Err(elapsed.into()),
+        };
+        self.report(addr, &result);
+        result
+    }
+
+    /// The health of the connection to `addr`, `None` if no rpc has been sent there.
+    pub fn health(&self, addr: &str) -> Option<PeerHealth> {
+        let connections = self.connections.lock().unwrap();
+        let conn = connections.get(addr)?;
+        let retry_in = match conn.state {
+            ConnectionState::Backoff => conn.retry_at.saturating_duration_since(Instant::now()).as_millis() as u64,
+            _ => 0,
+        };
+        Some(PeerHealth {
+            addr: addr.to_string(),
+            state: format!("{:?}", conn.state),
+            failures: conn.failures,
+            retry_in_ms: retry_in,
+            last_error: conn.last_error.clone(),
+            ..Default::default()
+        })
+    }
+}
+
+/// Whether `e` means the rpc did not reach the peer, rather than the peer rejecting it or taking
+/// too long to respond.
+fn is_unreachable(e: &RpcError) -> bool {
+    if is_timeout(e) {
+        return false;
+    }
+    match e.downcast_ref::<Status>() {
+        // a status sent by the peer has no source, the one of a transport error carries the error
+        Some(status) => status.source().is_some(),
+        None => true,
+    }
+}
+
+/// Whether `e` means the rpc missed its deadline, caught by tonic or by the timeout around the call.
+fn is_timeout(e: &RpcError) -> bool {
+    if e.is::<Elapsed>() {
+        return true;
+    }
+    let mut source = e.downcast_ref::<Status>().and_then(|status| status.source());
+    while let Some(err) = source {
+        if err.is::<TimeoutExpired>() {
+            return true;
+        }
+        source = err.source();
+    }
+    false
+}
+
+#[cfg(test)]
+mod test {
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use super::*;
+    use crate::node::Raft;
+    use crate::raft::ElectRequest;
+    use crate::test_util::start;
+
+    #[test]
+    fn test_backoff_grows_with_jitter() {
+        let policy = ConnectionPolicy {
+            backoff_initial: 100,
+            backoff_max: 1000,
+            ..Default::default()
+        };
+        let mut rng = StdRng::seed_from_u64(1);
+        for (failures, max) in [(1, 100), (2, 200), (3, 400), (4, 800), (5, 1000), (60, 1000)] {
+            for _ in 0..100 {
+                let backoff = policy.backoff(failures, &mut rng);
+                assert!((max / 2..=max).contains(&backoff), "{} failures backs off {}ms", failures, backoff);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_reconnect_after_backoff() {
+        let addr = "127.0.0.1:19321";
+        let pool = PeerPool::new(ConnectionPolicy {
+            backoff_initial: 200,
+            backoff_max: 400,
+            ..Default::default()
+        });
+        let elect = |pool: PeerPool| async move {
+            let request = ElectRequest::default();
+            pool.call(addr, RPC_TIMEOUT, request, |mut client, request| async move { client.pre_vote(request).await })
+                .await
+        };
+        assert!(pool.health(addr).is_none());
+
+        // nothing listens yet, the rpcs fail fast once the first one has failed
+        let e = elect(pool.clone()).await.unwrap_err();
+        assert!(!e.is::<Unreachable>());
+        let health = pool.health(addr).unwrap();
+        assert_eq!((health.state.as_str(), health.failures), ("Backoff", 1));
+        assert!(health.retry_in_ms <= 200);
+        assert!(!health.last_error.is_empty());
+        assert!(elect(pool.clone()).await.unwrap_err().is::<Unreachable>());
+
+        start(&[Raft::new(0, addr.to_string())]);
+        tokio::time::sleep(time::Duration::from_millis(500)).await;
+        let mut resp = elect(pool.clone()).await;
+        while let Err(e) = resp {
+            assert!(e.is::<Unreachable>(), "{}", e);
+            tokio::time::sleep(time::Duration::from_millis(100)).await;
+            resp = elect(pool.clone()).await;
+        }
+        let health = pool.health(addr).unwrap();
+        assert_eq!((health.state.as_str(), health.failures), ("Connected", 0));
+
+        // a status sent by the peer does not count as a failure
+        let request = ElectRequest {
+            group: 1,
+            ..Default::default()
+        };
+        let e = pool
+            .call(addr, RPC_TIMEOUT, request, |mut client, request| async move { client.elect(request).await })
+            .await
+            .unwrap_err();
+        assert_eq!(e.downcast_ref::<Status>().unwrap().code(), tonic::Code::NotFound);
+        assert_eq!(pool.health(addr).unwrap().state, "Connected");
+    }
+
+    #[tokio::test]
+    async fn test_slow_peer_is_not_backed_off() {
+        // the peer takes the connections but never responds
+        let addr = "127.0.0.1:19322";
+        let listener = tokio::net::TcpListener::bind(addr).await.unwrap();
+        tokio::spawn(async move {
+            let mut held = Vec::new();
+            while let Ok((stream, _)) = listener.accept().await {
+                held.push(stream);
+            }
+        });
+
+        let pool = PeerPool::default();
+        for _ in 0..3 {
+            let e = pool
+                .call(addr, 100, ElectRequest::default(), |mut client, request| async move {
+                    client.pre_vote(request).await
+                })
+                .await
+                .unwrap_err();
+            // every rpc waits for its deadline instead of failing fast
+            assert!(is_timeout(&e), "{}", e);
+        }
+        let health = pool.health(addr).unwrap();
+        assert_eq!((health.state.as_str(), health.failures), ("Connecting", 0));
+    }
+}
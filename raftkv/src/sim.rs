@@ -0,0 +1,588 @@
+use core::time;
+use std::{
+    collections::BTreeMap,
+    future::Future,
+    result::Result,
+    sync::{Arc, Mutex},
+};
+
+use derivative::Derivative;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use tonic::{Request, Status};
+
+use crate::clock::VirtualClock;
+use crate::node::{Raft, RPC_TIMEOUT};
+use crate::raft::{raft_server::Raft as RaftTrait, *};
+use crate::transport::{RpcError, Transport};
+
+/// The faults a `SimNetwork` injects into every rpc.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SimConfig {
+    /// the delay of a request or a response is picked in `[min_delay, max_delay]` milliseconds,
+    /// messages sent close together may be delivered out of order
+    pub min_delay: u64,
+    pub max_delay: u64,
+    /// the probability that a request or a response is lost, the rpc then times out
+    pub drop_rate: f64,
+    /// the probability that a request is delivered once more later, the response of the copy is lost
+    pub duplicate_rate: f64,
+}
+
+impl Default for SimConfig {
+    fn default() -> Self {
+        SimConfig {
+            min_delay: 1,
+            max_delay: 10,
+            drop_rate: 0.0,
+            duplicate_rate: 0.0,
+        }
+    }
+}
+
+/// An in-process network delivering the rpcs between raft instances by calling their handlers
+/// directly. Every fault is drawn from a single rng seeded at creation, so with the time of the
+/// tokio runtime paused, a run is reproduced by its seed.
+#[derive(Debug, Clone)]
+pub struct SimNetwork {
+    inner: Arc<Mutex<SimState>>,
+}
+
+#[derive(Derivative)]
+#[derivative(Debug)]
+struct SimState {
+    config: SimConfig,
+    #[derivative(Debug = "ignore")]
+    rng: StdRng,
+    /// the instances keyed by address, an rpc to an unknown address times out
+    #[derivative(Debug = "ignore")]
+    nodes: BTreeMap<String, Raft>,
+    /// the partition each address is in, only the nodes of the same partition reach each other.
+    /// An address not listed is in partition 0.
+    partitions: BTreeMap<String, usize>,
+}
+
+impl SimState {
+    fn reachable(&self, from: &str, to: &str) -> bool {
+        self.partitions.get(from).unwrap_or(&0) == self.partitions.get(to).unwrap_or(&0)
+    }
+
+    fn delay(&mut self) -> time::Duration {
+        time::Duration::from_millis(self.rng.gen_range(self.config.min_delay..=self.config.max_delay))
+    }
+
+    fn lost(&mut self) -> bool {
+        self.rng.gen_bool(self.config.drop_rate)
+    }
+}
+
+impl SimNetwork {
+    pub fn new(seed: u64, config: SimConfig) -> Self {
+        SimNetwork {
+            inner: Arc::new(Mutex::new(SimState {
+                config,
+                rng: StdRng::seed_from_u64(seed),
+                nodes: BTreeMap::new(),
+                partitions: BTreeMap::new(),
+            })),
+        }
+    }
+
+    /// Create a cluster of `n` instances on this network, with the addresses `sim-0` to `sim-{n-1}`.
+    /// Every instance runs on a `VirtualClock` and draws its election timeouts from an rng seeded
+    /// by the network. The schedulers are spawned on the current runtime.
+    pub fn start_cluster(&self, n: usize) -> Vec<Raft> {
+        self.start_cluster_with(n, |raft| raft)
+    }
+
+    /// Create a cluster like `start_cluster`, with every instance set up by `configure` before it starts.
+    pub fn start_cluster_with(&self, n: usize, configure: impl Fn(Raft) -> Raft) -> Vec<Raft> {
+        let addrs: Vec<String> = (0..n).map(|id| format!("sim-{}", id)).collect();
+        let clock = Arc::new(VirtualClock::new());
+        let nodes: Vec<Raft> = addrs
+            .iter()
+            .enumerate()
+            .map(|(id, addr)| {
+                let seed = { self.inner.lock().unwrap().rng.gen() };
+                let raft = Raft::new(id as u32, addrs.join(","))
+                    .with_transport(self.transport(addr))
+                    .with_clock(clock.clone())
+                    .with_seed(seed);
+                configure(raft)
+            })
+            .collect();
+        for (addr, node) in addrs.into_iter().zip(nodes.iter()) {
+            self.register(addr, node.clone());
+            let sch = node.clone();
+            tokio::spawn(async move { sch.scheduler().await });
+        }
+        nodes
+    }
+
+    /// Deliver the rpcs sent to `addr` to `raft`.
+    pub fn register(&self, addr: String, raft: Raft) {
+        self.inner.lock().unwrap().nodes.insert(addr, raft);
+    }
+
+    /// The transport of the instance listening on `addr`.
+    pub fn transport(&self, addr: &str) -> Arc<dyn Transport> {
+        Arc::new(SimTransport {
+            from: addr.to_string(),
+            net: self.clone(),
+        })
+    }
+
+    pub fn set_config(&self, config: SimConfig) {
+        self.inner.lock().unwrap().config = config;
+    }
+
+    /// Split the network, the nodes of each group only reach each other, and the nodes of no group
+    /// only reach the nodes of no group.
+    pub fn partition(&self, groups: &[&[&str]]) {
+        let mut state = self.inner.lock().unwrap();
+        state.partitions.clear();
+        for (i, group) in groups.iter().enumerate() {
+            for addr in group.iter() {
+                state.partitions.insert(addr.to_string(), i + 1);
+            }
+        }
+    }
+
+    /// Cut `addr` off from every other node.
+    pub fn isolate(&self, addr: &str) {
+        let mut state = self.inner.lock().unwrap();
+        let partition = state.partitions.values().max().copied().unwrap_or(0) + 1;
+        state.partitions.insert(addr.to_string(), partition);
+    }
+
+    /// Restore every link.
+    pub fn heal(&self) {
+        self.inner.lock().unwrap().partitions.clear();
+    }
+}
+
+/// The end of a `SimNetwork` at the instance listening on `from`.
+#[derive(Derivative)]
+#[derivative(Debug)]
+struct SimTransport {
+    from: String,
+    #[derivative(Debug = "ignore")]
+    net: SimNetwork,
+}
+
+impl SimTransport {
+    /// Deliver `request` to the instance at `to` with `handle`, and its response back, through the
+    /// faults of the network. The faults of an rpc are drawn before anything is sent.
+    async fn deliver<Req, Resp, F, Fut>(&self, to: String, request: Req, handle: F) -> Result<Resp, RpcError>
+    where
+        Req: Clone + Send + 'static,
+        Resp: Send,
+        F: Fn(Raft, Req) -> Fut + Clone + Send + 'static,
+        Fut: Future<Output = Result<Resp, Status>> + Send,
+    {
+        let (raft, request_lost, duplicate, request_delay, response_lost, response_delay) = {
+            let mut state = self.net.inner.lock().unwrap();
+            let raft = state.nodes.get(&to).cloned();
+            let request_lost = state.lost();
+            let duplicate_rate = state.config.duplicate_rate;
+            let duplicate = state.rng.gen_bool(duplicate_rate).then(|| state.delay());
+            let request_delay = state.delay();
+            let response_lost = state.lost();
+            let response_delay = state.delay();
+            (raft, request_lost, duplicate, request_delay, response_lost, response_delay)
+        };
+
+        let rpc = async {
+            let Some(raft) = raft.filter(|_| !request_lost) else {
+                return std::future::pending().await;
+            };
+            if let Some(delay) = duplicate {
+                let (raft, request, handle) = (raft.clone(), request.clone(), handle.clone());
+                let (net, from, to) = (self.net.clone(), self.from.clone(), to.clone());
+                tokio::spawn(async move {
+                    tokio::time::sleep(request_delay + delay).await;
+                    if net.inner.lock().unwrap().reachable(&from, &to) {
+                        let _ = handle(raft, request).await;
+                    }
+                });
+            }
+            tokio::time::sleep(request_delay).await;
+            if !self.net.inner.lock().unwrap().reachable(&self.from, &to) {
+                return std::future::pending().await;
+            }
+            let resp = handle(raft, request).await;
+            if response_lost {
+                return std::future::pending().await;
+            }
+            tokio::time::sleep(response_delay).await;
+            if !self.net.inner.lock().unwrap().reachable(&to, &self.from) {
+                return std::future::pending().await;
+            }
+            Ok::<_, RpcError>(resp?)
+        };
+        tokio::time::timeout(time::Duration::from_millis(RPC_TIMEOUT), rpc).await?
+    }
+}
+
+#[tonic::async_trait]
+impl Transport for SimTransport {
+    async fn elect(&self, addr: String, request: ElectRequest) -> Result<ElectResponse, RpcError> {
+        let handle =
+            |raft: Raft, request| async move { Ok(RaftTrait::elect(&raft, Request::new(request)).await?.into_inner()) };
+        self.deliver(addr, request, handle).await
+    }
+
+    async fn pre_vote(&self, addr: String, request: ElectRequest) -> Result<ElectResponse, RpcError> {
+        let handle = |raft: Raft, request| async move {
+            Ok(RaftTrait::pre_vote(&raft, Request::new(request)).await?.into_inner())
+        };
+        self.deliver(addr, request, handle).await
+    }
+
+    async fn append_log(&self, addr: String, request: AppendLogRequest) -> Result<AppendLogResponse, RpcError> {
+        let handle = |raft: Raft, request| async move {
+            Ok(RaftTrait::append_log(&raft, Request::new(request)).await?.into_inner())
+        };
+        self.deliver(addr, request, handle).await
+    }
+
+    async fn install_snapshot(
+        &self,
+        addr: String,
+        chunks: Vec<InstallSnapshotRequest>,
+    ) -> Result<InstallSnapshotResponse, RpcError> {
+        let handle = |raft: Raft, chunks| async move { raft.receive_snapshot(chunks, None).await };
+        self.deliver(addr, chunks, handle).await
+    }
+
+    async fn timeout_now(&self, addr: String, request: TimeoutNowRequest) -> Result<TimeoutNowResponse, RpcError> {
+        let handle = |raft: Raft, request| async move {
+            Ok(RaftTrait::timeout_now(&raft, Request::new(request)).await?.into_inner())
+        };
+        self.deliver(addr, request, handle).await
+    }
+
+    async fn read_index(&self, addr: String, request: ReadIndexRequest) -> Result<ReadIndexResponse, RpcError> {
+        let handle = |raft: Raft, request| async move {
+            Ok(RaftTrait::read_index(&raft, Request::new(request)).await?.into_inner())
+        };
+        self.deliver(addr, request, handle).await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use prost::Message;
+
+    use super::*;
+    use crate::clock::Clock;
+    use crate::node::{ReplicationPolicy, Role, ELECTION_TIMEOUT, TICK_INTERVAL};
+    use crate::state_machine::KvCommand;
+    use crate::test_util::find_leader;
+
+    fn lossy() -> SimConfig {
+        SimConfig {
+            min_delay: 1,
+            max_delay: 50,
+            drop_rate: 0.1,
+            duplicate_rate: 0.1,
+        }
+    }
+
+    /// Let the cluster run for `millis`, checking every tick that there is at most one leader per term.
+    async fn run(nodes: &[Raft], millis: u64, leaders: &mut BTreeMap<u64, u32>) {
+        for _ in 0..millis / TICK_INTERVAL {
+            tokio::time::sleep(time::Duration::from_millis(TICK_INTERVAL)).await;
+            for node in nodes.iter().filter(|node| node.metrics().role == Role::Leader) {
+                let term = node.metrics().term;
+                let leader = leaders.entry(term).or_insert(node.id);
+                assert_eq!(*leader, node.id, "two leaders in term {}", term);
+            }
+        }
+    }
+
+    /// Passes the rpcs of an instance on to the network, recording the peer and the number of entries
+    /// of every append log rpc.
+    #[derive(Debug)]
+    struct Tap {
+        inner: Arc<dyn Transport>,
+        sent: Arc<Mutex<Vec<(String, usize)>>>,
+    }
+
+    #[tonic::async_trait]
+    impl Transport for Tap {
+        async fn elect(&self, addr: String, request: ElectRequest) -> Result<ElectResponse, RpcError> {
+            self.inner.elect(addr, request).await
+        }
+
+        async fn pre_vote(&self, addr: String, request: ElectRequest) -> Result<ElectResponse, RpcError> {
+            self.inner.pre_vote(addr, request).await
+        }
+
+        async fn append_log(&self, addr: String, request: AppendLogRequest) -> Result<AppendLogResponse, RpcError> {
+            self.sent.lock().unwrap().push((addr.clone(), request.log.len()));
+            self.inner.append_log(addr, request).await
+        }
+
+        async fn install_snapshot(
+            &self,
+            addr: String,
+            chunks: Vec<InstallSnapshotRequest>,
+        ) -> Result<InstallSnapshotResponse, RpcError> {
+            self.inner.install_snapshot(addr, chunks).await
+        }
+
+        async fn timeout_now(&self, addr: String, request: TimeoutNowRequest) -> Result<TimeoutNowResponse, RpcError> {
+            self.inner.timeout_now(addr, request).await
+        }
+
+        async fn read_index(&self, addr: String, request: ReadIndexRequest) -> Result<ReadIndexResponse, RpcError> {
+            self.inner.read_index(addr, request).await
+        }
+    }
+
+    /// Start a cluster of `n` instances replicating with `policy`, whose append log rpcs are recorded in `sent`.
+    fn start_tapped_cluster(
+        net: &SimNetwork,
+        n: usize,
+        policy: ReplicationPolicy,
+        sent: &Arc<Mutex<Vec<(String, usize)>>>,
+    ) -> Vec<Raft> {
+        net.start_cluster_with(n, |raft| {
+            let tap = Tap {
+                inner: net.transport(&format!("sim-{}", raft.id)),
+                sent: sent.clone(),
+            };
+            raft.with_replication_policy(policy).with_transport(Arc::new(tap))
+        })
+    }
+
+    /// The entries sent to the peer at `addr` in every append log rpc carrying any.
+    fn entries_sent(sent: &Mutex<Vec<(String, usize)>>, addr: &str) -> Vec<usize> {
+        let sent = sent.lock().unwrap();
+        sent.iter()
+            .filter(|(to, entries)| to == addr && *entries > 0)
+            .map(|(_, entries)| *entries)
+            .collect()
+    }
+
+    fn put(i: usize) -> Vec<u8> {
+        KvCommand::Put {
+            key: format!("key-{}", i % 10),
+            value: format!("value-{}", i),
+        }
+        .encode()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_elections_under_faults() {
+        let net = SimNetwork::new(7, lossy());
+        let nodes = net.start_cluster(5);
+        let mut leaders = BTreeMap::new();
+        for i in 0..100 {
+            run(&nodes, TICK_INTERVAL * 4, &mut leaders).await;
+            if let Some(leader) = find_leader(&nodes) {
+                leader.propose(put(i)).await;
+            }
+            if i % 25 == 0 {
+                net.isolate(&format!("sim-{}", i % 5));
+            } else if i % 25 == 10 {
+                net.heal();
+            }
+        }
+        assert!(!leaders.is_empty());
+
+        // once the network is reliable again, every node ends up with the state of the leader
+        net.heal();
+        net.set_config(SimConfig::default());
+        run(&nodes, ELECTION_TIMEOUT.1 * 2, &mut leaders).await;
+        let leader = find_leader(&nodes).unwrap();
+        let (_, rx) = leader.propose(put(100)).await.unwrap();
+        rx.await.unwrap();
+        run(&nodes, ELECTION_TIMEOUT.0, &mut leaders).await;
+        let applied = leader.metrics().applied;
+        for node in nodes.iter() {
+            assert_eq!(node.metrics().applied, applied);
+            for i in 0..10 {
+                let key = format!("key-{}", i);
+                assert_eq!(node.query(&key).await.unwrap(), leader.query(&key).await.unwrap());
+            }
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_partitioned_leader_is_replaced() {
+        let net = SimNetwork::new(1, SimConfig::default());
+        let nodes = net.start_cluster(5);
+        let mut leaders = BTreeMap::new();
+        run(&nodes, ELECTION_TIMEOUT.1 * 2, &mut leaders).await;
+        let old = find_leader(&nodes).unwrap();
+        let term = old.metrics().term;
+
+        net.isolate(&format!("sim-{}", old.id));
+        run(&nodes, ELECTION_TIMEOUT.1 * 3, &mut leaders).await;
+        let new = find_leader(&nodes).unwrap();
+        assert_ne!(new.id, old.id);
+        // check quorum steps the old leader down, and pre-vote keeps it from raising its term
+        assert_ne!(old.metrics().role, Role::Leader);
+        assert_eq!(old.metrics().term, term);
+
+        net.heal();
+        run(&nodes, ELECTION_TIMEOUT.1, &mut leaders).await;
+        assert_eq!(find_leader(&nodes).unwrap().id, new.id);
+        assert_eq!(old.metrics().leader_id, Some(new.id));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_pipelined_replication_under_faults() {
+        let net = SimNetwork::new(3, lossy());
+        let policy = ReplicationPolicy {
+            max_inflight: 8,
+            max_entries: 4,
+            max_bytes: 256,
+            ..Default::default()
+        };
+        let nodes = net.start_cluster_with(3, |raft| raft.with_replication_policy(policy));
+        let mut leaders = BTreeMap::new();
+        run(&nodes, ELECTION_TIMEOUT.1 * 2, &mut leaders).await;
+
+        // the rpcs of a window are lost, duplicated and reordered, the leader starts over from what is acked
+        for round in 0..20 {
+            if let Some(leader) = find_leader(&nodes) {
+                for i in 0..20 {
+                    leader.propose(put(round * 20 + i)).await;
+                }
+            }
+            run(&nodes, TICK_INTERVAL * 2, &mut leaders).await;
+        }
+
+        net.set_config(SimConfig::default());
+        run(&nodes, ELECTION_TIMEOUT.1, &mut leaders).await;
+        let leader = find_leader(&nodes).unwrap();
+        let (_, rx) = leader.propose(put(400)).await.unwrap();
+        rx.await.unwrap();
+        run(&nodes, ELECTION_TIMEOUT.0, &mut leaders).await;
+        let metrics = leader.metrics();
+        assert!(metrics.last_log_id.index > 100, "{:?}", metrics.last_log_id);
+        for node in nodes.iter() {
+            assert_eq!(node.metrics().last_log_id, metrics.last_log_id);
+            assert_eq!(node.metrics().applied, metrics.applied);
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_queued_proposals_go_out_together() {
+        let net = SimNetwork::new(5, SimConfig::default());
+        let sent = Arc::new(Mutex::new(Vec::new()));
+        let policy = ReplicationPolicy {
+            max_inflight: 8,
+            ..Default::default()
+        };
+        let nodes = start_tapped_cluster(&net, 3, policy, &sent);
+        let mut leaders = BTreeMap::new();
+        run(&nodes, ELECTION_TIMEOUT.1 * 2, &mut leaders).await;
+        let leader = find_leader(&nodes).unwrap();
+        sent.lock().unwrap().clear();
+
+        // the proposals queued before the leader gets to them are appended and sent in one rpc
+        let proposals: Vec<_> = (0..10)
+            .map(|i| {
+                let leader = leader.clone();
+                tokio::spawn(async move {
+                    let (_, rx) = leader.propose(put(i)).await.unwrap();
+                    rx.await.unwrap()
+                })
+            })
+            .collect();
+        for proposal in proposals {
+            proposal.await.unwrap();
+        }
+        for follower in nodes.iter().filter(|node| node.id != leader.id) {
+            assert_eq!(entries_sent(&sent, &format!("sim-{}", follower.id)), vec![10]);
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_inflight_bytes_pause_replication() {
+        let net = SimNetwork::new(6, SimConfig::default());
+        let sent = Arc::new(Mutex::new(Vec::new()));
+        // the entries of `put` below 10 have the same size as long as the term and the index are small
+        let entry = Log {
+            id: Some(LogId { term: 1, index: 1 }),
+            data: put(0),
+            ..Default::default()
+        };
+        let policy = ReplicationPolicy {
+            max_inflight: 100,
+            max_entries: 1,
+            max_inflight_bytes: entry.encoded_len() as u64 * 3,
+            ..Default::default()
+        };
+        let nodes = start_tapped_cluster(&net, 3, policy, &sent);
+        let mut leaders = BTreeMap::new();
+        run(&nodes, ELECTION_TIMEOUT.1 * 2, &mut leaders).await;
+        let leader = find_leader(&nodes).unwrap();
+        let follower = nodes.iter().find(|node| node.id != leader.id).unwrap();
+        let addr = format!("sim-{}", follower.id);
+
+        // the rpcs to a follower cut off stay in flight until they time out, and use up the byte budget
+        net.isolate(&addr);
+        sent.lock().unwrap().clear();
+        for i in 0..10 {
+            leader.propose(put(i)).await.unwrap();
+        }
+        run(&nodes, RPC_TIMEOUT - TICK_INTERVAL, &mut leaders).await;
+        assert_eq!(entries_sent(&sent, &addr), vec![1, 1, 1]);
+        // the budget is kept per follower, the other one is not held back
+        let other = nodes
+            .iter()
+            .find(|node| node.id != leader.id && node.id != follower.id)
+            .unwrap();
+        assert_eq!(other.metrics().last_log_id, leader.metrics().last_log_id);
+
+        // the follower catches up once it is reachable again
+        net.heal();
+        run(&nodes, ELECTION_TIMEOUT.0, &mut leaders).await;
+        assert_eq!(follower.metrics().last_log_id, leader.metrics().last_log_id);
+    }
+
+    /// The role changes of every node along a run with faults drawn from `seed`, with the time they are seen.
+    fn election_history(seed: u64) -> Vec<(u128, u32, u64, Role)> {
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .start_paused(true)
+            .build()
+            .unwrap();
+        rt.block_on(async {
+            let clock = VirtualClock::new();
+            let net = SimNetwork::new(seed, lossy());
+            let nodes = net.start_cluster(3);
+            let mut history = Vec::new();
+            let mut roles = vec![Role::Follower; nodes.len()];
+            for tick in 0..400 {
+                tokio::time::sleep(time::Duration::from_millis(TICK_INTERVAL)).await;
+                if tick % 100 == 50 {
+                    if let Some(leader) = find_leader(&nodes) {
+                        net.isolate(&format!("sim-{}", leader.id));
+                    }
+                } else if tick % 100 == 90 {
+                    net.heal();
+                }
+                for (node, role) in nodes.iter().zip(roles.iter_mut()) {
+                    let metrics = node.metrics();
+                    if metrics.role != *role {
+                        *role = metrics.role;
+                        history.push((clock.now(), node.id, metrics.term, metrics.role));
+                    }
+                }
+            }
+            history
+        })
+    }
+
+    #[test]
+    fn test_same_seed_same_history() {
+        let history = election_history(42);
+        assert!(history.iter().filter(|(.., role)| *role == Role::Leader).count() > 1);
+        assert_eq!(history, election_history(42));
+    }
+}
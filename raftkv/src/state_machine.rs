@@ -0,0 +1,509 @@
+use std::{collections::BTreeMap, fmt::Debug, sync::Arc};
+
+use derivative::Derivative;
+use serde::{Deserialize, Serialize};
+
+use crate::multi::KeyRange;
+use crate::raft::{EntryType, Log, LogId};
+
+/// A replicated state machine, it consumes the committed log entries one by one in log order.
+pub trait StateMachine: Debug + Send + Sync {
+    /// Apply a committed log entry and return the result for the proposer of the entry. Every entry
+    /// is passed in to move `last_applied`, only the `EntryType::Normal` ones carry a command.
+    fn apply(&mut self, log: &Log) -> String;
+
+    /// Serve a read-only query against the applied state, the format of `query` and the result
+    /// is up to the state machine.
+    fn query(&self, query: &str) -> String;
+
+    /// The id of the last applied log entry.
+    fn last_applied(&self) -> LogId;
+
+    /// Serialize the applied state, the snapshot covers the logs up to `last_applied()`.
+    fn snapshot(&self) -> Vec<u8>;
+
+    /// Replace the whole state with a snapshot covering the logs up to `last`.
+    fn restore(&mut self, last: LogId, data: &[u8]);
+}
+
+/// The command carried by `Log::data` for the built-in key-value state machine.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum KvCommand {
+    Put {
+        key: String,
+        value: String,
+    },
+    Delete {
+        key: String,
+    },
+    /// set `key` to `value` only if its current value equals `expected`, `None` means absent
+    CompareAndSwap {
+        key: String,
+        expected: Option<String>,
+        value: String,
+    },
+    /// move the keys from `key` to the end of the range to the new group `group`, whose replicas are
+    /// the members given in the format of `Log`. The middle key of the range is picked if `key` is `None`
+    Split {
+        key: Option<String>,
+        group: u64,
+        configs: Vec<String>,
+        learners: String,
+    },
+    /// stop taking writes, so that the range can be merged into the adjacent range of `target`
+    PrepareMerge {
+        target: u64,
+    },
+    /// take writes again after a merge is given up
+    CancelMerge,
+    /// take over the adjacent range `source` prepared for the merge, along with its keys
+    CommitMerge {
+        source: KeyRange,
+        data: BTreeMap<String, String>,
+    },
+}
+
+/// The result of applying a `KvCommand`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct KvResult {
+    /// false if a compare-and-swap did not match
+    pub ok: bool,
+    /// the value of the key before the command is applied
+    pub prev: Option<String>,
+    /// the key is out of the range, or the range is being merged
+    pub moved: bool,
+}
+
+/// The state of a key-value state machine, the format of its snapshots.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct KvSnapshot {
+    pub range: KeyRange,
+    /// the group the range is being merged into
+    pub merging: Option<u64>,
+    pub data: BTreeMap<String, String>,
+    /// the ranges split off so far, one per split
+    pub split_off: Vec<SplitOff>,
+}
+
+/// A range split off a key-value state machine, with the members its group started with in the
+/// format of `Log`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SplitOff {
+    pub range: KeyRange,
+    pub configs: Vec<String>,
+    pub learners: String,
+}
+
+/// A change of the range of a key-value state machine, reported on every replica once applied.
+#[derive(Debug, Clone, PartialEq)]
+pub enum RangeChange {
+    /// the keys from `right.start` moved to the new group `right.group`, which starts from the state
+    /// machine `snapshot` with the members in the format of `Log`
+    Split {
+        left: KeyRange,
+        right: KeyRange,
+        snapshot: Vec<u8>,
+        configs: Vec<String>,
+        learners: String,
+    },
+    /// `range` grew over the range of `source`, whose group is gone
+    Merge { range: KeyRange, source: KeyRange },
+    /// a snapshot of `range` replaced the state, along with the splits and merges it was taken past.
+    /// The ranges in `split_off` were split off meanwhile, the groups of the ranges now covered by
+    /// `range` were merged into it
+    Restore { range: KeyRange, split_off: Vec<SplitOff> },
+}
+
+/// Called by a key-value state machine with every range change it applies.
+pub type RangeListener = Arc<dyn Fn(RangeChange) + Send + Sync>;
+
+impl KvCommand {
+    pub fn encode(&self) -> Vec<u8> {
+        serde_json::to_vec(self).unwrap()
+    }
+
+    pub fn decode(data: &[u8]) -> Option<Self> {
+        serde_json::from_slice(data).ok()
+    }
+}
+
+/// A key-value state machine, the default state machine of raftkv. It serves a range of keys,
+/// every key unless it is hosted by a `MultiRaft`.
+#[derive(Derivative, Default)]
+#[derivative(Debug)]
+pub struct KvStateMachine {
+    range: KeyRange,
+    /// the group the range is being merged into, no write is taken meanwhile
+    merging: Option<u64>,
+    data: BTreeMap<String, String>,
+    /// the ranges split off so far, so that a replica restoring a snapshot past a split learns of it
+    split_off: Vec<SplitOff>,
+    last_applied: LogId,
+    #[derivative(Debug = "ignore")]
+    listener: Option<RangeListener>,
+}
+
+impl KvStateMachine {
+    /// A state machine serving the keys of `range`, until a snapshot or a range change replaces it.
+    pub fn new(range: KeyRange) -> Self {
+        KvStateMachine {
+            range,
+            ..Default::default()
+        }
+    }
+
+    /// Report the range changes to `listener` as they are applied.
+    pub fn with_listener(mut self, listener: RangeListener) -> Self {
+        self.listener = Some(listener);
+        self
+    }
+
+    fn state(&self) -> KvSnapshot {
+        KvSnapshot {
+            range: self.range.clone(),
+            merging: self.merging,
+            data: self.data.clone(),
+            split_off: self.split_off.clone(),
+        }
+    }
+
+    fn report(&self, change: RangeChange) {
+        if let Some(listener) = self.listener.as_ref() {
+            listener(change);
+        }
+    }
+
+    fn write(&mut self, key: &str, f: impl FnOnce(&mut BTreeMap<String, String>) -> KvResult) -> KvResult {
+        if !self.range.contains(key) || self.merging.is_some() {
+            return KvResult {
+                moved: true,
+                ..Default::default()
+            };
+        }
+        f(&mut self.data)
+    }
+
+    /// Split at `key`, or the middle key. Returns the new range, or `None` if the left part would be empty.
+    fn split(&mut self, key: Option<String>, group: u64, configs: Vec<String>, learners: String) -> Option<KeyRange> {
+        let key = key.or_else(|| self.data.keys().nth(self.data.len() / 2).cloned())?;
+        if !self.range.contains(&key) || key <= self.range.start || self.merging.is_some() {
+            return None;
+        }
+        self.range.version += 1;
+        let right = KeyRange {
+            group,
+            start: key.clone(),
+            end: std::mem::replace(&mut self.range.end, key.clone()),
+            version: self.range.version,
+        };
+        let moved = KvSnapshot {
+            range: right.clone(),
+            merging: None,
+            data: self.data.split_off(&key),
+            split_off: Vec::new(),
+        };
+        self.split_off.push(SplitOff {
+            range: right.clone(),
+            configs: configs.clone(),
+            learners: learners.clone(),
+        });
+        self.report(RangeChange::Split {
+            left: self.range.clone(),
+            right: right.clone(),
+            snapshot: serde_json::to_vec(&moved).unwrap(),
+            configs,
+            learners,
+        });
+        Some(right)
+    }
+
+    /// Take over `source` if it is adjacent. Returns the merged range, also if `source` is merged
+    /// already, when a merge is retried.
+    fn merge(&mut self, source: KeyRange, data: BTreeMap<String, String>) -> Option<KeyRange> {
+        if self.merging.is_some() {
+            return None;
+        }
+        // a retry leaves the keys alone, they may have been written since the merge was applied
+        if self.range.covers(&source) {
+            return Some(self.range.clone());
+        }
+        if !source.end.is_empty() && source.end == self.range.start {
+            self.range.start = source.start.clone();
+        } else if !self.range.end.is_empty() && source.start == self.range.end {
+            self.range.end = source.end.clone();
+        } else {
+            return None;
+        }
+        self.range.version = self.range.version.max(source.version) + 1;
+        self.data.extend(data);
+        // a range merged back is not split off any more
+        self.split_off.retain(|split_off| split_off.range.group != source.group);
+        self.report(RangeChange::Merge {
+            range: self.range.clone(),
+            source,
+        });
+        Some(self.range.clone())
+    }
+}
+
+impl StateMachine for KvStateMachine {
+    /// The result of a key command is the json encoded `KvResult`, of a split or a commit merge the
+    /// new `Option<KeyRange>`, and of a prepare merge the `Option<KvSnapshot>` to merge.
+    fn apply(&mut self, log: &Log) -> String {
+        self.last_applied = log.id.clone().unwrap_or_default();
+        // no-op, membership and barrier entries only move `last_applied`
+        if log.entry_type() != EntryType::Normal {
+            return String::new();
+        }
+        let Some(cmd) = KvCommand::decode(&log.data) else {
+            return String::new();
+        };
+        let result = match cmd {
+            KvCommand::Put { key, value } => self.write(&key.clone(), |data| KvResult {
+                ok: true,
+                prev: data.insert(key, value),
+                ..Default::default()
+            }),
+            KvCommand::Delete { key } => self.write(&key, |data| KvResult {
+                ok: true,
+                prev: data.remove(&key),
+                ..Default::default()
+            }),
+            KvCommand::CompareAndSwap { key, expected, value } => self.write(&key.clone(), |data| {
+                let prev = data.get(&key).cloned();
+                let ok = prev == expected;
+                if ok {
+                    data.insert(key, value);
+                }
+                KvResult {
+                    ok,
+                    prev,
+                    ..Default::default()
+                }
+            }),
+            KvCommand::Split {
+                key,
+                group,
+                configs,
+                learners,
+            } => return serde_json::to_string(&self.split(key, group, configs, learners)).unwrap(),
+            KvCommand::PrepareMerge { target } => {
+                // preparing again for the same target is fine, the keys cannot have changed since
+                if self.merging.is_some_and(|merging| merging != target) {
+                    return serde_json::to_string(&None::<KvSnapshot>).unwrap();
+                }
+                self.merging = Some(target);
+                return serde_json::to_string(&Some(self.state())).unwrap();
+            }
+            KvCommand::CancelMerge => {
+                self.merging = None;
+                KvResult::default()
+            }
+            KvCommand::CommitMerge { source, data } => {
+                return serde_json::to_string(&self.merge(source, data)).unwrap();
+            }
+        };
+        serde_json::to_string(&result).unwrap()
+    }
+
+    /// The query is a key, and the result is its json encoded `Option<String>` value, or empty if
+    /// the key is out of the range or the range is being merged, as the target may have taken over
+    /// the key already.
+    fn query(&self, query: &str) -> String {
+        if !self.range.contains(query) || self.merging.is_some() {
+            return String::new();
+        }
+        serde_json::to_string(&self.data.get(query)).unwrap()
+    }
+
+    fn last_applied(&self) -> LogId {
+        self.last_applied.clone()
+    }
+
+    fn snapshot(&self) -> Vec<u8> {
+        serde_json::to_vec(&self.state()).unwrap()
+    }
+
+    /// It panics on a snapshot that cannot be decoded, the replica would diverge from the others if it
+    /// went on from an empty state.
+    fn restore(&mut self, last: LogId, data: &[u8]) {
+        let state: KvSnapshot = serde_json::from_slice(data)
+            .unwrap_or_else(|e| panic!("the snapshot covering the logs up to {:?} is corrupt: {}", last, e));
+        let changed = state.range != self.range;
+        // the splits the snapshot was taken past are the ones newer than the range replaced
+        let missed = state
+            .split_off
+            .iter()
+            .filter(|split_off| split_off.range.version > self.range.version)
+            .cloned()
+            .collect();
+        self.range = state.range;
+        self.merging = state.merging;
+        self.data = state.data;
+        self.split_off = state.split_off;
+        self.last_applied = last;
+        if changed {
+            self.report(RangeChange::Restore {
+                range: self.range.clone(),
+                split_off: missed,
+            });
+        }
+    }
+}
+
+/// The state machine of a witness, which votes and keeps the log but not the data. Every entry only
+/// moves `last_applied`, so its snapshots are empty and compacting the log costs nothing.
+#[derive(Debug, Default)]
+pub struct WitnessStateMachine {
+    last_applied: LogId,
+}
+
+impl StateMachine for WitnessStateMachine {
+    fn apply(&mut self, log: &Log) -> String {
+        self.last_applied = log.id.clone().unwrap_or_default();
+        String::new()
+    }
+
+    fn query(&self, _: &str) -> String {
+        String::new()
+    }
+
+    fn last_applied(&self) -> LogId {
+        self.last_applied.clone()
+    }
+
+    fn snapshot(&self) -> Vec<u8> {
+        Vec::new()
+    }
+
+    fn restore(&mut self, last: LogId, _: &[u8]) {
+        self.last_applied = last;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Mutex;
+
+    use super::*;
+
+    fn range(group: u64, start: &str, end: &str, version: u64) -> KeyRange {
+        KeyRange {
+            group,
+            start: start.to_string(),
+            end: end.to_string(),
+            version,
+        }
+    }
+
+    fn apply(sm: &mut KvStateMachine, cmd: KvCommand) -> String {
+        let index = sm.last_applied().index + 1;
+        let log = Log {
+            id: Some(LogId { term: 1, index }),
+            data: cmd.encode(),
+            ..Default::default()
+        };
+        sm.apply(&log)
+    }
+
+    fn put(sm: &mut KvStateMachine, key: &str) -> KvResult {
+        let put = KvCommand::Put {
+            key: key.to_string(),
+            value: "v".to_string(),
+        };
+        serde_json::from_str(&apply(sm, put)).unwrap()
+    }
+
+    #[test]
+    fn test_split_and_merge_range() {
+        let changes = Arc::new(Mutex::new(Vec::new()));
+        let reported = changes.clone();
+        let listener: RangeListener = Arc::new(move |change| reported.lock().unwrap().push(change));
+        let mut sm = KvStateMachine::new(KeyRange::full(1)).with_listener(listener.clone());
+        for key in ["a", "b", "c", "d"] {
+            assert!(put(&mut sm, key).ok);
+        }
+
+        // the left part of a split cannot be empty
+        let split = |key: Option<&str>| KvCommand::Split {
+            key: key.map(|key| key.to_string()),
+            group: 2,
+            configs: vec!["0=127.0.0.1:9001".to_string()],
+            learners: String::new(),
+        };
+        assert_eq!(apply(&mut sm, split(Some(""))), "null");
+        // the middle key is picked by default
+        let right: Option<KeyRange> = serde_json::from_str(&apply(&mut sm, split(None))).unwrap();
+        assert_eq!(right, Some(range(2, "c", "", 1)));
+        assert!(put(&mut sm, "c").moved);
+        assert!(!put(&mut sm, "b").moved);
+        assert_eq!(sm.query("c"), "");
+        assert_eq!(sm.query("a"), "\"v\"");
+
+        let Some(RangeChange::Split { left, snapshot, .. }) = changes.lock().unwrap().pop() else {
+            panic!("the split is not reported");
+        };
+        assert_eq!(left, range(1, "", "c", 1));
+        let mut right = KvStateMachine::default();
+        right.restore(LogId { term: 0, index: 1 }, &snapshot);
+        assert_eq!(right.query("d"), "\"v\"");
+        assert_eq!(right.query("a"), "");
+
+        // the range survives a snapshot
+        let mut restored = KvStateMachine::default();
+        restored.restore(sm.last_applied(), &sm.snapshot());
+        assert_eq!(restored.query("c"), "");
+        // and a replica restoring it past the split learns of the range split off
+        let mut lagging = KvStateMachine::new(KeyRange::full(1)).with_listener(listener.clone());
+        lagging.restore(sm.last_applied(), &sm.snapshot());
+        let Some(RangeChange::Restore { range: left, split_off }) = changes.lock().unwrap().pop() else {
+            panic!("the restore is not reported");
+        };
+        assert_eq!(left, range(1, "", "c", 1));
+        assert_eq!(split_off.len(), 1);
+        assert_eq!(split_off[0].range, range(2, "c", "", 1));
+
+        // a merge given up serves the keys again
+        apply(&mut restored, KvCommand::PrepareMerge { target: 2 });
+        assert_eq!(restored.query("a"), "");
+        apply(&mut restored, KvCommand::CancelMerge);
+        assert_eq!(restored.query("a"), "\"v\"");
+
+        // the source of a merge takes no write and serves no read once prepared
+        let output = apply(&mut right, KvCommand::PrepareMerge { target: 1 });
+        let prepared: KvSnapshot = serde_json::from_str::<Option<KvSnapshot>>(&output).unwrap().unwrap();
+        assert!(put(&mut right, "e").moved);
+        assert_eq!(right.query("d"), "");
+        assert_eq!(apply(&mut right, KvCommand::PrepareMerge { target: 3 }), "null");
+
+        let merge = KvCommand::CommitMerge {
+            source: prepared.range,
+            data: prepared.data,
+        };
+        let merged: Option<KeyRange> = serde_json::from_str(&apply(&mut sm, merge.clone())).unwrap();
+        assert_eq!(merged, Some(range(1, "", "", 2)));
+        assert_eq!(sm.query("d"), "\"v\"");
+        assert_eq!(right.query("d"), "");
+        assert!(!put(&mut sm, "e").moved);
+        let change = changes.lock().unwrap().pop();
+        assert!(matches!(change, Some(RangeChange::Merge { source, .. }) if source.group == 2));
+
+        // a retried merge succeeds without bringing back the keys it carried
+        let put = KvCommand::Put {
+            key: "d".to_string(),
+            value: "w".to_string(),
+        };
+        apply(&mut sm, put);
+        let merged: Option<KeyRange> = serde_json::from_str(&apply(&mut sm, merge)).unwrap();
+        assert_eq!(merged, Some(range(1, "", "", 2)));
+        assert_eq!(sm.query("d"), "\"w\"");
+        assert!(changes.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    #[should_panic(expected = "is corrupt")]
+    fn test_corrupt_snapshot() {
+        let mut sm = KvStateMachine::new(KeyRange::full(1));
+        sm.restore(LogId { term: 1, index: 1 }, br#"{"k":"v"}"#);
+    }
+}
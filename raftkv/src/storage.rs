@@ -0,0 +1,441 @@
+use std::{
+    fmt::Debug,
+    fs::{self, File, OpenOptions},
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+};
+
+use prost::Message;
+
+use crate::raft::{HardState, Log, Snapshot};
+
+/// the size a log segment file is rotated at, in bytes
+pub const SEGMENT_SIZE: u64 = 4 * 1024 * 1024;
+
+const HARD_STATE_FILE: &str = "hard_state";
+const SNAPSHOT_FILE: &str = "snapshot";
+const SEGMENT_PREFIX: &str = "log-";
+const SEGMENT_SUFFIX: &str = ".seg";
+/// every log record is prefixed with its length and crc32, both little endian u32
+const RECORD_HEADER_SIZE: u64 = 8;
+
+/// The durable backend of a `Store`. Every write must be persistent once the call returns.
+pub trait Storage: Debug + Send + Sync {
+    /// Load everything persisted, it is called once when the store is opened.
+    /// The log may still hold entries covered by the snapshot.
+    fn load(&mut self) -> io::Result<(HardState, Option<Snapshot>, Vec<Log>)>;
+
+    /// Persist the term and the vote.
+    fn save_hard_state(&mut self, hs: &HardState) -> io::Result<()>;
+
+    /// Append entries to the end of the log.
+    fn append(&mut self, logs: &[Log]) -> io::Result<()>;
+
+    /// Remove the entries whose index is `index` or greater.
+    fn truncate(&mut self, index: u64) -> io::Result<()>;
+
+    /// Persist a snapshot, replacing the previous one.
+    fn save_snapshot(&mut self, snapshot: &Snapshot) -> io::Result<()>;
+
+    /// Release the entries whose index is `index` or less, they are covered by the snapshot.
+    /// A backend may keep some of them, they are skipped on load.
+    fn compact(&mut self, index: u64) -> io::Result<()>;
+
+    /// Flush whatever the backend buffers, it is called before the instance stops.
+    fn sync(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// A storage keeping nothing, everything is lost on restart.
+#[derive(Debug, Default)]
+pub struct MemStorage;
+
+impl Storage for MemStorage {
+    fn load(&mut self) -> io::Result<(HardState, Option<Snapshot>, Vec<Log>)> {
+        Ok((HardState::default(), None, Vec::new()))
+    }
+
+    fn save_hard_state(&mut self, _hs: &HardState) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn append(&mut self, _logs: &[Log]) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn truncate(&mut self, _index: u64) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn save_snapshot(&mut self, _snapshot: &Snapshot) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn compact(&mut self, _index: u64) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+struct Segment {
+    /// the index of the first entry in this segment
+    first_index: u64,
+    path: PathBuf,
+    /// the file offset of every record, `offsets[i]` is the entry `first_index + i`
+    offsets: Vec<u64>,
+    size: u64,
+}
+
+/// A storage keeping the log in segmented append-only files, the hard state and the snapshot
+/// in separate files.
+///
+/// A log segment is named after the index of its first entry, e.g. `log-00000000000000000001.seg`,
+/// and holds records of `[len: u32][crc32: u32][Log]`. A torn record left by a crash is detected
+/// by its length or checksum and cut off on recovery.
+#[derive(Debug)]
+pub struct FileStorage {
+    dir: PathBuf,
+    segment_size: u64,
+    segments: Vec<Segment>,
+    /// the last segment, opened for appending
+    active: Option<File>,
+}
+
+impl FileStorage {
+    pub fn open(dir: impl AsRef<Path>) -> io::Result<Self> {
+        Self::with_segment_size(dir, SEGMENT_SIZE)
+    }
+
+    pub fn with_segment_size(dir: impl AsRef<Path>, segment_size: u64) -> io::Result<Self> {
+        fs::create_dir_all(dir.as_ref())?;
+        Ok(FileStorage {
+            dir: dir.as_ref().to_path_buf(),
+            segment_size,
+            segments: Vec::new(),
+            active: None,
+        })
+    }
+
+    fn segment_path(&self, first_index: u64) -> PathBuf {
+        self.dir
+            .join(format!("{}{:020}{}", SEGMENT_PREFIX, first_index, SEGMENT_SUFFIX))
+    }
+
+    /// List the segment files sorted by their first index.
+    fn list_segments(&self) -> io::Result<Vec<(u64, PathBuf)>> {
+        let mut segments = Vec::new();
+        for entry in fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            let name = path.file_name().and_then(|name| name.to_str()).unwrap_or_default();
+            let first_index = name
+                .strip_prefix(SEGMENT_PREFIX)
+                .and_then(|name| name.strip_suffix(SEGMENT_SUFFIX))
+                .and_then(|index| index.parse().ok());
+            if let Some(first_index) = first_index {
+                segments.push((first_index, path));
+            }
+        }
+        segments.sort();
+        Ok(segments)
+    }
+
+    /// Read the valid records of a segment file. It stops at the first torn or corrupted record,
+    /// or at a record that does not continue the log at `next_index`.
+    fn read_segment(path: &Path, mut next_index: u64) -> io::Result<(Vec<Log>, Vec<u64>, u64)> {
+        let mut buf = Vec::new();
+        File::open(path)?.read_to_end(&mut buf)?;
+
+        let (mut logs, mut offsets, mut pos) = (Vec::new(), Vec::new(), 0);
+        while pos + RECORD_HEADER_SIZE <= buf.len() as u64 {
+            let header = &buf[pos as usize..(pos + RECORD_HEADER_SIZE) as usize];
+            let len = u32::from_le_bytes(header[..4].try_into().unwrap()) as u64;
+            let crc = u32::from_le_bytes(header[4..].try_into().unwrap());
+            let start = pos + RECORD_HEADER_SIZE;
+            if start + len > buf.len() as u64 {
+                break;
+            }
+            let payload = &buf[start as usize..(start + len) as usize];
+            if crc32fast::hash(payload) != crc {
+                break;
+            }
+            let Ok(log) = Log::decode(payload) else {
+                break;
+            };
+            if log.id.as_ref().map(|id| id.index) != Some(next_index) {
+                break;
+            }
+            logs.push(log);
+            offsets.push(pos);
+            pos = start + len;
+            next_index += 1;
+        }
+        Ok((logs, offsets, pos))
+    }
+
+    /// Replace the file `name` with `buf`, so a crash leaves either the old or the new content.
+    fn write_atomic(&self, name: &str, buf: &[u8]) -> io::Result<()> {
+        let tmp = self.dir.join(format!("{}.tmp", name));
+        let mut file = File::create(&tmp)?;
+        file.write_all(buf)?;
+        file.sync_all()?;
+        fs::rename(&tmp, self.dir.join(name))?;
+        sync_dir(&self.dir)
+    }
+
+    /// Open the last segment for appending, creating a new one starting at `first_index` if
+    /// there is none or the last one is full.
+    fn prepare_active(&mut self, first_index: u64) -> io::Result<()> {
+        let full = self.segments.last().is_none_or(|seg| seg.size >= self.segment_size);
+        if full {
+            self.active = None;
+            let path = self.segment_path(first_index);
+            File::create(&path)?;
+            sync_dir(&self.dir)?;
+            self.segments.push(Segment {
+                first_index,
+                path,
+                offsets: Vec::new(),
+                size: 0,
+            });
+        }
+        if self.active.is_none() {
+            let seg = self.segments.last().unwrap();
+            self.active = Some(OpenOptions::new().append(true).open(&seg.path)?);
+        }
+        Ok(())
+    }
+}
+
+impl Storage for FileStorage {
+    fn load(&mut self) -> io::Result<(HardState, Option<Snapshot>, Vec<Log>)> {
+        let hs = read_message::<HardState>(&self.dir.join(HARD_STATE_FILE))?.unwrap_or_default();
+        let snapshot = read_message::<Snapshot>(&self.dir.join(SNAPSHOT_FILE))?;
+
+        self.segments.clear();
+        self.active = None;
+        let mut logs = Vec::new();
+        let mut torn = false;
+        for (first_index, path) in self.list_segments()? {
+            // nothing after a torn record can be trusted
+            let next_index = logs
+                .last()
+                .map_or(first_index, |log: &Log| log.id.as_ref().unwrap().index + 1);
+            if torn || first_index != next_index {
+                torn = true;
+                fs::remove_file(&path)?;
+                continue;
+            }
+            let (mut seg_logs, offsets, size) = Self::read_segment(&path, first_index)?;
+            if size < fs::metadata(&path)?.len() {
+                torn = true;
+                let file = OpenOptions::new().write(true).open(&path)?;
+                file.set_len(size)?;
+                file.sync_all()?;
+            }
+            logs.append(&mut seg_logs);
+            self.segments.push(Segment {
+                first_index,
+                path,
+                offsets,
+                size,
+            });
+        }
+        sync_dir(&self.dir)?;
+        Ok((hs, snapshot, logs))
+    }
+
+    fn save_hard_state(&mut self, hs: &HardState) -> io::Result<()> {
+        self.write_atomic(HARD_STATE_FILE, &hs.encode_to_vec())
+    }
+
+    fn append(&mut self, logs: &[Log]) -> io::Result<()> {
+        for log in logs {
+            let index = log.id.as_ref().unwrap().index;
+            self.prepare_active(index)?;
+            let payload = log.encode_to_vec();
+            let mut record = Vec::with_capacity(RECORD_HEADER_SIZE as usize + payload.len());
+            record.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+            record.extend_from_slice(&crc32fast::hash(&payload).to_le_bytes());
+            record.extend_from_slice(&payload);
+            self.active.as_mut().unwrap().write_all(&record)?;
+
+            let seg = self.segments.last_mut().unwrap();
+            seg.offsets.push(seg.size);
+            seg.size += record.len() as u64;
+            if seg.size >= self.segment_size {
+                self.active.take().unwrap().sync_data()?;
+            }
+        }
+        if let Some(active) = self.active.as_ref() {
+            active.sync_data()?;
+        }
+        Ok(())
+    }
+
+    fn truncate(&mut self, index: u64) -> io::Result<()> {
+        self.active = None;
+        while let Some(seg) = self.segments.last_mut() {
+            if seg.first_index >= index {
+                fs::remove_file(&seg.path)?;
+                self.segments.pop();
+                continue;
+            }
+            let keep = (index - seg.first_index) as usize;
+            if keep < seg.offsets.len() {
+                seg.size = seg.offsets[keep];
+                seg.offsets.truncate(keep);
+                let file = OpenOptions::new().write(true).open(&seg.path)?;
+                file.set_len(seg.size)?;
+                file.sync_all()?;
+            }
+            break;
+        }
+        sync_dir(&self.dir)
+    }
+
+    fn save_snapshot(&mut self, snapshot: &Snapshot) -> io::Result<()> {
+        self.write_atomic(SNAPSHOT_FILE, &snapshot.encode_to_vec())
+    }
+
+    fn compact(&mut self, index: u64) -> io::Result<()> {
+        // only whole segments are removed, the one holding `index + 1` is kept
+        let mut removed = 0;
+        while removed + 1 < self.segments.len() && self.segments[removed + 1].first_index <= index + 1 {
+            fs::remove_file(&self.segments[removed].path)?;
+            removed += 1;
+        }
+        self.segments.drain(..removed);
+        sync_dir(&self.dir)
+    }
+
+    fn sync(&mut self) -> io::Result<()> {
+        if let Some(active) = self.active.take() {
+            active.sync_all()?;
+        }
+        sync_dir(&self.dir)
+    }
+}
+
+/// Read a protobuf message from `path`, `None` if the file does not exist.
+fn read_message<M: Message + Default>(path: &Path) -> io::Result<Option<M>> {
+    match fs::read(path) {
+        Ok(buf) => M::decode(buf.as_slice())
+            .map(Some)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Make the creation, removal and renaming of files in `dir` durable.
+fn sync_dir(dir: &Path) -> io::Result<()> {
+    File::open(dir)?.sync_all()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::raft::LogId;
+
+    fn log(index: u64) -> Log {
+        Log {
+            id: Some(LogId { term: 1, index }),
+            data: format!("data-{}", index).into_bytes(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_file_storage_recovery() {
+        let dir = tempfile::tempdir().unwrap();
+        let hs = HardState {
+            term: 3,
+            voted_for: Some(2),
+        };
+        {
+            let mut sto = FileStorage::with_segment_size(dir.path(), 128).unwrap();
+            assert_eq!(sto.load().unwrap(), (HardState::default(), None, vec![]));
+            sto.save_hard_state(&hs).unwrap();
+            sto.append(&(1..=20).map(log).collect::<Vec<_>>()).unwrap();
+            sto.truncate(16).unwrap();
+            sto.append(&[log(16)]).unwrap();
+        }
+        assert!(FileStorage::open(dir.path()).unwrap().list_segments().unwrap().len() > 1);
+
+        let mut sto = FileStorage::open(dir.path()).unwrap();
+        assert_eq!(sto.load().unwrap(), (hs, None, (1..=16).map(log).collect()));
+        sto.append(&[log(17)]).unwrap();
+        let (_, _, logs) = FileStorage::open(dir.path()).unwrap().load().unwrap();
+        assert_eq!(logs, (1..=17).map(log).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_file_storage_compaction() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut sto = FileStorage::with_segment_size(dir.path(), 128).unwrap();
+        sto.load().unwrap();
+        sto.append(&(1..=20).map(log).collect::<Vec<_>>()).unwrap();
+        let snapshot = Snapshot {
+            last_log_id: log(12).id,
+            data: b"state".to_vec(),
+            ..Default::default()
+        };
+        sto.save_snapshot(&snapshot).unwrap();
+        sto.compact(12).unwrap();
+        let first = sto.segments[0].first_index;
+        assert!(first > 1 && first <= 13);
+
+        let (_, loaded, logs) = FileStorage::open(dir.path()).unwrap().load().unwrap();
+        assert_eq!(loaded, Some(snapshot));
+        assert_eq!(logs, (first..=20).map(log).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_file_storage_crash_mid_write() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut sto = FileStorage::open(dir.path()).unwrap();
+        sto.load().unwrap();
+        sto.append(&(1..=5).map(log).collect::<Vec<_>>()).unwrap();
+        let seg = sto.segments[0].path.clone();
+        let intact = fs::read(&seg).unwrap();
+        sto.append(&[log(6)]).unwrap();
+        let full = fs::read(&seg).unwrap();
+        drop(sto);
+
+        // the node is killed at every possible point while writing the 6th entry
+        for cut in intact.len()..full.len() {
+            fs::write(&seg, &full[..cut]).unwrap();
+            let mut sto = FileStorage::open(dir.path()).unwrap();
+            let (_, _, logs) = sto.load().unwrap();
+            assert_eq!(logs, (1..=5).map(log).collect::<Vec<_>>(), "cut at {}", cut);
+            assert_eq!(fs::read(&seg).unwrap(), intact);
+
+            // the log keeps working after recovery
+            sto.append(&[log(6)]).unwrap();
+            assert_eq!(fs::read(&seg).unwrap(), full);
+        }
+
+        // a corrupted record is cut off as well
+        let mut corrupted = full.clone();
+        *corrupted.last_mut().unwrap() ^= 0xff;
+        fs::write(&seg, &corrupted).unwrap();
+        let (_, _, logs) = FileStorage::open(dir.path()).unwrap().load().unwrap();
+        assert_eq!(logs, (1..=5).map(log).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_file_storage_crash_while_saving_hard_state() {
+        let dir = tempfile::tempdir().unwrap();
+        let hs = HardState {
+            term: 1,
+            voted_for: Some(0),
+        };
+        FileStorage::open(dir.path()).unwrap().save_hard_state(&hs).unwrap();
+        // a crash before the rename leaves a partial temporary file behind
+        fs::write(dir.path().join("hard_state.tmp"), [0xff, 0xff]).unwrap();
+
+        let (loaded, _, _) = FileStorage::open(dir.path()).unwrap().load().unwrap();
+        assert_eq!(loaded, hs);
+    }
+}
@@ -0,0 +1,278 @@
+use std::{collections::HashMap, convert::Infallible, future::Future, net::SocketAddr};
+
+use hyper::{
+    header::CONTENT_TYPE,
+    service::{make_service_fn, service_fn},
+    Body, Method, Request, Response, Server, StatusCode,
+};
+use prometheus::{
+    proto::MetricFamily, Encoder, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge, IntGaugeVec, Opts,
+    Registry, TextEncoder,
+};
+
+use crate::node::{RaftMetrics, Role};
+
+/// The Prometheus metrics of a raft instance, in a registry of its own labelled with the instance id
+/// and its group, so that the instances of a process do not mix. A clone shares the metrics.
+#[derive(Debug, Clone)]
+pub struct Telemetry {
+    registry: Registry,
+    /// the events handled by the core, including the responses of its own rpcs
+    pub events: IntCounter,
+    pub pre_votes_started: IntCounter,
+    pub elections_started: IntCounter,
+    pub elections_won: IntCounter,
+    pub term_changes: IntCounter,
+    /// the vote requests received, labelled by whether the vote is granted
+    pub votes: IntCounterVec,
+    /// the append log rpcs received from the leader, labelled by whether they are accepted
+    pub append_log_received: IntCounterVec,
+    /// the append log rpcs sent to a follower
+    pub append_log_sent: IntCounterVec,
+    /// the snapshots sent to a follower lagging behind the compacted log
+    pub snapshots_sent: IntCounterVec,
+    /// the round trip time of the append log and install snapshot rpcs to a follower
+    pub append_log_latency: HistogramVec,
+    /// the rpcs to a follower that failed to get a response
+    pub append_log_failures: IntCounterVec,
+    /// the rpcs a follower rejected as its log does not match
+    pub append_log_rejections: IntCounterVec,
+    pub applied_entries: IntCounter,
+    pub term: IntGauge,
+    pub is_leader: IntGauge,
+    pub last_log_index: IntGauge,
+    /// the entries kept in the log, i.e. not covered by the snapshot
+    pub log_entries: IntGauge,
+    pub commit_index: IntGauge,
+    pub applied_index: IntGauge,
+    /// the entries of the log that are not committed yet
+    pub commit_lag: IntGauge,
+    /// the entries committed but not applied yet
+    pub apply_lag: IntGauge,
+    /// the entries a follower is missing, only on the leader
+    pub replication_lag: IntGaugeVec,
+}
+
+impl Telemetry {
+    pub fn new(id: u32, group: u64) -> Self {
+        let labels = HashMap::from([
+            ("node".to_string(), id.to_string()),
+            ("group".to_string(), group.to_string()),
+        ]);
+        let registry = Registry::new_custom(None, Some(labels)).unwrap();
+        let counter = |name: &str, help: &str| {
+            let counter = IntCounter::new(name, help).unwrap();
+            registry.register(Box::new(counter.clone())).unwrap();
+            counter
+        };
+        let counter_vec = |name: &str, help: &str, label: &str| {
+            let counter = IntCounterVec::new(Opts::new(name, help), &[label]).unwrap();
+            registry.register(Box::new(counter.clone())).unwrap();
+            counter
+        };
+        let gauge = |name: &str, help: &str| {
+            let gauge = IntGauge::new(name, help).unwrap();
+            registry.register(Box::new(gauge.clone())).unwrap();
+            gauge
+        };
+        let append_log_latency = HistogramVec::new(
+            HistogramOpts::new(
+                "raft_append_log_duration_seconds",
+                "The round trip time of the append log rpcs to a follower",
+            ),
+            &["peer"],
+        )
+        .unwrap();
+        registry.register(Box::new(append_log_latency.clone())).unwrap();
+        let replication_lag =
+            IntGaugeVec::new(Opts::new("raft_replication_lag", "The log entries a follower is missing"), &["peer"])
+                .unwrap();
+        registry.register(Box::new(replication_lag.clone())).unwrap();
+
+        Telemetry {
+            events: counter("raft_events_total", "The events handled by the core"),
+            pre_votes_started: counter("raft_pre_votes_started_total", "The pre-vote rounds started"),
+            elections_started: counter("raft_elections_started_total", "The elections started as a candidate"),
+            elections_won: counter("raft_elections_won_total", "The elections won"),
+            term_changes: counter("raft_term_changes_total", "The times the term increased"),
+            votes: counter_vec("raft_votes_total", "The vote requests received", "granted"),
+            append_log_received: counter_vec(
+                "raft_append_log_received_total",
+                "The append log rpcs received from the leader",
+                "success",
+            ),
+            append_log_sent: counter_vec("raft_append_log_sent_total", "The append log rpcs sent", "peer"),
+            snapshots_sent: counter_vec("raft_snapshots_sent_total", "The snapshots sent to a follower", "peer"),
+            append_log_latency,
+            append_log_failures: counter_vec(
+                "raft_append_log_failures_total",
+                "The append log rpcs that failed to get a response",
+                "peer",
+            ),
+            append_log_rejections: counter_vec(
+                "raft_append_log_rejections_total",
+                "The append log rpcs rejected for a log mismatch",
+                "peer",
+            ),
+            applied_entries: counter("raft_applied_entries_total", "The log entries applied to the state machine"),
+            term: gauge("raft_term", "The current term"),
+            is_leader: gauge("raft_is_leader", "Whether this instance is the leader"),
+            last_log_index: gauge("raft_last_log_index", "The index of the last log entry"),
+            log_entries: gauge("raft_log_entries", "The log entries not covered by the snapshot"),
+            commit_index: gauge("raft_commit_index", "The index of the last committed entry"),
+            applied_index: gauge("raft_applied_index", "The index of the last applied entry"),
+            commit_lag: gauge("raft_commit_lag", "The log entries not committed yet"),
+            apply_lag: gauge("raft_apply_lag", "The entries committed but not applied yet"),
+            replication_lag,
+            registry,
+        }
+    }
+
+    /// Update the gauges with the metrics published by the core, `prev` is the previous one.
+    pub(crate) fn observe(&self, prev: &RaftMetrics, metrics: &RaftMetrics) {
+        if metrics.term > prev.term {
+            self.term_changes.inc();
+        }
+        self.applied_entries
+            .inc_by(metrics.applied.index.saturating_sub(prev.applied.index));
+        let last = metrics.last_log_id.index;
+        self.term.set(metrics.term as i64);
+        self.is_leader.set((metrics.role == Role::Leader) as i64);
+        self.last_log_index.set(last as i64);
+        self.log_entries.set((last - metrics.snapshot_last.index) as i64);
+        self.commit_index.set(metrics.commit as i64);
+        self.applied_index.set(metrics.applied.index as i64);
+        self.commit_lag.set(last.saturating_sub(metrics.commit) as i64);
+        self.apply_lag
+            .set(metrics.commit.saturating_sub(metrics.applied.index) as i64);
+        self.replication_lag.reset();
+        for (peer, matched) in metrics.matched.iter() {
+            self.replication_lag
+                .with_label_values(&[&peer.to_string()])
+                .set(last.saturating_sub(matched.index) as i64);
+        }
+    }
+
+    /// Encode every metric in the Prometheus text format.
+    pub fn gather(&self) -> String {
+        Self::gather_all(std::slice::from_ref(self))
+    }
+
+    /// Encode the metrics of several instances, e.g. the groups of a process, as one exposition.
+    /// The samples of the same metric go under a single family, told apart by their labels.
+    pub fn gather_all(telemetries: &[Telemetry]) -> String {
+        let mut families: Vec<MetricFamily> = Vec::new();
+        for mut family in telemetries.iter().flat_map(|telemetry| telemetry.registry.gather()) {
+            // the labels of the registry come in no particular order
+            for metric in family.mut_metric().iter_mut() {
+                metric.mut_label().sort_by(|a, b| a.get_name().cmp(b.get_name()));
+            }
+            match families.iter_mut().find(|known| known.get_name() == family.get_name()) {
+                Some(known) => known.mut_metric().extend(family.get_metric().iter().cloned()),
+                None => families.push(family),
+            }
+        }
+        let mut buf = Vec::new();
+        TextEncoder::new().encode(&families, &mut buf).unwrap();
+        String::from_utf8(buf).unwrap()
+    }
+
+    /// Serve the metrics at `/metrics` on `addr` over HTTP until `signal` completes.
+    pub async fn serve(self, addr: SocketAddr, signal: impl Future<Output = ()>) -> Result<(), hyper::Error> {
+        serve(addr, signal, move || self.gather()).await
+    }
+}
+
+/// Serve the exposition returned by `gather` at `/metrics` on `addr` over HTTP until `signal` completes.
+pub async fn serve<F>(addr: SocketAddr, signal: impl Future<Output = ()>, gather: F) -> Result<(), hyper::Error>
+where
+    F: Fn() -> String + Clone + Send + Sync + 'static,
+{
+    let make_service = make_service_fn(move |_| {
+        let gather = gather.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |req| {
+                let gather = gather.clone();
+                async move { Ok::<_, Infallible>(respond(req, gather)) }
+            }))
+        }
+    });
+    Server::try_bind(&addr)?
+        .serve(make_service)
+        .with_graceful_shutdown(signal)
+        .await
+}
+
+fn respond(req: Request<Body>, gather: impl Fn() -> String) -> Response<Body> {
+    if req.method() != Method::GET || req.uri().path() != "/metrics" {
+        let mut resp = Response::new(Body::from("not found"));
+        *resp.status_mut() = StatusCode::NOT_FOUND;
+        return resp;
+    }
+    let mut resp = Response::new(Body::from(gather()));
+    let content_type = TextEncoder::new().format_type().parse().unwrap();
+    resp.headers_mut().insert(CONTENT_TYPE, content_type);
+    resp
+}
+
+#[cfg(test)]
+mod test {
+    use core::time;
+
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use super::*;
+    use crate::node::{Raft, Timeouts};
+    use crate::state_machine::KvCommand;
+    use crate::test_util::spawn_scheduler;
+
+    #[tokio::test]
+    async fn test_serve_metrics() {
+        let timeouts = Timeouts {
+            election: (100, 200),
+            heartbeat: 20,
+        };
+        let node = Raft::new(0, "127.0.0.1:19221".to_string()).with_timeouts(timeouts);
+        spawn_scheduler(&node);
+        let mut metrics = node.subscribe();
+        let leader = metrics.wait_for(|m| m.role == Role::Leader);
+        tokio::time::timeout(time::Duration::from_millis(timeouts.election.1 * 2), leader)
+            .await
+            .unwrap()
+            .unwrap();
+        for i in 0..3 {
+            let cmd = KvCommand::Put {
+                key: format!("key-{}", i),
+                value: "value".to_string(),
+            };
+            let (_, rx) = node.propose(cmd.encode()).await.unwrap();
+            rx.await.unwrap();
+        }
+
+        let addr: SocketAddr = "127.0.0.1:19222".parse().unwrap();
+        let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+        let server = tokio::spawn(Raft::serve_metrics(node.clone(), addr, async {
+            let _ = stopped.await;
+        }));
+        tokio::time::sleep(time::Duration::from_millis(100)).await;
+        let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        stream.write_all(b"GET /metrics HTTP/1.0\r\n\r\n").await.unwrap();
+        let mut resp = String::new();
+        stream.read_to_string(&mut resp).await.unwrap();
+
+        assert!(resp.starts_with("HTTP/1.0 200 OK"));
+        for line in [
+            "raft_elections_started_total{group=\"0\",node=\"0\"} 1",
+            "raft_elections_won_total{group=\"0\",node=\"0\"} 1",
+            "raft_term{group=\"0\",node=\"0\"} 1",
+            "raft_is_leader{group=\"0\",node=\"0\"} 1",
+            // the no-op entry of the leader and the proposals
+            "raft_applied_entries_total{group=\"0\",node=\"0\"} 4",
+            "raft_commit_lag{group=\"0\",node=\"0\"} 0",
+        ] {
+            assert!(resp.contains(line), "{} is missing in\n{}", line, resp);
+        }
+        stop.send(()).unwrap();
+        server.await.unwrap().unwrap();
+    }
+}
@@ -0,0 +1,133 @@
+//! The fixtures shared by the tests of the modules.
+use core::time;
+use std::io;
+use std::sync::Arc;
+
+use tokio::task::JoinHandle;
+
+use crate::multi::{GroupFactory, KeyRange, MultiRaft};
+use crate::node::{Raft, Role, Store, Timeouts, ELECTION_TIMEOUT, TICK_INTERVAL};
+
+/// The timeouts of the groups in the multi-raft clusters, short enough for a few elections in a test.
+pub(crate) const GROUP_TIMEOUTS: Timeouts = Timeouts {
+    election: (300, 600),
+    heartbeat: 50,
+};
+
+/// Start the scheduler of `node` on the current runtime.
+pub(crate) fn spawn_scheduler(node: &Raft) -> JoinHandle<()> {
+    let sch = node.clone();
+    tokio::spawn(async move { sch.scheduler().await })
+}
+
+/// Serve the rpcs of every node on its address in the peers, and start its scheduler.
+pub(crate) fn start(nodes: &[Raft]) {
+    for node in nodes.iter() {
+        tokio::spawn(Raft::run(node.clone()));
+        spawn_scheduler(node);
+    }
+}
+
+/// Start a cluster of the instances listening on the comma separated `peers`, with the ids in that order.
+pub(crate) fn start_cluster(peers: &str) -> Vec<Raft> {
+    let nodes: Vec<Raft> = (0..peers.split(',').count())
+        .map(|id| Raft::new(id as u32, peers.to_string()))
+        .collect();
+    start(&nodes);
+    nodes
+}
+
+/// The leader of the highest term, a stale one may not have noticed yet.
+pub(crate) fn find_leader(nodes: &[Raft]) -> Option<Raft> {
+    nodes
+        .iter()
+        .filter(|node| node.metrics().role == Role::Leader)
+        .max_by_key(|node| node.metrics().term)
+        .cloned()
+}
+
+/// Poll the nodes every tick until one of them leads, the test fails if none is elected within
+/// a few election timeouts.
+pub(crate) async fn wait_for_leader(nodes: &[Raft]) -> Raft {
+    let elected = async {
+        loop {
+            tokio::time::sleep(time::Duration::from_millis(TICK_INTERVAL)).await;
+            if let Some(leader) = find_leader(nodes) {
+                return leader;
+            }
+        }
+    };
+    tokio::time::timeout(time::Duration::from_millis(ELECTION_TIMEOUT.1 * 5), elected)
+        .await
+        .expect("no leader is elected in time")
+}
+
+/// Keeps the groups of the multi-raft clusters in memory, with `GROUP_TIMEOUTS`.
+#[derive(Debug)]
+pub(crate) struct TestGroups;
+
+impl GroupFactory for TestGroups {
+    fn open(&self, id: u32, _group: u64) -> io::Result<Store> {
+        Ok(Store::new(id))
+    }
+
+    fn configure(&self, raft: Raft) -> Raft {
+        raft.with_timeouts(GROUP_TIMEOUTS)
+    }
+}
+
+/// A process for each of `addrs`, with the ids in that order, hosting an instance of the group of every
+/// range in `ranges` configured by `configure`. The ranges can be split and merged, the schedulers of the
+/// instances are started but the rpcs are not served yet.
+pub(crate) fn build_multi_cluster(
+    addrs: &[&str],
+    ranges: &[KeyRange],
+    configure: impl Fn(Raft) -> Raft,
+) -> Vec<MultiRaft> {
+    let peers = addrs.join(",");
+    let mut nodes = Vec::new();
+    for id in 0..addrs.len() as u32 {
+        let multi = MultiRaft::new(id).with_factory(Arc::new(TestGroups));
+        for range in ranges {
+            let sm = multi.state_machine(range.clone());
+            let raft = Raft::with_store(id, peers.clone(), Store::new(id), sm).with_timeouts(GROUP_TIMEOUTS);
+            spawn_scheduler(&multi.add_group(range.clone(), configure(raft)).unwrap());
+        }
+        nodes.push(multi);
+    }
+    nodes
+}
+
+/// Start the processes of `build_multi_cluster`, serving the rpcs of each on its address.
+pub(crate) fn start_multi_cluster(addrs: &[&str], ranges: &[KeyRange]) -> Vec<MultiRaft> {
+    let nodes = build_multi_cluster(addrs, ranges, |raft| raft);
+    for (node, addr) in nodes.iter().zip(addrs) {
+        tokio::spawn(MultiRaft::serve(node.clone(), addr.parse().unwrap(), std::future::pending()));
+    }
+    nodes
+}
+
+/// Poll the processes every tick until `f` finds what it looks for, the test fails if it is not found
+/// within a few election timeouts of the groups.
+pub(crate) async fn wait_for<T>(nodes: &[MultiRaft], f: impl Fn(&[MultiRaft]) -> Option<T>) -> T {
+    let found = async {
+        loop {
+            tokio::time::sleep(time::Duration::from_millis(TICK_INTERVAL)).await;
+            if let Some(found) = f(nodes) {
+                return found;
+            }
+        }
+    };
+    tokio::time::timeout(time::Duration::from_millis(GROUP_TIMEOUTS.election.1 * 5), found)
+        .await
+        .expect("the processes do not get there in time")
+}
+
+/// The index of the process leading `group`, once one is elected.
+pub(crate) async fn wait_for_group_leader(nodes: &[MultiRaft], group: u64) -> usize {
+    let is_leader = |node: &MultiRaft| {
+        node.group(group)
+            .is_some_and(|raft| raft.metrics().role == Role::Leader)
+    };
+    wait_for(nodes, |nodes| nodes.iter().position(is_leader)).await
+}
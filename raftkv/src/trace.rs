@@ -0,0 +1,143 @@
+use std::{fmt, future::Future, str::FromStr};
+
+use clap::ValueEnum;
+use serde::Deserialize;
+use tonic::{metadata::MetadataValue, Request};
+use tracing_subscriber::EnvFilter;
+
+/// the metadata key the trace context is carried in, in the format of the W3C `traceparent` header
+pub const TRACEPARENT: &str = "traceparent";
+
+tokio::task_local! {
+    /// the context of the rpcs sent by the current task
+    static CURRENT: TraceContext;
+}
+
+/// The context of a trace that spans several nodes, e.g. an election round and the votes it asks
+/// for. The spans on every node record the same `trace_id`, so the logs of a cluster can be joined
+/// by it, and a span receiving an rpc records the `span_id` of the sender as its parent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TraceContext {
+    pub trace_id: u128,
+    pub span_id: u64,
+}
+
+impl TraceContext {
+    /// The context of a new trace.
+    pub fn root() -> Self {
+        TraceContext {
+            trace_id: rand::random(),
+            span_id: rand::random(),
+        }
+    }
+
+    /// The context of a span within the same trace.
+    pub fn child(&self) -> Self {
+        TraceContext {
+            trace_id: self.trace_id,
+            span_id: rand::random(),
+        }
+    }
+
+    pub fn trace_id(&self) -> String {
+        format!("{:032x}", self.trace_id)
+    }
+
+    pub fn span_id(&self) -> String {
+        format!("{:016x}", self.span_id)
+    }
+
+    /// The context set by `scope` for the current task.
+    pub fn current() -> Option<Self> {
+        CURRENT.try_with(|ctx| *ctx).ok()
+    }
+
+    /// Run `f` with this context, the rpcs it sends carry a child of it.
+    pub async fn scope<F: Future>(self, f: F) -> F::Output {
+        CURRENT.scope(self, f).await
+    }
+
+    /// Add a child of the current context to the metadata of an outgoing `request`.
+    pub fn inject<T>(request: &mut Request<T>) {
+        if let Some(ctx) = Self::current() {
+            let value = MetadataValue::try_from(ctx.child().to_string()).unwrap();
+            request.metadata_mut().insert(TRACEPARENT, value);
+        }
+    }
+
+    /// The context carried by an incoming `request`, if any.
+    pub fn extract<T>(request: &Request<T>) -> Option<Self> {
+        request.metadata().get(TRACEPARENT)?.to_str().ok()?.parse().ok()
+    }
+}
+
+impl fmt::Display for TraceContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "00-{}-{}-01", self.trace_id(), self.span_id())
+    }
+}
+
+impl FromStr for TraceContext {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid trace context {:?}", s);
+        let parts: Vec<&str> = s.split('-').collect();
+        let [_version, trace_id, span_id, _flags] = parts[..] else {
+            return Err(invalid());
+        };
+        Ok(TraceContext {
+            trace_id: u128::from_str_radix(trace_id, 16).map_err(|_| invalid())?,
+            span_id: u64::from_str_radix(span_id, 16).map_err(|_| invalid())?,
+        })
+    }
+}
+
+/// How the log lines are written.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// one line per event, with the fields of the spans it is in
+    #[default]
+    Text,
+    /// several indented lines per event, easier to read by a human
+    Pretty,
+    /// one JSON object per event, with the current span and the list of spans it is in
+    Json,
+}
+
+/// Install the global subscriber writing the events that pass `filter` to stdout in `format`.
+pub fn init_subscriber(filter: &str, format: LogFormat) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let builder = tracing_subscriber::fmt().with_env_filter(EnvFilter::try_new(filter)?);
+    match format {
+        LogFormat::Text => builder.try_init(),
+        LogFormat::Pretty => builder.pretty().try_init(),
+        LogFormat::Json => builder.json().with_current_span(true).with_span_list(true).try_init(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_propagate_trace_context() {
+        let ctx = TraceContext::root();
+        assert_eq!(ctx.to_string().parse(), Ok(ctx));
+        assert!("00-xyz-01".parse::<TraceContext>().is_err());
+
+        // nothing is injected outside of a scope
+        let mut request = Request::new(());
+        TraceContext::inject(&mut request);
+        assert_eq!(TraceContext::extract(&request), None);
+
+        let sent = ctx.scope(async {
+            let mut request = Request::new(());
+            TraceContext::inject(&mut request);
+            request
+        });
+        let received = TraceContext::extract(&sent.await).unwrap();
+        assert_eq!(received.trace_id, ctx.trace_id);
+        assert_ne!(received.span_id, ctx.span_id);
+    }
+}
@@ -0,0 +1,336 @@
+use core::time;
+use std::{
+    collections::HashMap,
+    fmt::Debug,
+    result::Result,
+    sync::{Arc, Mutex},
+};
+
+use tokio::sync::oneshot;
+use tonic::transport::Channel;
+
+use crate::peers::{ConnectionPolicy, PeerPool};
+use crate::raft::{raft_client::RaftClient, *};
+
+pub type RpcError = Box<dyn std::error::Error + Send + Sync>;
+
+/// How a raft instance reaches its peers. Every rpc goes to the peer listening on `addr`,
+/// and fails once it has not completed within the deadline of the transport.
+#[tonic::async_trait]
+pub trait Transport: Debug + Send + Sync {
+    async fn elect(&self, addr: String, request: ElectRequest) -> Result<ElectResponse, RpcError>;
+
+    async fn pre_vote(&self, addr: String, request: ElectRequest) -> Result<ElectResponse, RpcError>;
+
+    async fn append_log(&self, addr: String, request: AppendLogRequest) -> Result<AppendLogResponse, RpcError>;
+
+    /// Send the chunks of a snapshot in order, the last one has `done` set.
+    async fn install_snapshot(
+        &self,
+        addr: String,
+        chunks: Vec<InstallSnapshotRequest>,
+    ) -> Result<InstallSnapshotResponse, RpcError>;
+
+    async fn timeout_now(&self, addr: String, request: TimeoutNowRequest) -> Result<TimeoutNowResponse, RpcError>;
+
+    /// Ask the leader listening on `addr` for a read index on behalf of a learner.
+    async fn read_index(&self, addr: String, request: ReadIndexRequest) -> Result<ReadIndexResponse, RpcError>;
+
+    /// Send the heartbeats of several groups to the peer listening on `addr` together. The results are
+    /// in the order of `requests`, a heartbeat the peer fails to handle gets the reason instead.
+    async fn heartbeat(
+        &self,
+        addr: String,
+        requests: Vec<AppendLogRequest>,
+    ) -> Result<Vec<Result<AppendLogResponse, String>>, RpcError> {
+        let mut results = Vec::with_capacity(requests.len());
+        for request in requests {
+            results.push(self.append_log(addr.clone(), request).await.map_err(|e| e.to_string()));
+        }
+        Ok(results)
+    }
+
+    /// The health of the connection to the peer listening on `addr`, if the transport keeps track of it.
+    fn peer_health(&self, _addr: &str) -> Option<PeerHealth> {
+        None
+    }
+}
+
+/// The transport of a real cluster. The rpcs to a peer share a long-lived gRPC channel of a `PeerPool`,
+/// which fails them fast while the peer is unreachable and reconnects after a backoff. A clone shares
+/// the channels.
+#[derive(Debug, Clone, Default)]
+pub struct GrpcTransport {
+    pool: PeerPool,
+}
+
+impl GrpcTransport {
+    pub fn new(policy: ConnectionPolicy) -> Self {
+        GrpcTransport {
+            pool: PeerPool::new(policy),
+        }
+    }
+
+    /// The deadline of a single rpc.
+    fn deadline(&self) -> u64 {
+        self.pool.policy().rpc_timeout
+    }
+}
+
+#[tonic::async_trait]
+impl Transport for GrpcTransport {
+    async fn elect(&self, addr: String, request: ElectRequest) -> Result<ElectResponse, RpcError> {
+        let rpc = |mut client: RaftClient<Channel>, request| async move { client.elect(request).await };
+        self.pool.call(&addr, self.deadline(), request, rpc).await
+    }
+
+    async fn pre_vote(&self, addr: String, request: ElectRequest) -> Result<ElectResponse, RpcError> {
+        let rpc = |mut client: RaftClient<Channel>, request| async move { client.pre_vote(request).await };
+        self.pool.call(&addr, self.deadline(), request, rpc).await
+    }
+
+    async fn append_log(&self, addr: String, request: AppendLogRequest) -> Result<AppendLogResponse, RpcError> {
+        let rpc = |mut client: RaftClient<Channel>, request| async move { client.append_log(request).await };
+        self.pool.call(&addr, self.deadline(), request, rpc).await
+    }
+
+    async fn install_snapshot(
+        &self,
+        addr: String,
+        chunks: Vec<InstallSnapshotRequest>,
+    ) -> Result<InstallSnapshotResponse, RpcError> {
+        // a snapshot may take many chunks, the deadline is per chunk
+        let deadline = self.deadline() * chunks.len() as u64;
+        let rpc = |mut client: RaftClient<Channel>, request| async move { client.install_snapshot(request).await };
+        self.pool.call(&addr, deadline, tokio_stream::iter(chunks), rpc).await
+    }
+
+    async fn timeout_now(&self, addr: String, request: TimeoutNowRequest) -> Result<TimeoutNowResponse, RpcError> {
+        let rpc = |mut client: RaftClient<Channel>, request| async move { client.timeout_now(request).await };
+        self.pool.call(&addr, self.deadline(), request, rpc).await
+    }
+
+    async fn read_index(&self, addr: String, request: ReadIndexRequest) -> Result<ReadIndexResponse, RpcError> {
+        // the leader answers after a heartbeat round of its own
+        let rpc = |mut client: RaftClient<Channel>, request| async move { client.read_index(request).await };
+        self.pool.call(&addr, self.deadline() * 2, request, rpc).await
+    }
+
+    async fn heartbeat(
+        &self,
+        addr: String,
+        requests: Vec<AppendLogRequest>,
+    ) -> Result<Vec<Result<AppendLogResponse, String>>, RpcError> {
+        let request = HeartbeatRequest { heartbeats: requests };
+        let rpc = |mut client: RaftClient<Channel>, request| async move { client.heartbeat(request).await };
+        let resp = self.pool.call(&addr, self.deadline(), request, rpc).await?;
+        Ok(resp
+            .results
+            .into_iter()
+            .map(|result| result.response.ok_or(result.error))
+            .collect())
+    }
+
+    fn peer_health(&self, addr: &str) -> Option<PeerHealth> {
+        self.pool.health(addr)
+    }
+}
+
+/// heartbeats waiting to go out to a peer together, with where their responses go
+type Batch = Vec<(AppendLogRequest, oneshot::Sender<Result<AppendLogResponse, String>>)>;
+
+/// A transport shared by the groups of a `MultiRaft`. The heartbeats, i.e. the append log rpcs without
+/// entries, sent to the same peer within a window go out in one heartbeat rpc, so the traffic of idle
+/// groups does not grow with their number. Everything else is passed to the inner transport as is.
+#[derive(Debug)]
+pub struct CoalescingTransport {
+    inner: Arc<dyn Transport>,
+    /// how long the first heartbeat of a batch waits for the others, in milliseconds
+    window: u64,
+    batches: Arc<Mutex<HashMap<String, Batch>>>,
+}
+
+impl CoalescingTransport {
+    pub fn new(inner: Arc<dyn Transport>, window: u64) -> Self {
+        CoalescingTransport {
+            inner,
+            window,
+            batches: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Send the batch of `addr` once the window has passed, and hand every heartbeat its result.
+    fn flush_later(&self, addr: String) {
+        let (inner, batches, window) = (self.inner.clone(), self.batches.clone(), self.window);
+        tokio::spawn(async move {
+            tokio::time::sleep(time::Duration::from_millis(window)).await;
+            let batch = batches.lock().unwrap().remove(&addr).unwrap_or_default();
+            let (requests, txs): (Vec<_>, Vec<_>) = batch.into_iter().unzip();
+            match inner.heartbeat(addr, requests).await {
+                Ok(results) => {
+                    for (tx, result) in txs.into_iter().zip(results) {
+                        let _ = tx.send(result);
+                    }
+                }
+                Err(e) => {
+                    let e = e.to_string();
+                    for tx in txs {
+                        let _ = tx.send(Err(e.clone()));
+                    }
+                }
+            }
+        });
+    }
+}
+
+#[tonic::async_trait]
+impl Transport for CoalescingTransport {
+    async fn elect(&self, addr: String, request: ElectRequest) -> Result<ElectResponse, RpcError> {
+        self.inner.elect(addr, request).await
+    }
+
+    async fn pre_vote(&self, addr: String, request: ElectRequest) -> Result<ElectResponse, RpcError> {
+        self.inner.pre_vote(addr, request).await
+    }
+
+    async fn append_log(&self, addr: String, request: AppendLogRequest) -> Result<AppendLogResponse, RpcError> {
+        if !request.log.is_empty() {
+            return self.inner.append_log(addr, request).await;
+        }
+        let (tx, rx) = oneshot::channel();
+        let first = {
+            let mut batches = self.batches.lock().unwrap();
+            let batch = batches.entry(addr.clone()).or_default();
+            batch.push((request, tx));
+            batch.len() == 1
+        };
+        if first {
+            self.flush_later(addr);
+        }
+        Ok(rx.await.map_err(|_| "the heartbeat batch is dropped")??)
+    }
+
+    async fn install_snapshot(
+        &self,
+        addr: String,
+        chunks: Vec<InstallSnapshotRequest>,
+    ) -> Result<InstallSnapshotResponse, RpcError> {
+        self.inner.install_snapshot(addr, chunks).await
+    }
+
+    async fn timeout_now(&self, addr: String, request: TimeoutNowRequest) -> Result<TimeoutNowResponse, RpcError> {
+        self.inner.timeout_now(addr, request).await
+    }
+
+    async fn read_index(&self, addr: String, request: ReadIndexRequest) -> Result<ReadIndexResponse, RpcError> {
+        self.inner.read_index(addr, request).await
+    }
+
+    async fn heartbeat(
+        &self,
+        addr: String,
+        requests: Vec<AppendLogRequest>,
+    ) -> Result<Vec<Result<AppendLogResponse, String>>, RpcError> {
+        self.inner.heartbeat(addr, requests).await
+    }
+
+    fn peer_health(&self, addr: &str) -> Option<PeerHealth> {
+        self.inner.peer_health(addr)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Answer every heartbeat with the group it is sent for as the term, and record the batches.
+    #[derive(Debug, Default)]
+    struct Recorder {
+        batches: Mutex<Vec<(String, Vec<u64>)>>,
+    }
+
+    #[tonic::async_trait]
+    impl Transport for Recorder {
+        async fn elect(&self, _: String, _: ElectRequest) -> Result<ElectResponse, RpcError> {
+            Err("unused".into())
+        }
+
+        async fn pre_vote(&self, _: String, _: ElectRequest) -> Result<ElectResponse, RpcError> {
+            Err("unused".into())
+        }
+
+        async fn append_log(&self, _: String, request: AppendLogRequest) -> Result<AppendLogResponse, RpcError> {
+            Ok(AppendLogResponse {
+                success: true,
+                term: request.term,
+                ..Default::default()
+            })
+        }
+
+        async fn install_snapshot(
+            &self,
+            _: String,
+            _: Vec<InstallSnapshotRequest>,
+        ) -> Result<InstallSnapshotResponse, RpcError> {
+            Err("unused".into())
+        }
+
+        async fn timeout_now(&self, _: String, _: TimeoutNowRequest) -> Result<TimeoutNowResponse, RpcError> {
+            Err("unused".into())
+        }
+
+        async fn read_index(&self, _: String, _: ReadIndexRequest) -> Result<ReadIndexResponse, RpcError> {
+            Err("unused".into())
+        }
+
+        async fn heartbeat(
+            &self,
+            addr: String,
+            requests: Vec<AppendLogRequest>,
+        ) -> Result<Vec<Result<AppendLogResponse, String>>, RpcError> {
+            let groups: Vec<u64> = requests.iter().map(|request| request.group).collect();
+            self.batches.lock().unwrap().push((addr, groups.clone()));
+            let result = |group| match group {
+                0 => Err("no group 0".to_string()),
+                term => Ok(AppendLogResponse {
+                    success: true,
+                    term,
+                    ..Default::default()
+                }),
+            };
+            Ok(groups.into_iter().map(result).collect())
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_coalesce_heartbeats() {
+        let recorder = Arc::new(Recorder::default());
+        let transport = Arc::new(CoalescingTransport::new(recorder.clone(), 10));
+        let heartbeat = |addr: &str, group: u64, entries: usize| {
+            let (transport, addr) = (transport.clone(), addr.to_string());
+            let request = AppendLogRequest {
+                term: 7,
+                log: vec![Log::default(); entries],
+                group,
+                ..Default::default()
+            };
+            tokio::spawn(async move { transport.append_log(addr, request).await.map_err(|e| e.to_string()) })
+        };
+        let rpcs = vec![
+            heartbeat("a", 1, 0),
+            heartbeat("a", 2, 0),
+            heartbeat("b", 3, 0),
+            heartbeat("a", 0, 0),
+            // an rpc carrying entries goes out right away
+            heartbeat("a", 4, 1),
+        ];
+        let mut results = Vec::new();
+        for rpc in rpcs {
+            results.push(rpc.await.unwrap().map(|resp| resp.term));
+        }
+        assert_eq!(results, vec![Ok(1), Ok(2), Ok(3), Err("no group 0".to_string()), Ok(7)]);
+        let mut batches = recorder.batches.lock().unwrap().clone();
+        batches.sort();
+        assert_eq!(batches, vec![("a".to_string(), vec![1, 2, 0]), ("b".to_string(), vec![3])]);
+    }
+}